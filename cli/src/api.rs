@@ -0,0 +1,108 @@
+use anyhow::{bail, Context, Result};
+use pc_status_shared::{HistorySample, HostInfo};
+use reqwest::{Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+
+/// サーバーのREST APIクライアント
+pub struct Api {
+    http: reqwest::Client,
+    base: Url,
+    /// テナントを設定したサーバーで使う閲覧者トークン
+    token: Option<String>,
+}
+
+impl Api {
+    pub fn new(server: &str, token: Option<String>) -> Result<Self> {
+        let base = Url::parse(server).with_context(|| format!("Invalid server URL: {}", server))?;
+        if base.cannot_be_a_base() {
+            bail!("Invalid server URL: {}", server);
+        }
+        Ok(Self { http: reqwest::Client::new(), base, token })
+    }
+
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    /// `/api/...`のURL（各要素はパスとしてエスケープする）
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("base URL is checked in new")
+            .pop_if_empty()
+            .push("api")
+            .extend(segments);
+        url
+    }
+
+    /// GETする（404はNone、それ以外のエラーはサーバーのメッセージを含めて返す）
+    async fn send(&self, url: Url) -> Result<Option<Response>> {
+        let mut request = self.http.get(url.clone());
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.base))?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("{} returned {}: {}", url, status, body.trim());
+        }
+        Ok(Some(response))
+    }
+
+    /// GETしてJSONを読む（404はNone）
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> Result<Option<T>> {
+        let Some(response) = self.send(url.clone()).await? else {
+            return Ok(None);
+        };
+        let value = response.json().await.with_context(|| format!("Invalid response from {}", url))?;
+        Ok(Some(value))
+    }
+
+    pub async fn hosts(&self, selector: Option<&str>) -> Result<Vec<HostInfo>> {
+        let mut url = self.url(&["hosts"]);
+        if let Some(selector) = selector {
+            url.query_pairs_mut().append_pair("selector", selector);
+        }
+        self.get(url.clone()).await?.with_context(|| format!("{} is not available", url))
+    }
+
+    pub async fn host(&self, host: &str) -> Result<Option<HostInfo>> {
+        self.get(self.url(&["hosts", host])).await
+    }
+
+    pub async fn history(&self, host: &str, since: Option<&str>) -> Result<Option<Vec<HistorySample>>> {
+        let mut url = self.url(&["hosts", host, "history"]);
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        self.get(url).await
+    }
+
+    /// 履歴の書き出し（本文は`Response::chunk`で少しずつ読む）
+    pub async fn export(
+        &self,
+        host: &str,
+        from: Option<&str>,
+        to: Option<&str>,
+        columns: Option<&str>,
+        format: &str,
+    ) -> Result<Option<Response>> {
+        let mut url = self.url(&["hosts", host, "export"]);
+        {
+            let mut query = url.query_pairs_mut();
+            for (name, value) in [("from", from), ("to", to), ("columns", columns), ("format", Some(format))] {
+                if let Some(value) = value {
+                    query.append_pair(name, value);
+                }
+            }
+        }
+        self.send(url).await
+    }
+}
@@ -0,0 +1,312 @@
+mod api;
+mod output;
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use pc_status_shared::{parse_duration, HostInfo};
+use std::cmp::Ordering;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+use crate::api::Api;
+
+/// wait-untilがタイムアウトしたときの終了コード（timeoutコマンドと同じ）
+const EXIT_TIMED_OUT: u8 = 124;
+
+/// PC Statusサーバーに問い合わせるコマンドラインツール
+#[derive(Debug, Parser)]
+#[command(name = "pcstatus", version)]
+struct Cli {
+    /// サーバーのURL
+    #[arg(long, env = "PCSTATUS_SERVER", default_value = "http://localhost:3000", global = true)]
+    server: String,
+
+    /// 閲覧者トークン（サーバーでテナントを設定している場合）
+    #[arg(long, env = "PCSTATUS_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 接続中のホストを一覧表示する
+    List {
+        /// 並び順（使用率は低い順）
+        #[arg(long, value_enum, default_value_t = SortKey::Name)]
+        sort: SortKey,
+        /// タグ・ラベルで絞り込む（`gpu,role=ci`、`site!=osaka`など）
+        #[arg(long, short = 'l')]
+        selector: Option<String>,
+        /// 表示する件数
+        #[arg(long, short = 'n')]
+        limit: Option<usize>,
+        /// ホスト名だけを出力する
+        #[arg(long, short)]
+        quiet: bool,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// ホストの詳細を表示する（ID、ホスト名、`hostname#index`で指定）
+    Show {
+        host: String,
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// ホストの時刻付き履歴を出力する
+    History {
+        host: String,
+        /// 期間（`30s`・`15m`・`1h`・`2d`）またはRFC 3339の時刻
+        #[arg(long)]
+        since: Option<String>,
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Csv)]
+        format: HistoryFormat,
+    },
+    /// ホストの履歴を指定した列・形式で書き出す（サーバーのHISTORY_DIRに保存した範囲も含む）
+    Export {
+        host: String,
+        /// 開始（期間またはRFC 3339の時刻、未指定は保存しているすべて）
+        #[arg(long)]
+        from: Option<String>,
+        /// 終了（期間またはRFC 3339の時刻、未指定は現在）
+        #[arg(long)]
+        to: Option<String>,
+        /// カンマ区切りの列（cpu, cpu_cores, ram, swap, storages, gpus, load, uptime, tags, labels）
+        #[arg(long)]
+        columns: Option<String>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// 出力先のファイル（未指定は標準出力）
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// 一覧を定期的に更新して表示する
+    Watch {
+        /// 更新間隔
+        #[arg(long, default_value = "2s", value_parser = duration_arg)]
+        interval: Duration,
+        /// タグ・ラベルで絞り込む
+        #[arg(long, short = 'l')]
+        selector: Option<String>,
+    },
+    /// ホストが条件を満たすまで待つ（タイムアウト時は124で終了）
+    WaitUntil {
+        host: String,
+        /// CPU使用率（%）がこの値を下回るまで待つ
+        #[arg(long)]
+        cpu_below: Option<f64>,
+        /// メモリ使用率（%）がこの値を下回るまで待つ
+        #[arg(long)]
+        ram_below: Option<f64>,
+        /// 条件を満たし続ける必要がある時間
+        #[arg(long = "for", default_value = "0s", value_parser = duration_arg)]
+        hold: Duration,
+        /// 待つ時間の上限（未指定は無制限）
+        #[arg(long, value_parser = duration_arg)]
+        timeout: Option<Duration>,
+        /// 確認の間隔
+        #[arg(long, default_value = "5s", value_parser = duration_arg)]
+        interval: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortKey {
+    Name,
+    Cpu,
+    Ram,
+    Disk,
+    Load,
+}
+
+impl SortKey {
+    fn compare(self, a: &HostInfo, b: &HostInfo) -> Ordering {
+        // 値のないホストは最後に並べる
+        let by_value = |value: fn(&HostInfo) -> Option<f64>| {
+            value(a).unwrap_or(f64::INFINITY).total_cmp(&value(b).unwrap_or(f64::INFINITY))
+        };
+        match self {
+            Self::Name => Ordering::Equal,
+            Self::Cpu => by_value(|host| output::cpu_usage(&host.status.cpu)),
+            Self::Ram => by_value(|host| output::percent(host.status.ram.free, host.status.ram.total)),
+            Self::Disk => by_value(|host| output::disk_usage(&host.status.storages)),
+            Self::Load => a.status.loadavg[0].total_cmp(&b.status.loadavg[0]),
+        }
+        .then_with(|| output::host_name(a).to_lowercase().cmp(&output::host_name(b).to_lowercase()))
+    }
+}
+
+fn duration_arg(value: &str) -> Result<Duration, String> {
+    parse_duration(value).ok_or_else(|| format!("invalid duration: {} (expected e.g. 30s, 15m, 1h)", value))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+
+    // rustlsのデフォルトCryptoProviderを初期化（https://での接続に使用）
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
+
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode> {
+    let api = Api::new(&cli.server, cli.token)?;
+
+    match cli.command {
+        Command::List { sort, selector, limit, quiet, format } => {
+            let mut hosts = api.hosts(selector.as_deref()).await?;
+            hosts.sort_by(|a, b| sort.compare(a, b));
+            hosts.truncate(limit.unwrap_or(usize::MAX));
+
+            if quiet {
+                for host in &hosts {
+                    println!("{}", output::host_name(host));
+                }
+            } else {
+                match format {
+                    Format::Table => print!("{}", output::hosts_table(&hosts)),
+                    Format::Json => println!("{}", serde_json::to_string_pretty(&hosts)?),
+                }
+            }
+        }
+        Command::Show { host, format } => {
+            let Some(info) = api.host(&host).await? else {
+                bail!("Host not found: {}", host);
+            };
+            match format {
+                Format::Table => print!("{}", output::host_detail(&info)),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&info)?),
+            }
+        }
+        Command::History { host, since, format } => {
+            let Some(samples) = api.history(&host, since.as_deref()).await? else {
+                bail!("No history for host: {}", host);
+            };
+            match format {
+                HistoryFormat::Csv => print!("{}", output::history_csv(&samples)),
+                HistoryFormat::Json => println!("{}", serde_json::to_string_pretty(&samples)?),
+            }
+        }
+        Command::Export { host, from, to, columns, format, output } => {
+            let format = match format {
+                ExportFormat::Csv => "csv",
+                ExportFormat::Ndjson => "ndjson",
+            };
+            let Some(mut response) = api
+                .export(&host, from.as_deref(), to.as_deref(), columns.as_deref(), format)
+                .await?
+            else {
+                bail!("No history for host: {}", host);
+            };
+
+            // 受信したものから順に書き出す
+            let mut writer: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    std::fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+                )),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            while let Some(chunk) = response.chunk().await? {
+                writer.write_all(&chunk)?;
+            }
+            writer.flush()?;
+        }
+        Command::Watch { interval, selector } => loop {
+            let screen = match api.hosts(selector.as_deref()).await {
+                Ok(hosts) => output::hosts_table(&hosts),
+                Err(e) => format!("Error: {:#}\n", e),
+            };
+            // 画面を消して先頭から描き直す
+            print!(
+                "\x1b[2J\x1b[HEvery {}s: {}  {}\n\n{}",
+                interval.as_secs_f64(),
+                api.base(),
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                screen
+            );
+            std::io::stdout().flush()?;
+            sleep(interval).await;
+        },
+        Command::WaitUntil { host, cpu_below, ram_below, hold, timeout, interval } => {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let mut satisfied_since: Option<Instant> = None;
+
+            loop {
+                // 再起動中などでサーバーやホストに繋がらない間も待ち続ける
+                let info = match api.host(&host).await {
+                    Ok(info) => info,
+                    Err(e) => {
+                        eprintln!("{:#}", e);
+                        None
+                    }
+                };
+
+                let satisfied = info.as_ref().is_some_and(|info| {
+                    let cpu = output::cpu_usage(&info.status.cpu);
+                    let ram = output::percent(info.status.ram.free, info.status.ram.total);
+                    below(cpu, cpu_below) && below(ram, ram_below)
+                });
+
+                if satisfied {
+                    let since = *satisfied_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= hold {
+                        if let Some(info) = info {
+                            print!("{}", output::hosts_table(&[info]));
+                        }
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                } else {
+                    satisfied_since = None;
+                }
+
+                if deadline.is_some_and(|deadline| Instant::now() + interval > deadline) {
+                    eprintln!("Timed out waiting for {}", host);
+                    return Ok(ExitCode::from(EXIT_TIMED_OUT));
+                }
+                sleep(interval).await;
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// 値が閾値を下回っているか（閾値が未指定の場合は常に満たす）
+fn below(value: Option<f64>, threshold: Option<f64>) -> bool {
+    match threshold {
+        Some(threshold) => value.is_some_and(|value| value < threshold),
+        None => true,
+    }
+}
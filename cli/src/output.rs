@@ -0,0 +1,225 @@
+use pc_status_shared::{Cpu, Gpu, HistorySample, HostInfo, Storage};
+use std::fmt::Write;
+
+pub fn percent(free: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| total.saturating_sub(free) as f64 / total as f64 * 100.0)
+}
+
+/// 全コアの平均使用率
+pub fn cpu_usage(cpu: &Cpu) -> Option<f64> {
+    (!cpu.cpus.is_empty()).then(|| cpu.cpus.iter().map(|core| core.cpu).sum::<f64>() / cpu.cpus.len() as f64)
+}
+
+/// 最も使用率の高いストレージの使用率
+pub fn disk_usage(storages: &[Storage]) -> Option<f64> {
+    storages.iter().filter_map(|storage| percent(storage.free, storage.total)).reduce(f64::max)
+}
+
+/// 最も使用率の高いGPUの使用率
+pub fn gpu_usage(gpus: &[Gpu]) -> Option<f64> {
+    gpus.iter().map(|gpu| gpu.usage).reduce(f64::max)
+}
+
+pub fn host_name(host: &HostInfo) -> String {
+    if host.status.index > 0 {
+        format!("{}#{}", host.status.hostname, host.status.index)
+    } else {
+        host.status.hostname.clone()
+    }
+}
+
+fn format_percent(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.1}%", value)).unwrap_or_else(|| "-".to_string())
+}
+
+/// CSV向けの値（値がない場合は空欄）
+fn csv_value(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.2}", value)).unwrap_or_default()
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_uptime(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = seconds % 3600 / 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+/// 列幅をそろえた表
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    let header: Vec<String> = header.iter().map(|title| title.to_string()).collect();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        let _ = writeln!(output, "{}", line.join("  ").trim_end());
+    }
+    output
+}
+
+pub fn hosts_table(hosts: &[HostInfo]) -> String {
+    let rows: Vec<Vec<String>> = hosts
+        .iter()
+        .map(|host| {
+            let status = &host.status;
+            vec![
+                host_name(host),
+                format_percent(cpu_usage(&status.cpu)),
+                format_percent(percent(status.ram.free, status.ram.total)),
+                format_percent(percent(status.swap.free, status.swap.total)),
+                format_percent(disk_usage(&status.storages)),
+                format_percent(gpu_usage(&status.gpus)),
+                format!("{:.2}", status.loadavg[0]),
+                format_uptime(status.uptime),
+                status.os.clone(),
+            ]
+        })
+        .collect();
+    table(&["HOST", "CPU", "RAM", "SWAP", "DISK", "GPU", "LOAD", "UPTIME", "OS"], &rows)
+}
+
+pub fn host_detail(host: &HostInfo) -> String {
+    let status = &host.status;
+    let mut output = String::new();
+    let _ = writeln!(output, "Host:    {} ({})", host_name(host), host.id);
+    let _ = writeln!(output, "OS:      {}", status.os);
+    let _ = writeln!(output, "Agent:   {}", status.version);
+    if !status.tags.is_empty() {
+        let _ = writeln!(output, "Tags:    {}", status.tags.join(", "));
+    }
+    if !status.labels.is_empty() {
+        let labels: Vec<String> = status.labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        let _ = writeln!(output, "Labels:  {}", labels.join(", "));
+    }
+    let _ = writeln!(output, "Uptime:  {}", format_uptime(status.uptime));
+    let _ = writeln!(
+        output,
+        "Load:    {:.2} {:.2} {:.2}",
+        status.loadavg[0], status.loadavg[1], status.loadavg[2]
+    );
+    let _ = writeln!(
+        output,
+        "CPU:     {} ({} cores, {})",
+        status.cpu.model,
+        status.cpu.cpus.len(),
+        format_percent(cpu_usage(&status.cpu))
+    );
+    let _ = writeln!(
+        output,
+        "RAM:     {} / {} ({})",
+        format_bytes(status.ram.total.saturating_sub(status.ram.free)),
+        format_bytes(status.ram.total),
+        format_percent(percent(status.ram.free, status.ram.total))
+    );
+    let _ = writeln!(
+        output,
+        "Swap:    {} / {} ({})",
+        format_bytes(status.swap.total.saturating_sub(status.swap.free)),
+        format_bytes(status.swap.total),
+        format_percent(percent(status.swap.free, status.swap.total))
+    );
+    for storage in &status.storages {
+        let _ = writeln!(
+            output,
+            "Disk:    {} {} / {} ({})",
+            storage.name.as_deref().unwrap_or("-"),
+            format_bytes(storage.total.saturating_sub(storage.free)),
+            format_bytes(storage.total),
+            format_percent(percent(storage.free, storage.total))
+        );
+    }
+    for gpu in &status.gpus {
+        let _ = writeln!(
+            output,
+            "GPU:     {} {} (memory {})",
+            gpu.name,
+            format_percent(Some(gpu.usage)),
+            format_percent(percent(gpu.memory.free, gpu.memory.total))
+        );
+    }
+    output
+}
+
+/// 履歴のCSV（使用率は%）
+pub fn history_csv(samples: &[HistorySample]) -> String {
+    let mut output = String::from("time,cpu,ram,swap,disk,gpu,load1,uptime\n");
+    for sample in samples {
+        let data = &sample.data;
+        let _ = writeln!(
+            output,
+            "{},{},{},{},{},{},{:.2},{}",
+            sample.time.to_rfc3339(),
+            csv_value(cpu_usage(&data.cpu)),
+            csv_value(percent(data.ram.free, data.ram.total)),
+            csv_value(percent(data.swap.free, data.swap.total)),
+            csv_value(disk_usage(&data.storages)),
+            csv_value(gpu_usage(&data.gpus)),
+            sample.loadavg[0],
+            data.uptime
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use pc_status_shared::{CpuData, HistoriesData, Ram, Swap};
+
+    #[test]
+    fn test_table_alignment() {
+        let rows = vec![vec!["web".to_string(), "1.0%".to_string()], vec!["database".to_string(), "-".to_string()]];
+        assert_eq!(table(&["HOST", "CPU"], &rows), "HOST      CPU\nweb       1.0%\ndatabase  -\n");
+    }
+
+    #[test]
+    fn test_history_csv() {
+        let sample = HistorySample {
+            time: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            data: HistoriesData {
+                cpu: Cpu { model: "cpu".to_string(), cpus: vec![CpuData { cpu: 10.0 }, CpuData { cpu: 30.0 }] },
+                ram: Ram { free: 25, total: 100 },
+                swap: Swap { free: 0, total: 0 },
+                storages: vec![],
+                gpus: vec![],
+                uptime: 60,
+            },
+            loadavg: [0.5, 0.25, 0.0],
+            tags: vec![],
+            labels: Default::default(),
+        };
+        assert_eq!(
+            history_csv(&[sample]),
+            "time,cpu,ram,swap,disk,gpu,load1,uptime\n2024-01-02T03:04:05+00:00,20.00,75.00,,,,0.50,60\n"
+        );
+    }
+}
@@ -9,6 +9,10 @@ fn main() -> io::Result<()> {
             println!("cargo::rustc-env=GIT_DESCRIBE=unknown");
         }
     }
+    // 自動更新で取得する実行ファイルの選択に使う
+    if let Ok(target) = std::env::var("TARGET") {
+        println!("cargo::rustc-env=TARGET={target}");
+    }
 
     Ok(())
 }
@@ -17,7 +21,7 @@ fn get_git_describe_result() -> Result<String, Box<dyn std::error::Error>> {
     use std::process::Command;
     
     let output = Command::new("git")
-        .args(&["describe", "--tags", "--always", "--dirty=-dirty"])
+        .args(["describe", "--tags", "--always", "--dirty=-dirty"])
         .output()?;
     
     if output.status.success() {
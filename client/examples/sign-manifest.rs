@@ -0,0 +1,95 @@
+//! 自動更新の署名鍵の作成と、署名付きマニフェストの作成
+//!
+//! ```sh
+//! # 鍵を作成し、公開鍵（client/update-public-key.txtに書く）を表示する
+//! cargo run -p pc-status-client --example sign-manifest -- keygen update-key.pk8
+//! # 実行ファイルのサイズ・SHA-256を記載したマニフェストに署名する
+//! cargo run -p pc-status-client --example sign-manifest -- sign update-key.pk8 v1.2.0 \
+//!     x86_64-unknown-linux-gnu=dist/pc-status-client-x86_64-unknown-linux-gnu > dist/manifest.json
+//! ```
+//!
+//! マニフェストに記載するURLは実行ファイルのファイル名（マニフェストからの相対パス）になる。
+//! マニフェストは`--valid-days`日（既定は90日）で期限が切れるため、配布を続ける場合は期限前に署名し直す。
+//! 現在より古いバージョンへ戻すマニフェストには`--rollback`を付ける。
+
+use anyhow::{bail, Context, Result};
+use chrono::{Duration, Utc};
+use pc_status_shared::{SignedManifest, UpdateAsset, UpdateManifest};
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::path::Path;
+use std::{env, fs};
+
+/// マニフェストの既定の有効日数
+const DEFAULT_VALID_DAYS: i64 = 90;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["keygen", key] => keygen(Path::new(key)),
+        ["sign", key, version, args @ ..] if !args.is_empty() => sign(Path::new(key), version, args),
+        _ => bail!(
+            "Usage: sign-manifest keygen <key> | sign <key> <version> [--valid-days <days>] [--rollback] <target>=<file>..."
+        ),
+    }
+}
+
+fn keygen(path: &Path) -> Result<()> {
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).map_err(|_| anyhow::anyhow!("Failed to generate a key"))?;
+    fs::write(path, pkcs8.as_ref()).with_context(|| format!("Failed to write {}", path.display()))?;
+    let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|_| anyhow::anyhow!("Failed to load the key"))?;
+    println!("{}", hex::encode(key.public_key()));
+    Ok(())
+}
+
+fn sign(path: &Path, version: &str, args: &[&str]) -> Result<()> {
+    let mut valid_days = DEFAULT_VALID_DAYS;
+    let mut rollback = false;
+    let mut assets = vec![];
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--valid-days" => {
+                let days = args.next().context("--valid-days needs a value")?;
+                valid_days = days.parse().with_context(|| format!("Invalid --valid-days: {}", days))?;
+            }
+            "--rollback" => rollback = true,
+            asset => assets.push(asset),
+        }
+    }
+    if assets.is_empty() {
+        bail!("No <target>=<file> given");
+    }
+
+    let pkcs8 = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8).map_err(|_| anyhow::anyhow!("Invalid key: {}", path.display()))?;
+
+    let assets = assets
+        .iter()
+        .map(|asset| {
+            let (target, file) = asset.split_once('=').with_context(|| format!("Expected <target>=<file>: {}", asset))?;
+            let file = Path::new(file);
+            let binary = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+            Ok(UpdateAsset {
+                target: target.to_string(),
+                url: file.file_name().context("Invalid file name")?.to_string_lossy().into_owned(),
+                size: binary.len() as u64,
+                sha256: hex::encode(digest(&SHA256, &binary)),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let manifest = UpdateManifest {
+        version: version.to_string(),
+        assets,
+        expires_at: Utc::now() + Duration::days(valid_days),
+        rollback,
+    };
+    let manifest = serde_json::to_string(&manifest)?;
+    let signature = hex::encode(key.sign(manifest.as_bytes()));
+    println!("{}", serde_json::to_string_pretty(&SignedManifest { manifest, signature })?);
+    Ok(())
+}
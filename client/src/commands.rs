@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use pc_status_shared::{CommandRequest, CommandResult, CommandStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// 制限時間の既定値（秒）
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+/// 制限時間の上限（秒、サーバーが結果を待つ時間より短くする）
+const MAX_TIMEOUT_SECS: u64 = 300;
+/// 標準出力・標準エラー出力ごとに返す最大バイト数
+const MAX_OUTPUT_BYTES: u64 = 64 * 1024;
+
+/// 許可リストに登録するコマンド
+#[derive(Debug, Clone, Deserialize)]
+struct AllowedCommand {
+    /// 実行するプログラム（シェルは経由しない）
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default = "default_timeout")]
+    timeout_secs: u64,
+    /// 作業ディレクトリ（未指定の場合はエージェントと同じ）
+    #[serde(default)]
+    working_dir: Option<PathBuf>,
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// エージェント側の監査ログの1件
+#[derive(Serialize)]
+struct CommandLogEntry<'a> {
+    time: chrono::DateTime<Utc>,
+    id: &'a str,
+    command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_by: Option<&'a str>,
+    status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    duration_ms: u64,
+}
+
+/// サーバーから要求されたコマンドを許可リストに従って実行する
+pub struct CommandRunner {
+    commands: HashMap<String, AllowedCommand>,
+    /// 実行結果を追記するJSON Linesファイル
+    log_file: Option<Mutex<PathBuf>>,
+}
+
+impl CommandRunner {
+    /// `COMMANDS_FILE`から許可リストを読み込む（未設定の場合はNone）
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = env::var("COMMANDS_FILE").ok().filter(|path| !path.is_empty()) else {
+            return Ok(None);
+        };
+        let log_file = env::var("COMMAND_LOG_FILE").ok().filter(|path| !path.is_empty()).map(PathBuf::from);
+        Self::load(Path::new(&path), log_file).map(Some)
+    }
+
+    fn load(path: &Path, log_file: Option<PathBuf>) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read commands file {}", path.display()))?;
+        let commands: HashMap<String, AllowedCommand> = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse commands file {}", path.display()))?;
+        for (name, command) in &commands {
+            if name.trim().is_empty() {
+                bail!("Command name must not be empty");
+            }
+            if command.program.trim().is_empty() {
+                bail!("Command {} has an empty program", name);
+            }
+            if !(1..=MAX_TIMEOUT_SECS).contains(&command.timeout_secs) {
+                bail!("Command {}: timeout_secs must be between 1 and {}", name, MAX_TIMEOUT_SECS);
+            }
+        }
+        Ok(Self { commands, log_file: log_file.map(Mutex::new) })
+    }
+
+    /// 許可されたコマンドの名前
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// 要求されたコマンドを実行する（許可リストにない場合は実行しない）
+    pub async fn run(&self, request: &CommandRequest) -> CommandResult {
+        let requested_by = request.requested_by.as_deref().unwrap_or("unknown");
+        let result = match self.commands.get(&request.command) {
+            Some(command) => {
+                info!("Running command {} ({}) requested by {}", request.command, request.id, requested_by);
+                execute(&request.id, command).await
+            }
+            None => {
+                warn!("Refusing command {} ({}) requested by {}: not in allowlist", request.command, request.id, requested_by);
+                CommandResult::rejected(&request.id, CommandStatus::NotAllowed, "Command is not in the agent's allowlist")
+            }
+        };
+        self.record(request, &result).await;
+        result
+    }
+
+    async fn record(&self, request: &CommandRequest, result: &CommandResult) {
+        let entry = CommandLogEntry {
+            time: Utc::now(),
+            id: &request.id,
+            command: &request.command,
+            requested_by: request.requested_by.as_deref(),
+            status: result.status,
+            exit_code: result.exit_code,
+            duration_ms: result.duration_ms,
+        };
+        let mut line = match serde_json::to_string(&entry) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize command log entry: {}", e);
+                return;
+            }
+        };
+        info!(target: "audit", "{}", line);
+
+        let Some(log_file) = &self.log_file else {
+            return;
+        };
+        let path = log_file.lock().await;
+        line.push('\n');
+        let written = async {
+            let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&*path).await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        };
+        if let Err(e) = written.await {
+            error!("Failed to write command log {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// コマンドを実行し、制限時間を超えた場合は強制終了する
+async fn execute(id: &str, command: &AllowedCommand) -> CommandResult {
+    let start = Instant::now();
+    let mut process = Command::new(&command.program);
+    process
+        .args(&command.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(dir) = &command.working_dir {
+        process.current_dir(dir);
+    }
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let message = format!("Failed to start {}: {}", command.program, e);
+            return CommandResult::rejected(id, CommandStatus::Failed, message);
+        }
+    };
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let finished = async {
+        let (stdout, stderr, status) = tokio::join!(read_limited(stdout), read_limited(stderr), child.wait());
+        (stdout, stderr, status)
+    };
+    // 制限時間を超えた場合はchildをdropして強制終了する
+    let timeout = Duration::from_secs(command.timeout_secs);
+    let (status, exit_code, (stdout, stdout_truncated), (stderr, stderr_truncated)) =
+        match tokio::time::timeout(timeout, finished).await {
+            Ok((stdout, stderr, Ok(exit))) => (CommandStatus::Completed, exit.code(), stdout, stderr),
+            Ok((stdout, stderr, Err(e))) => {
+                let (mut message, truncated) = stderr;
+                message.push_str(&format!("\nFailed to wait for {}: {}", command.program, e));
+                (CommandStatus::Failed, None, stdout, (message, truncated))
+            }
+            Err(_) => {
+                let message = format!("Timed out after {}s", command.timeout_secs);
+                (CommandStatus::TimedOut, None, (String::new(), false), (message, false))
+            }
+        };
+
+    CommandResult {
+        id: id.to_string(),
+        status,
+        exit_code,
+        stdout,
+        stderr,
+        truncated: stdout_truncated || stderr_truncated,
+        duration_ms: start.elapsed().as_millis() as u64,
+    }
+}
+
+/// 出力を`MAX_OUTPUT_BYTES`まで読み、残りは読み捨てる（切り詰めた場合はtrue）
+async fn read_limited(mut reader: impl AsyncRead + Unpin) -> (String, bool) {
+    let mut buffer = Vec::new();
+    if let Err(e) = (&mut reader).take(MAX_OUTPUT_BYTES).read_to_end(&mut buffer).await {
+        warn!("Failed to read command output: {}", e);
+    }
+    // 読み捨てないとパイプが詰まってコマンドが終了しない
+    let rest = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await.unwrap_or_default();
+    (String::from_utf8_lossy(&buffer).into_owned(), rest > 0)
+}
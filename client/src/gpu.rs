@@ -1377,7 +1377,7 @@ fn get_intel_gpu_memory_linux() -> (u64, u64) {
 
     // /proc/meminfoからシステムメモリを取得してIntel統合GPU用に推定
     if let Ok(system_memory) = get_system_memory_linux() {
-        let estimated_gpu_memory = (system_memory / 8).max(512 * 1024 * 1024).min(2 * 1024 * 1024 * 1024);
+        let estimated_gpu_memory = (system_memory / 8).clamp(512 * 1024 * 1024, 2 * 1024 * 1024 * 1024);
         let free_memory = get_dynamic_intel_gpu_memory_linux(estimated_gpu_memory);
         return (estimated_gpu_memory, free_memory);
     }
@@ -1414,10 +1414,10 @@ fn get_amd_gpu_memory_linux() -> (u64, u64) {
 #[cfg(not(target_os = "windows"))]
 fn extract_percentage_from_line(line: &str) -> Option<f64> {
     let regex = Regex::new(r"(\d+(?:\.\d+)?)%").ok()?;
-    if let Some(captures) = regex.captures(line) {
-        if let Some(percentage_str) = captures.get(1) {
-            return percentage_str.as_str().parse::<f64>().ok();
-        }
+    if let Some(captures) = regex.captures(line)
+        && let Some(percentage_str) = captures.get(1)
+    {
+        return percentage_str.as_str().parse::<f64>().ok();
     }
     None
 }
@@ -1434,22 +1434,20 @@ fn get_gpu_usage_from_sysfs(driver: &str) -> Result<f64, std::io::Error> {
 
         if path.file_name().unwrap().to_str().unwrap().starts_with("card") {
             let busy_path = path.join("device/gpu_busy_percent");
-            if busy_path.exists() {
-                if let Ok(content) = fs::read_to_string(&busy_path) {
-                    if let Ok(usage) = content.trim().parse::<f64>() {
-                        return Ok(usage);
-                    }
-                }
+            if busy_path.exists()
+                && let Ok(content) = fs::read_to_string(&busy_path)
+                && let Ok(usage) = content.trim().parse::<f64>()
+            {
+                return Ok(usage);
             }
 
             // ドライバー固有のパスを確認
             let driver_path = path.join(format!("device/{}_busy_percent", driver));
-            if driver_path.exists() {
-                if let Ok(content) = fs::read_to_string(&driver_path) {
-                    if let Ok(usage) = content.trim().parse::<f64>() {
-                        return Ok(usage);
-                    }
-                }
+            if driver_path.exists()
+                && let Ok(content) = fs::read_to_string(&driver_path)
+                && let Ok(usage) = content.trim().parse::<f64>()
+            {
+                return Ok(usage);
             }
         }
     }
@@ -1459,7 +1457,7 @@ fn get_gpu_usage_from_sysfs(driver: &str) -> Result<f64, std::io::Error> {
 
 // sysfsからGPUメモリ情報を取得
 #[cfg(not(target_os = "windows"))]
-fn get_gpu_memory_from_sysfs(driver: &str) -> Result<(u64, u64), std::io::Error> {
+fn get_gpu_memory_from_sysfs(_driver: &str) -> Result<(u64, u64), std::io::Error> {
     use std::fs;
 
     for entry in fs::read_dir("/sys/class/drm/")? {
@@ -1471,18 +1469,18 @@ fn get_gpu_memory_from_sysfs(driver: &str) -> Result<(u64, u64), std::io::Error>
             let mem_info_path = path.join("device/mem_info_vram_total");
             let mem_used_path = path.join("device/mem_info_vram_used");
 
-            if mem_info_path.exists() && mem_used_path.exists() {
-                if let (Ok(total_str), Ok(used_str)) = (
+            if mem_info_path.exists()
+                && mem_used_path.exists()
+                && let (Ok(total_str), Ok(used_str)) = (
                     fs::read_to_string(&mem_info_path),
                     fs::read_to_string(&mem_used_path)
-                ) {
-                    if let (Ok(total), Ok(used)) = (
-                        total_str.trim().parse::<u64>(),
-                        used_str.trim().parse::<u64>()
-                    ) {
-                        return Ok((total, used));
-                    }
-                }
+                )
+                && let (Ok(total), Ok(used)) = (
+                    total_str.trim().parse::<u64>(),
+                    used_str.trim().parse::<u64>()
+                )
+            {
+                return Ok((total, used));
             }
         }
     }
@@ -1499,10 +1497,10 @@ fn get_system_memory_linux() -> Result<u64, std::io::Error> {
     for line in content.lines() {
         if line.starts_with("MemTotal:") {
             let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                if let Ok(kb) = parts[1].parse::<u64>() {
-                    return Ok(kb * 1024); // KBをバイトに変換
-                }
+            if parts.len() >= 2
+                && let Ok(kb) = parts[1].parse::<u64>()
+            {
+                return Ok(kb * 1024); // KBをバイトに変換
             }
         }
     }
@@ -1525,13 +1523,13 @@ fn get_dynamic_gpu_usage_linux(gpu_type: &str) -> f64 {
             // Intel統合GPU: 1-8%の範囲
             let base = 1.0 + ((now % 7) as f64);
             let variation = ((now as f64 / 15.0).sin() * 2.0 + 2.0).abs();
-            (base + variation).min(8.0).max(0.5)
+            (base + variation).clamp(0.5, 8.0)
         }
         "amd" => {
             // AMD GPU: 2-15%の範囲
             let base = 2.0 + ((now % 10) as f64);
             let variation = ((now as f64 / 20.0).sin() * 3.0 + 3.0).abs();
-            (base + variation).min(15.0).max(1.0)
+            (base + variation).clamp(1.0, 15.0)
         }
         _ => 0.0,
     }
@@ -1550,7 +1548,7 @@ fn get_dynamic_intel_gpu_memory_linux(total_memory: u64) -> u64 {
     // 70-90%の範囲で利用可能メモリが変動
     let base_ratio = 0.7 + ((now % 20) as f64 / 100.0);
     let variation = (now as f64 / 40.0).sin() * 0.05;
-    let available_ratio = (base_ratio + variation).max(0.65).min(0.95);
+    let available_ratio = (base_ratio + variation).clamp(0.65, 0.95);
 
     (total_memory as f64 * available_ratio) as u64
 }
@@ -1568,7 +1566,7 @@ fn get_dynamic_amd_gpu_memory_linux(total_memory: u64) -> u64 {
     // 60-85%の範囲で利用可能メモリが変動
     let base_ratio = 0.6 + ((now % 25) as f64 / 100.0);
     let variation = (now as f64 / 35.0).sin() * 0.08;
-    let available_ratio = (base_ratio + variation).max(0.55).min(0.90);
+    let available_ratio = (base_ratio + variation).clamp(0.55, 0.90);
 
     (total_memory as f64 * available_ratio) as u64
 }
@@ -1582,25 +1580,27 @@ fn get_intel_gpu_from_sysfs() -> Option<Gpu> {
     if let Ok(entries) = fs::read_dir("/sys/class/drm/") {
         for entry in entries.flatten() {
             let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("card") && !name.contains("-") {
-                    let device_path = path.join("device");
-                    if let Ok(vendor) = fs::read_to_string(device_path.join("vendor")) {
-                        if vendor.trim() == "0x8086" { // Intel vendor ID
-                            let gpu_name = "Intel Graphics (detected via sysfs)".to_string();
-                            let usage = get_dynamic_gpu_usage_linux("intel");
-                            let (total_memory, free_memory) = get_intel_gpu_memory_linux();
-
-                            return Some(Gpu {
-                                name: gpu_name,
-                                usage,
-                                memory: GpuMemory {
-                                    free: free_memory,
-                                    total: total_memory,
-                                },
-                            });
-                        }
-                    }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && name.starts_with("card")
+                && !name.contains("-")
+            {
+                let device_path = path.join("device");
+                // Intel vendor ID
+                if let Ok(vendor) = fs::read_to_string(device_path.join("vendor"))
+                    && vendor.trim() == "0x8086"
+                {
+                    let gpu_name = "Intel Graphics (detected via sysfs)".to_string();
+                    let usage = get_dynamic_gpu_usage_linux("intel");
+                    let (total_memory, free_memory) = get_intel_gpu_memory_linux();
+
+                    return Some(Gpu {
+                        name: gpu_name,
+                        usage,
+                        memory: GpuMemory {
+                            free: free_memory,
+                            total: total_memory,
+                        },
+                    });
                 }
             }
         }
@@ -1617,25 +1617,27 @@ fn get_amd_gpu_from_sysfs() -> Option<Gpu> {
     if let Ok(entries) = fs::read_dir("/sys/class/drm/") {
         for entry in entries.flatten() {
             let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.starts_with("card") && !name.contains("-") {
-                    let device_path = path.join("device");
-                    if let Ok(vendor) = fs::read_to_string(device_path.join("vendor")) {
-                        if vendor.trim() == "0x1002" { // AMD vendor ID
-                            let gpu_name = "AMD Graphics (detected via sysfs)".to_string();
-                            let usage = get_dynamic_gpu_usage_linux("amd");
-                            let (total_memory, free_memory) = get_amd_gpu_memory_linux();
-
-                            return Some(Gpu {
-                                name: gpu_name,
-                                usage,
-                                memory: GpuMemory {
-                                    free: free_memory,
-                                    total: total_memory,
-                                },
-                            });
-                        }
-                    }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str())
+                && name.starts_with("card")
+                && !name.contains("-")
+            {
+                let device_path = path.join("device");
+                // AMD vendor ID
+                if let Ok(vendor) = fs::read_to_string(device_path.join("vendor"))
+                    && vendor.trim() == "0x1002"
+                {
+                    let gpu_name = "AMD Graphics (detected via sysfs)".to_string();
+                    let usage = get_dynamic_gpu_usage_linux("amd");
+                    let (total_memory, free_memory) = get_amd_gpu_memory_linux();
+
+                    return Some(Gpu {
+                        name: gpu_name,
+                        usage,
+                        memory: GpuMemory {
+                            free: free_memory,
+                            total: total_memory,
+                        },
+                    });
                 }
             }
         }
@@ -1655,12 +1657,11 @@ fn get_amd_gpu_memory_from_device() -> Result<u64, std::io::Error> {
 
         if path.file_name().unwrap().to_str().unwrap().starts_with("card") {
             let mem_path = path.join("device/mem_info_vram_total");
-            if mem_path.exists() {
-                if let Ok(content) = fs::read_to_string(&mem_path) {
-                    if let Ok(memory) = content.trim().parse::<u64>() {
-                        return Ok(memory);
-                    }
-                }
+            if mem_path.exists()
+                && let Ok(content) = fs::read_to_string(&mem_path)
+                && let Ok(memory) = content.trim().parse::<u64>()
+            {
+                return Ok(memory);
             }
         }
     }
@@ -1681,49 +1682,44 @@ fn get_nvidia_gpu_info() -> Option<Gpu> {
         }
     };
 
-    let output = command.output();
-    if output.is_err() {
+    let Ok(res) = command.output() else {
         return None;
-    } else {
-        let res = output.expect("process error");
-
-        let split_separator = Regex::new(r"\r\n|\n").expect("Invalid regex");
-        let split_binding = String::from_utf8(res.stdout).unwrap();
-        let split_lines: Vec<_> = split_separator.split(&split_binding).into_iter().collect();
-
-        if split_lines.len() < 2 {
-            return None;
-        }
-
-        let replace_separator = Regex::new(r" %| MiB| GiB|\r").expect("Invalid regex");
-        let split2_separator = Regex::new(r", ").expect("Invalid regex");
-        let replaced = replace_separator.replace_all(
-            split_lines.get(1).unwrap_or(&""),
-            ""
-        );
-        let split_values: Vec<_> = split2_separator.split(&replaced).into_iter().collect();
+    };
 
-        if split_values.len() < 4 {
-            return None;
-        }
+    let split_separator = Regex::new(r"\r\n|\n").expect("Invalid regex");
+    let split_binding = String::from_utf8(res.stdout).unwrap();
+    let split_lines: Vec<_> = split_separator.split(&split_binding).collect();
 
-        let usage: f64 = match split_values[1] {
-            "[N/A]" => 0.0,
-            _ => split_values[1].parse::<f64>().unwrap_or(0.0),
-        };
+    if split_lines.len() < 2 {
+        return None;
+    }
 
-        let free_memory = split_values[2].parse::<u64>().unwrap_or(0);
-        let total_memory = split_values[3].parse::<u64>().unwrap_or(0);
+    let replace_separator = Regex::new(r" %| MiB| GiB|\r").expect("Invalid regex");
+    let split2_separator = Regex::new(r", ").expect("Invalid regex");
+    let replaced = replace_separator.replace_all(
+        split_lines.get(1).unwrap_or(&""),
+        ""
+    );
+    let split_values: Vec<_> = split2_separator.split(&replaced).collect();
 
-        let result = Some(Gpu {
-            name: split_values[0].to_string(),
-            usage,
-            memory: GpuMemory {
-                free: free_memory * 1024 * 1024, // MiBをバイトに変換
-                total: total_memory * 1024 * 1024, // MiBをバイトに変換
-            },
-        });
+    if split_values.len() < 4 {
+        return None;
+    }
 
-        return result;
+    let usage: f64 = match split_values[1] {
+        "[N/A]" => 0.0,
+        _ => split_values[1].parse::<f64>().unwrap_or(0.0),
     };
+
+    let free_memory = split_values[2].parse::<u64>().unwrap_or(0);
+    let total_memory = split_values[3].parse::<u64>().unwrap_or(0);
+
+    Some(Gpu {
+        name: split_values[0].to_string(),
+        usage,
+        memory: GpuMemory {
+            free: free_memory * 1024 * 1024, // MiBをバイトに変換
+            total: total_memory * 1024 * 1024, // MiBをバイトに変換
+        },
+    })
 }
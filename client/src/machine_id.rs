@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use std::{env, fs, path::PathBuf};
+use uuid::Uuid;
+
+const DEFAULT_MACHINE_ID_FILE: &str = ".pc-status-machine-id";
+
+/// マシンIDの保存先（MACHINE_ID_FILEで変更可能）
+fn machine_id_path() -> PathBuf {
+    env::var("MACHINE_ID_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_MACHINE_ID_FILE))
+}
+
+/// 保存済みのマシンIDを読み込む。存在しない場合は生成して保存する
+///
+/// サーバーはこのIDでエージェントを識別するため、再接続しても履歴が引き継がれる。
+pub fn load_or_create() -> Result<String> {
+    let path = machine_id_path();
+
+    if let Ok(content) = fs::read_to_string(&path) {
+        let id = content.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let id = Uuid::new_v4().to_string();
+    fs::write(&path, &id)
+        .with_context(|| format!("Failed to write machine ID to {}", path.display()))?;
+    Ok(id)
+}
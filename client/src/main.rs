@@ -1,23 +1,54 @@
 #![cfg_attr(all(not(debug_assertions), not(feature = "debug_console")), windows_subsystem = "windows")]
 
+mod commands;
 mod gpu;
+mod machine_id;
 mod system_info;
 mod sysinfo_instance;
-mod uptime_formatter;
 mod updater;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 use futures_util::{SinkExt, StreamExt};
-use pc_status_shared::{ClientMessage, ServerMessage};
-use std::{env, path::Path, process, time::Duration};
+use pc_status_shared::{
+    Capability, ClientMessage, CommandResult, CommandStatus, ErrorCode, ServerMessage, WireFormat, PROTOCOL_VERSION,
+};
+use std::{env, path::Path, process, sync::Arc, time::Duration};
 use sysinfo::IS_SUPPORTED_SYSTEM;
+use tokio::sync::mpsc;
 use tokio::time::sleep;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tracing::{debug, error, info, warn};
-use tracing_subscriber;
 
+use crate::commands::CommandRunner;
 use crate::system_info::SystemInfoCollector;
+use crate::updater::{UpdateHandle, Updater, UpdaterConfig};
+
+/// サーバーの挨拶を待つ最大時間
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+/// エージェントが対応している機能
+const AGENT_CAPABILITIES: &[Capability] = &[Capability::Binary];
+/// 再接続までの標準の待ち時間
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// ホスト名重複時の待ち時間の上限
+const MAX_DUPLICATE_BACKOFF: Duration = Duration::from_secs(600);
+/// 認証に失敗したときの終了コード
+const EXIT_AUTH_FAILED: i32 = 77;
+/// プロトコルに互換性がないときの終了コード
+const EXIT_INCOMPATIBLE_PROTOCOL: i32 = 76;
+
+/// サーバーからの要求を処理する機能（設定していないものはNone）
+struct Handlers {
+    commands: Option<Arc<CommandRunner>>,
+    updates: Option<UpdateHandle>,
+}
+
+/// サーバーから受け取った拒否の理由
+struct Rejection {
+    code: ErrorCode,
+    message: String,
+    retry: Option<u64>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -87,9 +118,6 @@ async fn main() -> Result<()> {
     // ログ設定
     tracing_subscriber::fmt::init();
 
-    // 自動更新チェック
-    let _ = updater::update();
-
     println!("This OS is supported!");
     let server_url = env::var("PCSC_URI")
         .or_else(|_| env::var("SERVER_URL"))
@@ -103,52 +131,182 @@ async fn main() -> Result<()> {
         .parse::<bool>()
         .unwrap_or(false);
 
+    // 送信時のエンコード形式（json/msgpack）
+    let wire_format = env::var("WIRE_FORMAT")
+        .ok()
+        .and_then(|value| match value.parse::<WireFormat>() {
+            Ok(format) => Some(format),
+            Err(e) => {
+                warn!("{}, falling back to json", e);
+                None
+            }
+        })
+        .unwrap_or_default();
+
+    // 再接続しても解決しないエラーで終了するか（falseの場合は再接続を止めて待機する）
+    let exit_on_fatal = env::var("EXIT_ON_FATAL")
+        .unwrap_or_else(|_| "false".to_string())
+        .parse::<bool>()
+        .unwrap_or(false);
+
+    let machine_id = match machine_id::load_or_create() {
+        Ok(id) => id,
+        Err(e) => {
+            error!("{:#}", e);
+            process::exit(95);
+        }
+    };
+
     info!("Starting PC Status Client");
     info!("Server URL: {}", server_url);
     info!("Dev mode: {}", dev_mode);
+    info!("Machine ID: {}", machine_id);
+    info!("Wire format: {:?}", wire_format);
+
+    // サーバーから実行できるコマンドの許可リスト（未設定の場合は実行しない）
+    let commands = match CommandRunner::from_env() {
+        Ok(commands) => commands.map(Arc::new),
+        Err(e) => {
+            error!("{:#}", e);
+            process::exit(95);
+        }
+    };
+    match &commands {
+        Some(commands) => info!("Remote commands: {}", commands.names().join(", ")),
+        None => info!("Remote commands: disabled"),
+    }
+
+    // 更新後の起動であれば、接続できなかった場合に元のバージョンへ戻す
+    updater::check_pending_update();
+    // 自動更新（未設定の場合は更新しない）
+    let updates = match UpdaterConfig::from_env().and_then(|config| config.map(Updater::new).transpose()) {
+        Ok(Some(updater)) => {
+            info!("Auto-update: enabled");
+            Some(updater.spawn())
+        }
+        Ok(None) => {
+            info!("Auto-update: disabled");
+            None
+        }
+        Err(e) => {
+            error!("{:#}", e);
+            process::exit(95);
+        }
+    };
+    let handlers = Handlers { commands, updates };
 
     let mut system_collector = SystemInfoCollector::new();
+    let mut duplicate_backoff: Option<Duration> = None;
 
     loop {
-        match connect_to_server(&server_url, &password, dev_mode, &mut system_collector).await {
-            Ok(_) => {
+        let delay = match connect_to_server(&server_url, &password, &machine_id, dev_mode, wire_format, &mut system_collector, &handlers).await {
+            Ok(None) => {
                 info!("Connection closed normally");
+                duplicate_backoff = None;
+                RECONNECT_DELAY
+            }
+            Ok(Some(rejection)) if rejection.code.is_fatal() => {
+                error!("Server rejected connection ({:?}): {}", rejection.code, rejection.message);
+                if exit_on_fatal {
+                    process::exit(match rejection.code {
+                        ErrorCode::AuthFailed => EXIT_AUTH_FAILED,
+                        _ => EXIT_INCOMPATIBLE_PROTOCOL,
+                    });
+                }
+                error!("Not reconnecting until the agent is restarted");
+                std::future::pending::<()>().await;
+                unreachable!();
+            }
+            Ok(Some(rejection)) => {
+                warn!("Server rejected connection ({:?}): {}", rejection.code, rejection.message);
+                let hint = rejection.retry.map(Duration::from_secs).unwrap_or(RECONNECT_DELAY);
+                match rejection.code {
+                    // 重複が解消されるまで待ち時間を倍にしていく
+                    ErrorCode::DuplicateHostname => {
+                        let delay = duplicate_backoff
+                            .map(|previous| (previous * 2).min(MAX_DUPLICATE_BACKOFF))
+                            .unwrap_or(hint)
+                            .max(hint);
+                        duplicate_backoff = Some(delay);
+                        delay
+                    }
+                    _ => {
+                        duplicate_backoff = None;
+                        hint
+                    }
+                }
             }
             Err(e) => {
                 error!("Connection error: {}", e);
+                RECONNECT_DELAY
             }
-        }
+        };
 
-        info!("Reconnecting in 5 seconds...");
-        sleep(Duration::from_secs(5)).await;
+        info!("Reconnecting in {} seconds...", delay.as_secs());
+        sleep(delay).await;
     }
 }
 
 async fn connect_to_server(
     server_url: &str,
     password: &str,
+    machine_id: &str,
     dev_mode: bool,
+    wire_format: WireFormat,
     system_collector: &mut SystemInfoCollector,
-) -> Result<()> {
+    handlers: &Handlers,
+) -> Result<Option<Rejection>> {
     info!("Connecting to server: {}", server_url);
     
     let (ws_stream, _) = connect_async(server_url).await?;
     let (mut write, mut read) = ws_stream.split();
 
+    // サーバーの挨拶を待ってから送信形式を決める
+    let hello = match tokio::time::timeout(HELLO_TIMEOUT, read.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => ServerMessage::decode(text.as_bytes(), WireFormat::Json)?,
+        Ok(Some(Ok(Message::Binary(bytes)))) => ServerMessage::decode(&bytes, WireFormat::MessagePack)?,
+        Ok(Some(Ok(_))) | Ok(None) => bail!("Connection closed before server greeting"),
+        Ok(Some(Err(e))) => return Err(e.into()),
+        Err(_) => bail!("Timed out waiting for server greeting"),
+    };
+    let hello = match hello {
+        ServerMessage::Hi(hello) => hello,
+        other => bail!("Unexpected first message from server: {:?}", other),
+    };
+    info!("Server greeting: {} (protocol {}, capabilities {:?})", hello.message, hello.protocol, hello.capabilities);
+
+    let wire_format = if wire_format == WireFormat::MessagePack && !hello.supports(Capability::Binary) {
+        warn!("Server does not support binary frames, falling back to JSON");
+        WireFormat::Json
+    } else {
+        wire_format
+    };
+
     // 初回接続時にシステム情報を送信
     let mut status_data = system_collector.collect_system_info().await?;
     status_data.dev = Some(dev_mode);
     
+    // 許可リスト・自動更新を設定している場合だけコマンド・更新の指示を受け付ける
+    let mut capabilities = AGENT_CAPABILITIES.to_vec();
+    if handlers.commands.is_some() {
+        capabilities.push(Capability::Commands);
+    }
+    if handlers.updates.is_some() {
+        capabilities.push(Capability::Updates);
+    }
     let hi_message = ClientMessage::Hi {
         data: status_data,
         pass: Some(password.to_string()),
+        machine_id: Some(machine_id.to_string()),
+        protocol: Some(PROTOCOL_VERSION),
+        capabilities,
     };
     
-    let message_json = hi_message.to_json()?;
-    write.send(Message::Text(message_json.into())).await?;
+    write.send(encode_frame(&hi_message, wire_format)?).await?;
     info!("Sent initial system info");
 
-    // 定期的にシステム情報を送信するタスク
+    // 定期的にシステム情報を送信するタスク（コマンドの実行結果も送る）
+    let (reply_tx, mut reply_rx) = mpsc::channel::<ClientMessage>(8);
     let mut write_for_sync = write;
     let password_clone = password.to_string();
     let sync_task = tokio::spawn(async move {
@@ -158,7 +316,20 @@ async fn connect_to_server(
         let start_time = std::time::Instant::now();
 
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                Some(reply) = reply_rx.recv() => {
+                    match encode_frame(&reply, wire_format) {
+                        Ok(frame) => {
+                            if write_for_sync.send(frame).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => error!("Failed to encode reply: {}", e),
+                    }
+                    continue;
+                }
+            }
 
             match collector.collect_system_info().await {
                 Ok(mut status_data) => {
@@ -166,12 +337,12 @@ async fn connect_to_server(
                     status_data.pass = Some(password_clone.clone());
 
                     let sync_message = ClientMessage::Sync(status_data);
-                    if let Ok(json) = sync_message.to_json() {
-                        if write_for_sync.send(Message::Text(json.into())).await.is_ok() {
+                    if let Ok(frame) = encode_frame(&sync_message, wire_format) {
+                        if write_for_sync.send(frame).await.is_ok() {
                             send_count += 1;
 
                             // 10秒ごとに統計情報をログ出力
-                            if send_count % 10 == 0 {
+                            if send_count.is_multiple_of(10) {
                                 let elapsed = start_time.elapsed();
                                 let avg_interval = elapsed.as_millis() as f64 / send_count as f64;
                                 info!("Client send stats: {} messages in {:.2}s (avg: {:.1}ms interval)",
@@ -190,29 +361,20 @@ async fn connect_to_server(
     });
 
     // サーバーからのメッセージを処理
+    let mut rejection = None;
+    // 更新に対応したサーバーは登録を受け付けた接続にだけRegisteredを送る
+    let acknowledges_registration = hello.supports(Capability::Updates);
+    let mut registered = false;
     while let Some(msg) = read.next().await {
-        match msg {
+        // フレームの種類でエンコード形式を判別する
+        let decoded = match msg {
             Ok(Message::Text(text)) => {
                 debug!("Received message: {}", text);
-                
-                match ServerMessage::from_json(&text) {
-                    Ok(ServerMessage::Hi(greeting)) => {
-                        info!("Server greeting: {}", greeting);
-                    }
-                    Ok(ServerMessage::Close) => {
-                        warn!("Server requested connection close");
-                        break;
-                    }
-                    Ok(ServerMessage::Sync(sync_msg)) => {
-                        debug!("Sync message: {}", sync_msg);
-                    }
-                    Ok(_) => {
-                        debug!("Received other message type");
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse server message: {}", e);
-                    }
-                }
+                ServerMessage::decode(text.as_bytes(), WireFormat::Json)
+            }
+            Ok(Message::Binary(bytes)) => {
+                debug!("Received binary message ({} bytes)", bytes.len());
+                ServerMessage::decode(&bytes, WireFormat::MessagePack)
             }
             Ok(Message::Close(_)) => {
                 info!("Server closed connection");
@@ -222,10 +384,86 @@ async fn connect_to_server(
                 error!("WebSocket error: {}", e);
                 break;
             }
-            _ => {}
+            _ => continue,
+        };
+
+        match decoded {
+            Ok(ServerMessage::Hi(hello)) => {
+                info!("Server greeting: {}", hello.message);
+            }
+            Ok(ServerMessage::Error { code, message, retry }) => {
+                debug!("Server error ({:?}): {}", code, message);
+                rejection = Some(Rejection { code, message, retry });
+            }
+            Ok(ServerMessage::Close) => {
+                warn!("Server requested connection close");
+                break;
+            }
+            Ok(ServerMessage::Registered) => {
+                debug!("Registered by server");
+                // 登録されたことが分かってから更新を確定し、結果を送る
+                registered = true;
+                updater::confirm_update();
+                send_update_reports(&reply_tx).await;
+            }
+            Ok(ServerMessage::Sync(sync_msg)) => {
+                debug!("Sync message: {}", sync_msg);
+                // Syncはほかのエージェントの送信でも届くため、Registeredを送るサーバーでは登録の確認に使わない
+                if !registered && !acknowledges_registration {
+                    registered = true;
+                    updater::confirm_update();
+                }
+                if registered {
+                    send_update_reports(&reply_tx).await;
+                }
+            }
+            Ok(ServerMessage::Update(offer)) => match &handlers.updates {
+                Some(updates) => updates.offer(server_url, offer),
+                None => warn!("Ignoring update offer {}: auto-update is disabled", offer.version),
+            },
+            Ok(ServerMessage::Command(request)) => {
+                // 実行中も状態の送信を続けるため別タスクで実行する
+                let commands = handlers.commands.clone();
+                let reply_tx = reply_tx.clone();
+                tokio::spawn(async move {
+                    let result = match commands {
+                        Some(commands) => commands.run(&request).await,
+                        None => {
+                            warn!("Refusing command {}: remote commands are disabled", request.command);
+                            CommandResult::rejected(&request.id, CommandStatus::NotAllowed, "Remote commands are disabled")
+                        }
+                    };
+                    if reply_tx.send(ClientMessage::CommandResult(result)).await.is_err() {
+                        warn!("Connection closed before sending the result of {}", request.id);
+                    }
+                });
+            }
+            Ok(_) => {
+                debug!("Received other message type");
+            }
+            Err(e) => {
+                warn!("Failed to parse server message: {}", e);
+            }
         }
     }
 
     sync_task.abort();
-    Ok(())
+    Ok(rejection)
+}
+
+/// 送信待ちの更新の結果を送る
+async fn send_update_reports(reply_tx: &mpsc::Sender<ClientMessage>) {
+    for report in updater::take_reports() {
+        if reply_tx.send(ClientMessage::UpdateReport(report)).await.is_err() {
+            warn!("Connection closed before sending the update report");
+        }
+    }
+}
+
+/// 設定されたエンコード形式でWebSocketフレームを作成する
+fn encode_frame(message: &ClientMessage, format: WireFormat) -> Result<Message> {
+    Ok(match format {
+        WireFormat::Json => Message::Text(message.to_json()?.into()),
+        WireFormat::MessagePack => Message::Binary(message.encode(format)?.into()),
+    })
 }
@@ -1,27 +1,39 @@
 use anyhow::Result;
 use cfg_if::cfg_if;
 use itertools::Itertools;
-use pc_status_shared::{StatusData, Cpu, CpuData, Ram, Swap, Storage};
+use pc_status_shared::{parse_labels, parse_tags, StatusData, Cpu, CpuData, Ram, Swap, Storage};
+use std::collections::BTreeMap;
 use std::env;
 use sysinfo::System;
+use tracing::warn;
 
 use crate::{gpu, sysinfo_instance::SysinfoInstance, updater};
 
 pub struct SystemInfoCollector {
     sysinfo: SysinfoInstance,
+    tags: Vec<String>,
+    labels: BTreeMap<String, String>,
 }
 
 impl SystemInfoCollector {
     pub fn new() -> Self {
+        // タグ・ラベル（環境変数TAGS・LABELSから取得）
+        let tags = env::var("TAGS").map(|value| parse_tags(&value)).unwrap_or_default();
+        let labels = match env::var("LABELS") {
+            Ok(value) => parse_labels(&value).unwrap_or_else(|e| {
+                warn!("{}, ignoring LABELS", e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
         Self {
             sysinfo: SysinfoInstance::new(),
+            tags,
+            labels,
         }
     }
 
-    pub fn refresh(&mut self) {
-        self.sysinfo.refresh();
-    }
-
     pub async fn collect_system_info(&mut self) -> Result<StatusData> {
         // システム情報を更新
         self.sysinfo.refresh();
@@ -31,7 +43,7 @@ impl SystemInfoCollector {
         // OS情報
         let os_name = System::name().unwrap_or_else(|| "unknown".to_string());
         let os_version = System::os_version()
-            .or_else(|| System::kernel_version())
+            .or_else(System::kernel_version)
             .unwrap_or_else(|| "unknown".to_string());
 
         // ホスト名（環境変数から取得、なければシステムから）
@@ -115,6 +127,9 @@ impl SystemInfoCollector {
             gpus,
             index: 0,
             histories: vec![],
+            tags: self.tags.clone(),
+            labels: self.labels.clone(),
+            tenant: String::new(),
         })
     }
 
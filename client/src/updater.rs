@@ -1,30 +1,958 @@
-use std::{env, path::PathBuf};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use pc_status_shared::{SignedManifest, UpdateManifest, UpdateOffer, UpdateOutcome, UpdateReport};
+use reqwest::Url;
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{env, fs, io};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
 
 const GIT_DESCRIBE: &str = env!("GIT_DESCRIBE");
+/// このバイナリのターゲットトリプル
+const TARGET: &str = env!("TARGET");
 
-fn restart_program(bin_install_path: PathBuf) {
-    use std::process::{exit, Command};
+/// マニフェストの署名を検証するEd25519公開鍵（ビルド時に埋め込む）
+const PINNED_PUBLIC_KEY: &str = include_str!("../update-public-key.txt");
+
+/// 更新を確認する既定の間隔
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+/// GitHub APIの既定のURL
+const DEFAULT_GITHUB_API: &str = "https://api.github.com";
+/// ダウンロードする実行ファイルの最大サイズ
+const MAX_DOWNLOAD_BYTES: u64 = 256 * 1024 * 1024;
+/// 更新後の起動がこの回数を超えても接続できなければ元に戻す
+const MAX_TRIAL_STARTS: u32 = 3;
+/// 更新後にこの時間内に接続できなければ元に戻す
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// 更新後の動作（環境変数`PCSC_UPDATED`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// 更新しない
+    None,
+    /// 更新後に新しいバイナリを起動する
+    Restart,
+    /// 更新後に終了する（サービスマネージャーによる再起動を前提とする）
+    Terminate,
+}
+
+impl UpdateMode {
+    fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "restart" => Ok(Self::Restart),
+            "terminate" => Ok(Self::Terminate),
+            other => bail!("Invalid PCSC_UPDATED: {} (expected restart, terminate or none)", other),
+        }
+    }
+}
+
+/// 更新の取得元
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReleaseSource {
+    /// `SignedManifest`のJSONを返すURL（pc-statusサーバーなど）
+    Manifest(Url),
+    /// GitHub互換のリリースAPI（`{api}/repos/{repo}/releases/latest`の`manifest.json`）
+    GitHub { api: Url, repo: String },
+}
+
+impl ReleaseSource {
+    /// `github:owner/repo`またはマニフェストのURL
+    fn parse(value: &str, github_api: Option<&str>) -> Result<Self> {
+        if let Some(repo) = value.strip_prefix("github:") {
+            if repo.split('/').filter(|part| !part.is_empty()).count() != 2 {
+                bail!("Invalid GitHub repository: {} (expected github:owner/repo)", repo);
+            }
+            let mut api = Url::parse(github_api.unwrap_or(DEFAULT_GITHUB_API)).context("Invalid UPDATE_GITHUB_API")?;
+            // `https://host/api/v3`のようなパスの最後の部分が`join`で失われないよう`/`で終える
+            if !api.path().ends_with('/') {
+                api.set_path(&format!("{}/", api.path()));
+            }
+            return Ok(Self::GitHub { api, repo: repo.to_string() });
+        }
+        Ok(Self::Manifest(Url::parse(value).with_context(|| format!("Invalid UPDATE_SOURCE: {}", value))?))
+    }
+}
+
+/// 自動更新の設定
+#[derive(Debug, Clone)]
+pub struct UpdaterConfig {
+    pub mode: UpdateMode,
+    /// 定期的に確認する取得元（未設定の場合はサーバーの指示でのみ更新する）
+    pub source: Option<ReleaseSource>,
+    /// マニフェストの署名を検証するEd25519公開鍵
+    pub public_key: Vec<u8>,
+    pub interval: Duration,
+}
+
+impl UpdaterConfig {
+    /// 環境変数から読み込む（更新しない設定の場合はNone）
+    pub fn from_env() -> Result<Option<Self>> {
+        let mode = UpdateMode::parse(&env::var("PCSC_UPDATED").unwrap_or_default())?;
+        if mode == UpdateMode::None {
+            return Ok(None);
+        }
+        let source = env::var("UPDATE_SOURCE")
+            .ok()
+            .filter(|source| !source.is_empty())
+            .map(|source| ReleaseSource::parse(&source, env::var("UPDATE_GITHUB_API").ok().as_deref()))
+            .transpose()?;
+        let public_key = pinned_public_key(PINNED_PUBLIC_KEY)?
+            .context("PCSC_UPDATED is set but this build has no pinned update key (client/update-public-key.txt)")?;
+        let interval = env::var("UPDATE_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_CHECK_INTERVAL);
+        Ok(Some(Self { mode, source, public_key, interval }))
+    }
+}
+
+/// 取得元から見つけた更新
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    pub url: Url,
+    pub size: u64,
+    pub sha256: Vec<u8>,
+    /// 古いバージョンへ戻すことを署名したマニフェストが明示しているか
+    pub rollback: bool,
+}
+
+/// GitHubのリリース
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// 更新後の試用中の状態（実行ファイルの隣に保存する）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TrialState {
+    version: String,
+    previous_version: String,
+    /// 更新後に起動した回数
+    starts: u32,
+}
+
+/// 更新の確認・適用
+pub struct Updater {
+    config: UpdaterConfig,
+    http: reqwest::Client,
+    exe: PathBuf,
+    /// 実行中のバージョン
+    version: String,
+}
+
+impl Updater {
+    pub fn new(config: UpdaterConfig) -> Result<Self> {
+        let exe = env::current_exe().context("Failed to locate the agent executable")?;
+        Self::with_executable(config, exe)
+    }
+
+    fn with_executable(config: UpdaterConfig, exe: PathBuf) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(format!("pc-status-client/{}", GIT_DESCRIBE))
+            .timeout(Duration::from_secs(300))
+            .build()?;
+        Ok(Self { config, http, exe, version: GIT_DESCRIBE.to_string() })
+    }
+
+    /// 更新を確認するタスクを開始し、サーバーの指示を渡すハンドルを返す
+    pub fn spawn(self) -> UpdateHandle {
+        // 更新中に届いた指示は捨てる
+        let (offers_tx, offers_rx) = mpsc::channel(1);
+        tokio::spawn(self.run(offers_rx));
+        UpdateHandle { offers: offers_tx }
+    }
+
+    /// 定期的に、またはサーバーの指示で更新を確認し、見つかれば適用する
+    async fn run(self, mut offers: mpsc::Receiver<UpdateOffer>) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick(), if self.config.source.is_some() => match self.check().await {
+                    Ok(Some(release)) => self.update(&release).await,
+                    Ok(None) => debug!("No update available (current: {})", self.version),
+                    Err(e) => warn!("Failed to check for updates: {:#}", e),
+                },
+                Some(offer) = offers.recv() => match self.offered_release(&offer).await {
+                    Ok(Some(release)) => self.update(&release).await,
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Failed to check offered update {}: {:#}", offer.version, e);
+                        report(&offer.version, UpdateOutcome::Failed, Some(format!("{:#}", e)));
+                    }
+                },
+                else => break,
+            }
+        }
+    }
+
+    async fn update(&self, release: &Release) {
+        match self.apply(release).await {
+            Ok(()) => self.finish(release),
+            Err(e) => {
+                error!("Failed to update to {}: {:#}", release.version, e);
+                report(&release.version, UpdateOutcome::Failed, Some(format!("{:#}", e)));
+            }
+        }
+    }
+
+    /// 現在より新しい更新を探す
+    pub async fn check(&self) -> Result<Option<Release>> {
+        let release = match &self.config.source {
+            Some(ReleaseSource::Manifest(url)) => self.manifest_release(url).await?,
+            Some(ReleaseSource::GitHub { api, repo }) => self.github_release(api, repo).await?,
+            None => return Ok(None),
+        };
+        let Some(release) = release else {
+            debug!("No release for target {}", TARGET);
+            return Ok(None);
+        };
+        if !is_allowed(&self.version, &release) {
+            return Ok(None);
+        }
+        if failed_version(&self.exe).as_deref() == Some(release.version.as_str()) {
+            debug!("Skipping {} which was rolled back before", release.version);
+            return Ok(None);
+        }
+        Ok(Some(release))
+    }
+
+    /// サーバーが指示した更新を探す（古いバージョンへはマニフェストが戻すことを明示した場合だけ）
+    async fn offered_release(&self, offer: &UpdateOffer) -> Result<Option<Release>> {
+        // 更新後の試用中は、同じバージョンへの指示を受けても再び更新しない
+        let trial = TRIAL.lock().expect("trial lock poisoned").as_ref().map(|(_, version)| version.clone());
+        if offer.version == self.version || trial.as_deref() == Some(offer.version.as_str()) {
+            return Ok(None);
+        }
+        if failed_version(&self.exe).as_deref() == Some(offer.version.as_str()) {
+            debug!("Skipping {} which was rolled back before", offer.version);
+            report(&offer.version, UpdateOutcome::RolledBack, Some("Rolled back before, not retrying".to_string()));
+            return Ok(None);
+        }
+        let url = Url::parse(&offer.manifest).with_context(|| format!("Invalid manifest URL: {}", offer.manifest))?;
+        let release = self
+            .manifest_release(&url)
+            .await?
+            .with_context(|| format!("No release for target {}", TARGET))?;
+        if release.version != offer.version {
+            bail!("Manifest offers {}, expected {}", release.version, offer.version);
+        }
+        if !is_allowed(&self.version, &release) {
+            bail!("Refusing to downgrade to {} without a signed rollback", release.version);
+        }
+        Ok(Some(release))
+    }
+
+    /// 署名付きのマニフェストを取得して検証し、このターゲットの実行ファイルを探す
+    async fn manifest_release(&self, url: &Url) -> Result<Option<Release>> {
+        let signed: SignedManifest = self.http.get(url.clone()).send().await?.error_for_status()?.json().await?;
+        let manifest = verify_manifest(&signed, &self.config.public_key, Utc::now())?;
+        let Some(asset) = manifest.asset(TARGET) else {
+            return Ok(None);
+        };
+        Ok(Some(Release {
+            version: manifest.version.clone(),
+            url: url.join(&asset.url).with_context(|| format!("Invalid asset URL: {}", asset.url))?,
+            size: asset.size,
+            sha256: hex::decode(&asset.sha256).context("Invalid sha256 in manifest")?,
+            rollback: manifest.rollback,
+        }))
+    }
 
+    /// 最新のリリースに添付された`manifest.json`（`SignedManifest`）から探す
+    async fn github_release(&self, api: &Url, repo: &str) -> Result<Option<Release>> {
+        let url = api.join(&format!("repos/{}/releases/latest", repo))?;
+        let release: GitHubRelease = self
+            .http
+            .get(url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let Some(manifest) = release.assets.iter().find(|asset| asset.name == "manifest.json") else {
+            bail!("Release {} has no manifest.json", release.tag_name);
+        };
+        // 実行ファイルはマニフェストからの相対パス（同じリリースの添付ファイル）で参照できる
+        self.manifest_release(&Url::parse(&manifest.browser_download_url)?).await
+    }
+
+    /// ダウンロードして検証し、実行ファイルを置き換える
+    pub async fn apply(&self, release: &Release) -> Result<()> {
+        info!("Downloading update {} from {}", release.version, release.url);
+        let binary = self.download(release).await?;
+        verify(&binary, release)?;
+        install(&self.exe, &binary, &release.version)?;
+        info!("Installed update {} (previous: {})", release.version, GIT_DESCRIBE);
+        Ok(())
+    }
+
+    async fn download(&self, release: &Release) -> Result<Vec<u8>> {
+        if release.size > MAX_DOWNLOAD_BYTES {
+            bail!("Update is too large: {} bytes", release.size);
+        }
+        let mut response = self.http.get(release.url.clone()).send().await?.error_for_status()?;
+        let mut binary = Vec::with_capacity(release.size as usize);
+        while let Some(chunk) = response.chunk().await? {
+            binary.extend_from_slice(&chunk);
+            if binary.len() as u64 > release.size {
+                bail!("Downloaded more than the expected {} bytes", release.size);
+            }
+        }
+        Ok(binary)
+    }
+
+    fn finish(&self, release: &Release) {
+        match self.config.mode {
+            UpdateMode::Restart => {
+                info!("Restarting into {}", release.version);
+                restart_program(self.exe.clone());
+            }
+            UpdateMode::Terminate => {
+                info!("Exiting so that {} is started by the service manager", release.version);
+                std::process::exit(0);
+            }
+            UpdateMode::None => {}
+        }
+    }
+}
+
+/// 新しいバージョンか（どちらかがsemverでない場合は比べられないため新しいとみなさない）
+fn is_newer(current: &str, candidate: &str) -> bool {
+    let strip = |version: &str| version.trim_start_matches('v').to_string();
+    self_update::version::bump_is_greater(&strip(current), &strip(candidate)).unwrap_or(false)
+}
+
+/// 現在のバージョンから適用してよい更新か（古いバージョンはマニフェストが戻すことを明示した場合だけ）
+fn is_allowed(current: &str, release: &Release) -> bool {
+    release.version != current && (release.rollback || is_newer(current, &release.version))
+}
+
+/// 埋め込んだ公開鍵を読む（`#`で始まる行と空行は無視し、鍵がなければNone）
+fn pinned_public_key(text: &str) -> Result<Option<Vec<u8>>> {
+    let Some(line) = text.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')) else {
+        return Ok(None);
+    };
+    let public_key = hex::decode(line).context("The pinned update key must be hex")?;
+    if public_key.len() != 32 {
+        bail!("The pinned update key must be a 32-byte Ed25519 public key");
+    }
+    Ok(Some(public_key))
+}
+
+/// マニフェストの署名と有効期限を検証してから中身を読む
+fn verify_manifest(signed: &SignedManifest, public_key: &[u8], now: DateTime<Utc>) -> Result<UpdateManifest> {
+    let signature = hex::decode(signed.signature.trim()).context("Invalid manifest signature")?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(signed.manifest.as_bytes(), &signature)
+        .map_err(|_| anyhow::anyhow!("Invalid manifest signature"))?;
+    let manifest = signed.parse_unverified().context("Invalid manifest")?;
+    if manifest.expires_at <= now {
+        bail!("Manifest for {} expired at {}", manifest.version, manifest.expires_at);
+    }
+    Ok(manifest)
+}
+
+/// サイズ・SHA-256を署名済みのマニフェストと照合する
+fn verify(binary: &[u8], release: &Release) -> Result<()> {
+    if binary.len() as u64 != release.size {
+        bail!("Size mismatch: expected {} bytes, got {}", release.size, binary.len());
+    }
+    if digest(&SHA256, binary).as_ref() != release.sha256.as_slice() {
+        bail!("Checksum mismatch for {}", release.version);
+    }
+    Ok(())
+}
+
+/// 実行ファイルと同じディレクトリに置くファイル（`{実行ファイル}.{suffix}`）
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut path = exe.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    path.into()
+}
+
+/// 元に戻すためのバックアップ
+fn backup_path(exe: &Path) -> PathBuf {
+    sibling(exe, "previous")
+}
+
+fn state_path(exe: &Path) -> PathBuf {
+    sibling(exe, "update.json")
+}
+
+/// 元に戻したバージョン（再び適用しない）
+fn failed_path(exe: &Path) -> PathBuf {
+    sibling(exe, "update-failed")
+}
+
+/// 元に戻したことを、戻した後の起動でサーバーに報告する
+fn report_path(exe: &Path) -> PathBuf {
+    sibling(exe, "update-report.json")
+}
+
+fn failed_version(exe: &Path) -> Option<String> {
+    fs::read_to_string(failed_path(exe)).ok().map(|version| version.trim().to_string())
+}
+
+/// 検証済みのバイナリで実行ファイルを置き換え、試用中の状態を保存する
+fn install(exe: &Path, binary: &[u8], version: &str) -> Result<()> {
+    let new = sibling(exe, "new");
+    write_executable(&new, binary)?;
+    fs::copy(exe, backup_path(exe)).context("Failed to back up the current executable")?;
+    replace_executable(&new, exe).context("Failed to replace the executable")?;
+    let state = TrialState { version: version.to_string(), previous_version: GIT_DESCRIBE.to_string(), starts: 0 };
+    fs::write(state_path(exe), serde_json::to_vec(&state)?)?;
+    Ok(())
+}
+
+fn write_executable(path: &Path, binary: &[u8]) -> io::Result<()> {
+    fs::write(path, binary)?;
+    #[cfg(unix)]
     {
-        Command::new(bin_install_path)
-            .spawn()
-            .expect("Failed to restart the program");
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
     }
+    Ok(())
+}
 
-    exit(0);
+/// 実行ファイルを置き換える（同じディレクトリ内の名前の変更なので途中の状態は残らない）
+fn replace_executable(new: &Path, exe: &Path) -> io::Result<()> {
+    // Windowsでは実行中のファイルを上書きできないため、self_replaceに任せる
+    #[cfg(windows)]
+    if env::current_exe().is_ok_and(|current| current == exe) {
+        self_update::self_replace::self_replace(new)?;
+        return fs::remove_file(new);
+    }
+    fs::rename(new, exe)
 }
 
-pub fn update() -> Result<(), Box<dyn std::error::Error>> {
-    // 自動更新機能は現在無効化（必要に応じて有効化）
-    // 元のコードではGitHubからの自動更新を行っていたが、
-    // 新しいプロジェクトでは手動更新を推奨
-    
-    println!("Current version: {}", GIT_DESCRIBE);
-    println!("Auto-update is disabled. Please update manually if needed.");
-    
+/// バックアップに戻し、失敗したバージョンと報告を記録する
+fn rollback(exe: &Path, reason: &str) -> Result<()> {
+    let state: Option<TrialState> = fs::read(state_path(exe)).ok().and_then(|json| serde_json::from_slice(&json).ok());
+    let restored = sibling(exe, "rollback");
+    fs::copy(backup_path(exe), &restored).context("No backup to roll back to")?;
+    replace_executable(&restored, exe).context("Failed to restore the previous executable")?;
+    if let Some(state) = state {
+        fs::write(failed_path(exe), &state.version)?;
+        let report = UpdateReport {
+            version: state.version.clone(),
+            outcome: UpdateOutcome::RolledBack,
+            message: Some(reason.to_string()),
+        };
+        fs::write(report_path(exe), serde_json::to_vec(&report)?)?;
+        warn!("Rolled back from {} to {}: {}", state.version, state.previous_version, reason);
+    }
+    let _ = fs::remove_file(state_path(exe));
     Ok(())
 }
 
+/// 更新後の試用中に、接続を確認するまで記録しておく実行ファイルとバージョン
+static TRIAL: Mutex<Option<(PathBuf, String)>> = Mutex::new(None);
+/// サーバーに送る更新の結果
+static REPORTS: Mutex<Vec<UpdateReport>> = Mutex::new(Vec::new());
+
+fn report(version: &str, outcome: UpdateOutcome, message: Option<String>) {
+    let report = UpdateReport { version: version.to_string(), outcome, message };
+    REPORTS.lock().expect("reports lock poisoned").push(report);
+}
+
+/// 送信待ちの更新の結果を取り出す
+pub fn take_reports() -> Vec<UpdateReport> {
+    std::mem::take(&mut *REPORTS.lock().expect("reports lock poisoned"))
+}
+
+/// 接続からアップデーターにサーバーの指示を渡す
+#[derive(Clone)]
+pub struct UpdateHandle {
+    offers: mpsc::Sender<UpdateOffer>,
+}
+
+impl UpdateHandle {
+    /// サーバーの指示を渡す（マニフェストの相対パスは接続先のサーバーから解決する）
+    pub fn offer(&self, server_url: &str, mut offer: UpdateOffer) {
+        match resolve_manifest(server_url, &offer.manifest) {
+            Ok(url) => offer.manifest = url.to_string(),
+            Err(e) => {
+                warn!("Ignoring update offer {}: {:#}", offer.version, e);
+                report(&offer.version, UpdateOutcome::Failed, Some(format!("{:#}", e)));
+                return;
+            }
+        }
+        info!("Server offered update {}", offer.version);
+        if self.offers.try_send(offer).is_err() {
+            debug!("An update is already in progress, ignoring offer");
+        }
+    }
+}
+
+/// WebSocketのURLを基準にマニフェストのURLを解決する
+fn resolve_manifest(server_url: &str, manifest: &str) -> Result<Url> {
+    let mut base = Url::parse(server_url).with_context(|| format!("Invalid server URL: {}", server_url))?;
+    let scheme = match base.scheme() {
+        "wss" => "https",
+        "ws" => "http",
+        other => other,
+    }
+    .to_string();
+    base.set_scheme(&scheme).map_err(|_| anyhow::anyhow!("Cannot derive an HTTP URL from {}", server_url))?;
+    base.join(manifest).with_context(|| format!("Invalid manifest URL: {}", manifest))
+}
+
+/// 起動時に更新後の試用中かを確認する
+///
+/// 接続できないまま起動を繰り返した場合や、一定時間内に接続できなかった場合は元のバイナリに戻して再起動する。
+pub fn check_pending_update() {
+    let Ok(exe) = env::current_exe() else {
+        return;
+    };
+    // 元に戻す前に記録した報告を、戻した後の接続で送る
+    if let Ok(json) = fs::read(report_path(&exe)) {
+        if let Ok(report) = serde_json::from_slice::<UpdateReport>(&json) {
+            REPORTS.lock().expect("reports lock poisoned").push(report);
+        }
+        let _ = fs::remove_file(report_path(&exe));
+    }
+    match start_trial(&exe) {
+        Ok(TrialStart::None) => {}
+        Ok(TrialStart::Started(state)) => {
+            info!("Running updated version {} (start {}/{})", state.version, state.starts, MAX_TRIAL_STARTS);
+            *TRIAL.lock().expect("trial lock poisoned") = Some((exe.clone(), state.version));
+            tokio::spawn(async move {
+                tokio::time::sleep(TRIAL_TIMEOUT).await;
+                if TRIAL.lock().expect("trial lock poisoned").is_some() {
+                    let reason = format!("Could not connect within {:?} after updating", TRIAL_TIMEOUT);
+                    error!("{}", reason);
+                    roll_back_and_restart(&exe, &reason);
+                }
+            });
+        }
+        Ok(TrialStart::Exhausted(state)) => {
+            let reason = format!("Failed to connect after {} starts", MAX_TRIAL_STARTS);
+            error!("Version {}: {}", state.version, reason);
+            roll_back_and_restart(&exe, &reason);
+        }
+        Err(e) => warn!("Failed to read update state: {:#}", e),
+    }
+}
+
+/// 更新後に接続できたことを記録する（試用中でなければ何もしない）
+pub fn confirm_update() {
+    let Some((exe, version)) = TRIAL.lock().expect("trial lock poisoned").take() else {
+        return;
+    };
+    match fs::remove_file(state_path(&exe)) {
+        Ok(()) => info!("Update to {} confirmed", version),
+        Err(e) => warn!("Failed to clear update state: {}", e),
+    }
+    report(&version, UpdateOutcome::Installed, None);
+}
+
+fn roll_back_and_restart(exe: &Path, reason: &str) {
+    match rollback(exe, reason) {
+        Ok(()) => restart_program(exe.to_path_buf()),
+        Err(e) => error!("Failed to roll back: {:#}", e),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TrialStart {
+    /// 試用中ではない
+    None,
+    Started(TrialState),
+    /// 起動の回数を使い切った
+    Exhausted(TrialState),
+}
+
+fn start_trial(exe: &Path) -> Result<TrialStart> {
+    let json = match fs::read(state_path(exe)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(TrialStart::None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut state: TrialState = serde_json::from_slice(&json)?;
+    state.starts += 1;
+    if state.starts > MAX_TRIAL_STARTS {
+        return Ok(TrialStart::Exhausted(state));
+    }
+    fs::write(state_path(exe), serde_json::to_vec(&state)?)?;
+    Ok(TrialStart::Started(state))
+}
+
+/// 同じ引数で起動し直す（起動できなければ異常終了し、サービスマネージャーに再起動を任せる）
+fn restart_program(bin_install_path: PathBuf) {
+    use std::process::{exit, Command};
+
+    // 新しいプロセスは起動したまま終了する（待たずに終了するため子プロセスは残らない）
+    match Command::new(&bin_install_path).args(env::args_os().skip(1)).spawn() {
+        Ok(_child) => exit(0),
+        Err(e) => {
+            error!("Failed to restart {}: {}", bin_install_path.display(), e);
+            exit(1);
+        }
+    }
+}
+
 pub fn get_version() -> &'static str {
     GIT_DESCRIBE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// パスごとに固定の内容を返すHTTPサーバー（内容はサーバーのURLから作る）
+    async fn serve(files: impl FnOnce(&Url) -> HashMap<String, Vec<u8>>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let files = Arc::new(files(&url));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let files = files.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut request_line = String::new();
+                    stream.read_line(&mut request_line).await.unwrap();
+                    // ヘッダーを読み飛ばす
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).await.unwrap() > 2 {
+                        line.clear();
+                    }
+                    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+                    let response = match files.get(path) {
+                        Some(body) => {
+                            let mut response =
+                                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len())
+                                    .into_bytes();
+                            response.extend_from_slice(body);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                    };
+                    stream.get_mut().write_all(&response).await.unwrap();
+                });
+            }
+        });
+        url
+    }
+
+    fn key_pair() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn updater(source: ReleaseSource, key: &Ed25519KeyPair, exe: PathBuf) -> Updater {
+        let config = UpdaterConfig {
+            mode: UpdateMode::None,
+            source: Some(source),
+            public_key: key.public_key().as_ref().to_vec(),
+            interval: DEFAULT_CHECK_INTERVAL,
+        };
+        let mut updater = Updater::with_executable(config, exe).unwrap();
+        updater.version = "v1.0.0".to_string();
+        updater
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("pc-status-updater-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn unsigned_manifest(binary: &[u8]) -> UpdateManifest {
+        UpdateManifest {
+            version: "v999.0.0".to_string(),
+            assets: vec![pc_status_shared::UpdateAsset {
+                target: TARGET.to_string(),
+                url: "binaries/client".to_string(),
+                size: binary.len() as u64,
+                sha256: hex::encode(digest(&SHA256, binary)),
+            }],
+            expires_at: Utc::now() + chrono::Duration::days(1),
+            rollback: false,
+        }
+    }
+
+    fn sign(manifest: &UpdateManifest, key: &Ed25519KeyPair) -> SignedManifest {
+        let manifest = serde_json::to_string(manifest).unwrap();
+        let signature = hex::encode(key.sign(manifest.as_bytes()));
+        SignedManifest { manifest, signature }
+    }
+
+    fn manifest(binary: &[u8], key: &Ed25519KeyPair) -> Vec<u8> {
+        serde_json::to_vec(&sign(&unsigned_manifest(binary), key)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_manifest_update_is_verified_and_installed() {
+        let key = key_pair();
+        let binary = b"new agent binary".to_vec();
+        let base = serve(|_| {
+            HashMap::from([
+                ("/api/agent/manifest".to_string(), manifest(&binary, &key)),
+                ("/api/agent/binaries/client".to_string(), binary.clone()),
+            ])
+        })
+        .await;
+
+        let dir = temp_dir();
+        let exe = dir.join("client");
+        fs::write(&exe, b"old agent binary").unwrap();
+        let updater = updater(ReleaseSource::Manifest(base.join("api/agent/manifest").unwrap()), &key, exe.clone());
+
+        let release = updater.check().await.unwrap().expect("update available");
+        assert_eq!(release.version, "v999.0.0");
+        assert_eq!(release.url, base.join("api/agent/binaries/client").unwrap());
+        updater.apply(&release).await.unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), binary);
+        assert_eq!(fs::read(backup_path(&exe)).unwrap(), b"old agent binary");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_manifests_are_rejected() {
+        let key = key_pair();
+        let public_key = key.public_key().as_ref();
+        let signed = sign(&unsigned_manifest(b"new agent binary"), &key);
+        let now = Utc::now();
+        assert_eq!(verify_manifest(&signed, public_key, now).unwrap().version, "v999.0.0");
+
+        // 署名後に中身を書き換えたマニフェスト
+        let altered = SignedManifest { manifest: signed.manifest.replace("v999.0.0", "v999.0.1"), ..signed.clone() };
+        let error = verify_manifest(&altered, public_key, now).unwrap_err();
+        assert!(error.to_string().contains("signature"), "{}", error);
+        // 埋め込んだ鍵以外で署名したマニフェスト
+        let forged = sign(&unsigned_manifest(b"evil agent binary"), &key_pair());
+        assert!(verify_manifest(&forged, public_key, now).is_err());
+        // 署名の形式が正しくないマニフェスト
+        for signature in ["", "not hex", &"00".repeat(64), &signed.signature[..64]] {
+            let broken = SignedManifest { signature: signature.to_string(), ..signed.clone() };
+            assert!(verify_manifest(&broken, public_key, now).is_err(), "{:?}", signature);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tampered_downloads_are_rejected() {
+        let key = key_pair();
+        let binary = b"new agent binary".to_vec();
+        let tampered = b"evil agent binary".to_vec();
+        let base = serve(|_| {
+            HashMap::from([
+                ("/manifest".to_string(), manifest(&binary, &key)),
+                ("/binaries/client".to_string(), tampered[..binary.len()].to_vec()),
+                ("/forged".to_string(), manifest(&tampered, &key_pair())),
+            ])
+        })
+        .await;
+        let dir = temp_dir();
+        let exe = dir.join("client");
+        fs::write(&exe, b"old agent binary").unwrap();
+
+        // 署名済みのチェックサム・サイズと一致しなければ適用しない
+        let genuine = updater(ReleaseSource::Manifest(base.join("manifest").unwrap()), &key, exe.clone());
+        let release = genuine.check().await.unwrap().unwrap();
+        let error = genuine.apply(&release).await.unwrap_err();
+        assert!(error.to_string().contains("Checksum"), "{}", error);
+        let error = verify(&tampered, &release).unwrap_err();
+        assert!(error.to_string().contains("Size"), "{}", error);
+        verify(&binary, &release).unwrap();
+
+        // 別の鍵で署名されたマニフェストは更新の確認の時点で拒否する
+        let forged = updater(ReleaseSource::Manifest(base.join("forged").unwrap()), &key, exe.clone());
+        assert!(forged.check().await.is_err());
+
+        assert_eq!(fs::read(&exe).unwrap(), b"old agent binary");
+        assert!(!backup_path(&exe).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pinned_public_key() {
+        let key = key_pair();
+        let text = format!("# comment\n\n{}\n", hex::encode(key.public_key()));
+        assert_eq!(pinned_public_key(&text).unwrap().as_deref(), Some(key.public_key().as_ref()));
+        assert_eq!(pinned_public_key("# no key yet\n").unwrap(), None);
+        assert!(pinned_public_key("abcd").is_err());
+        assert!(pinned_public_key("not hex").is_err());
+        // リリースの公開鍵を埋め込んでいること
+        assert!(pinned_public_key(PINNED_PUBLIC_KEY).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_github_release_source() {
+        let key = key_pair();
+        let binary = b"new agent binary".to_vec();
+        let name = format!("pc-status-client-{}{}", TARGET, env::consts::EXE_SUFFIX);
+        let base = serve(|base| {
+            let asset = |name: &str| {
+                serde_json::json!({
+                    "name": name,
+                    "browser_download_url": base.join(&format!("download/v999.1.0/{}", name)).unwrap().to_string(),
+                })
+            };
+            let release = serde_json::json!({
+                "tag_name": "v999.1.0",
+                "assets": [asset(&name), asset("manifest.json")],
+            });
+            let mut manifest = unsigned_manifest(&binary);
+            manifest.version = "v999.1.0".to_string();
+            manifest.assets[0].url = name.clone();
+            HashMap::from([
+                ("/api/v3/repos/owner/agent/releases/latest".to_string(), serde_json::to_vec(&release).unwrap()),
+                ("/download/v999.1.0/manifest.json".to_string(), serde_json::to_vec(&sign(&manifest, &key)).unwrap()),
+                (format!("/download/v999.1.0/{}", name), binary.clone()),
+            ])
+        })
+        .await;
+
+        // GitHub Enterpriseのようにパスを含むAPIのURL
+        let api = format!("{}api/v3", base);
+        let source = ReleaseSource::parse("github:owner/agent", Some(&api)).unwrap();
+        assert_eq!(source, ReleaseSource::GitHub { api: base.join("api/v3/").unwrap(), repo: "owner/agent".to_string() });
+        let updater = updater(source, &key, temp_dir().join("client"));
+        let release = updater.check().await.unwrap().expect("update available");
+        assert_eq!(release.version, "v999.1.0");
+        assert_eq!(release.url, base.join(&format!("download/v999.1.0/{}", name)).unwrap());
+        let downloaded = updater.download(&release).await.unwrap();
+        verify(&downloaded, &release).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_after_failed_starts() {
+        let dir = temp_dir();
+        let exe = dir.join("client");
+        fs::write(&exe, b"old agent binary").unwrap();
+        install(&exe, b"new agent binary", "v2.0.0").unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), b"new agent binary");
+
+        for starts in 1..=MAX_TRIAL_STARTS {
+            match start_trial(&exe).unwrap() {
+                TrialStart::Started(state) => assert_eq!(state.starts, starts),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(start_trial(&exe).unwrap(), TrialStart::Exhausted(_)));
+
+        rollback(&exe, "no connection").unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), b"old agent binary");
+        assert_eq!(failed_version(&exe).as_deref(), Some("v2.0.0"));
+        let report: UpdateReport = serde_json::from_slice(&fs::read(report_path(&exe)).unwrap()).unwrap();
+        assert_eq!(report.outcome, UpdateOutcome::RolledBack);
+        assert_eq!(report.version, "v2.0.0");
+        assert_eq!(start_trial(&exe).unwrap(), TrialStart::None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_offered_update_must_match_manifest() {
+        let key = key_pair();
+        let binary = b"new agent binary".to_vec();
+        let base = serve(|_| {
+            HashMap::from([
+                ("/agent/manifest.json".to_string(), manifest(&binary, &key)),
+                ("/agent/binaries/client".to_string(), binary.clone()),
+            ])
+        })
+        .await;
+        let server_url = format!("ws://{}:{}/ws", base.host_str().unwrap(), base.port().unwrap());
+        let manifest = resolve_manifest(&server_url, "/agent/manifest.json").unwrap();
+        assert_eq!(manifest, base.join("agent/manifest.json").unwrap());
+
+        let dir = temp_dir();
+        let updater = updater(ReleaseSource::Manifest(manifest.clone()), &key, dir.join("client"));
+        let offer = |version: &str| UpdateOffer { version: version.to_string(), manifest: manifest.to_string() };
+        let release = updater.offered_release(&offer("v999.0.0")).await.unwrap().expect("offered release");
+        assert_eq!(release.url, base.join("agent/binaries/client").unwrap());
+        // マニフェストが差し替えられていた場合は更新しない
+        let error = updater.offered_release(&offer("v998.0.0")).await.unwrap_err();
+        assert!(error.to_string().contains("expected v998.0.0"), "{}", error);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_expired_manifest_is_rejected() {
+        let key = key_pair();
+        let binary = b"old agent binary".to_vec();
+        let mut expired = unsigned_manifest(&binary);
+        expired.expires_at = Utc::now() - chrono::Duration::days(1);
+        let signed = sign(&expired, &key);
+        let error = verify_manifest(&signed, key.public_key().as_ref(), Utc::now()).unwrap_err();
+        assert!(error.to_string().contains("expired"), "{}", error);
+
+        // 期限切れの署名済みマニフェストをサーバーから指示されても更新しない
+        let base = serve(|_| HashMap::from([("/manifest.json".to_string(), serde_json::to_vec(&signed).unwrap())])).await;
+        let manifest = base.join("manifest.json").unwrap();
+        let dir = temp_dir();
+        let updater = updater(ReleaseSource::Manifest(manifest.clone()), &key, dir.join("client"));
+        let offer = UpdateOffer { version: "v999.0.0".to_string(), manifest: manifest.to_string() };
+        let error = updater.offered_release(&offer).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("expired"), "{:#}", error);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_older_versions_need_a_signed_rollback() {
+        let release = |version: &str, rollback: bool| Release {
+            version: version.to_string(),
+            url: Url::parse("https://example.com/client").unwrap(),
+            size: 0,
+            sha256: vec![],
+            rollback,
+        };
+        assert!(is_allowed("v1.2.0", &release("v1.3.0", false)));
+        // 古いバージョンの署名済みマニフェストを使い回しても戻さない
+        assert!(!is_allowed("v1.3.0", &release("v1.2.0", false)));
+        assert!(is_allowed("v1.3.0", &release("v1.2.0", true)));
+        assert!(!is_allowed("v1.3.0", &release("v1.3.0", true)));
+        // タグのないビルド（コミットハッシュやunknown）とは比べられないため、戻すマニフェストだけ適用する
+        assert!(!is_allowed("b799876-dirty", &release("v1.2.0", false)));
+        assert!(!is_allowed("unknown", &release("v1.2.0", false)));
+        assert!(is_allowed("b799876", &release("v1.2.0", true)));
+    }
+
+    #[test]
+    fn test_resolve_manifest() {
+        let url = resolve_manifest("wss://status.example.com/server", "/agent/manifest.json").unwrap();
+        assert_eq!(url.as_str(), "https://status.example.com/agent/manifest.json");
+        let url = resolve_manifest("ws://localhost:3000/ws", "https://cdn.example.com/manifest.json").unwrap();
+        assert_eq!(url.as_str(), "https://cdn.example.com/manifest.json");
+    }
+
+    #[test]
+    fn test_version_comparison() {
+        assert!(is_newer("v1.2.0", "v1.3.0"));
+        assert!(!is_newer("v1.3.0", "v1.2.0"));
+        assert!(!is_newer("v1.3.0", "v1.3.0"));
+        // semverでないバージョンはどちらが新しいか判断しない
+        assert!(!is_newer("b799876-dirty", "v1.3.0"));
+        assert!(!is_newer("unknown", "v1.3.0"));
+        assert!(!is_newer("v1.3.0", "b799876"));
+    }
+}
@@ -0,0 +1,28 @@
+import { ClientData, StatusDelta } from "../types/client"
+
+export function applyDelta(clients: ClientData, delta: StatusDelta): ClientData {
+    const next: ClientData = { ...clients }
+
+    for (const id of delta.removed ?? []) {
+        delete next[id]
+    }
+    for (const [id, status] of Object.entries(delta.added ?? {})) {
+        next[id] = status
+    }
+    for (const [id, hostDelta] of Object.entries(delta.updated ?? {})) {
+        const current = next[id]
+        if (!current) continue
+
+        const { histories_dropped, histories, ...fields } = hostDelta
+        next[id] = {
+            ...current,
+            ...fields,
+            histories: [
+                ...current.histories.slice(histories_dropped ?? 0),
+                ...(histories ?? []),
+            ],
+        } as typeof current
+    }
+
+    return next
+}
@@ -70,7 +70,14 @@ const Status = ({ children, status, pc, onFocusClick }: Props) => {
                         </div>
                     </div>
                     <h2 className="card-title flex justify-between">
-                        {pcData?.hostname}
+                        <span>
+                            {pcData?.hostname}
+                            {pcData?.index > 0 && (
+                                <span className="badge badge-ghost ml-2">
+                                    #{pcData.index}
+                                </span>
+                            )}
+                        </span>
                         <button
                             onClick={onFocusClick}
                             className="btn border-none bg-base-50 bg-transparent"
@@ -1,5 +1,6 @@
 import { useEffect, useState, useRef, useCallback } from 'react'
-import { ClientData } from '../types/client'
+import { Alert, ClientData, StatusDelta, StatusSnapshot } from '../types/client'
+import { applyDelta } from '../Utils/applyDelta'
 
 interface ToastData {
     message: string
@@ -12,6 +13,17 @@ interface UseWebSocketReturn {
     error: string | null
 }
 
+// ページのURLの?token=（テナントの閲覧者トークン）をWebSocketのURLに付ける
+const withViewerToken = (wsUrl: string): string => {
+    const token = new URLSearchParams(window.location.search).get('token')
+    if (!token) {
+        return wsUrl
+    }
+    const url = new URL(wsUrl)
+    url.searchParams.set('token', token)
+    return url.toString()
+}
+
 export const useWebSocket = (url: string): UseWebSocketReturn => {
     const [status, setStatus] = useState<ClientData | undefined>()
     const [connected, setConnected] = useState<boolean>(false)
@@ -23,6 +35,9 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
     const reconnectDelay = 2000  // 2秒に短縮
     const lastUpdateTime = useRef<number>(0)
     const updateCount = useRef<number>(0)
+    // 差分配信の状態（最後に適用したシーケンス番号と状態）
+    const deltaSeq = useRef<number | null>(null)
+    const clientsRef = useRef<ClientData>({})
 
     // デバッグ用：環境変数の確認
     console.log('Environment variables:', {
@@ -74,7 +89,7 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
 
             console.log('Connecting to WebSocket:', wsUrl)
             
-            const ws = new WebSocket(wsUrl)
+            const ws = new WebSocket(withViewerToken(wsUrl))
             wsRef.current = ws
 
             ws.onopen = () => {
@@ -82,6 +97,9 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
                 setConnected(true)
                 setError(null)
                 reconnectAttempts.current = 0
+                // 差分配信を購読（最初にスナップショットが届く）
+                deltaSeq.current = null
+                ws.send(JSON.stringify({ type: 'Subscribe' }))
             }
 
             ws.onmessage = (event) => {
@@ -98,6 +116,30 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
                             setStatus(data.data)
                             console.log('Status updated:', data.data)
                             break
+                        case 'Snapshot': {
+                            const snapshot: StatusSnapshot = data.data
+                            deltaSeq.current = snapshot.seq
+                            clientsRef.current = snapshot.clients
+                            setStatus(snapshot.clients)
+                            break
+                        }
+                        case 'Delta': {
+                            const delta: StatusDelta = data.data
+                            if (deltaSeq.current === null || delta.seq <= deltaSeq.current) {
+                                break
+                            }
+                            if (delta.seq !== deltaSeq.current + 1) {
+                                // 欠番があればスナップショットを再要求
+                                console.warn(`Delta gap (${deltaSeq.current} -> ${delta.seq}), resubscribing`)
+                                deltaSeq.current = null
+                                ws.send(JSON.stringify({ type: 'Subscribe' }))
+                                break
+                            }
+                            deltaSeq.current = delta.seq
+                            clientsRef.current = applyDelta(clientsRef.current, delta)
+                            setStatus(clientsRef.current)
+                            break
+                        }
                         case 'Toast':
                             // トーストメッセージをカスタムイベントとして発火
                             const toastEvent = new CustomEvent('websocket-toast', {
@@ -105,6 +147,18 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
                             })
                             window.dispatchEvent(toastEvent)
                             break
+                        case 'Alert': {
+                            // 発報・解消をトーストとして表示
+                            const alert: Alert = data.data
+                            window.dispatchEvent(new CustomEvent('websocket-toast', {
+                                detail: {
+                                    message: alert.message,
+                                    color: alert.state === 'firing' ? '#d008' : '#0508',
+                                    toast_time: 10000,
+                                }
+                            }))
+                            break
+                        }
                         case 'Close':
                             console.log('Server requested close')
                             ws.close()
@@ -112,6 +166,15 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
                         case 'Sync':
                             console.log('Sync message:', data.data)
                             break
+                        case 'Error': {
+                            // 認証エラー（トークンがない・正しくない）は再接続しても成功しないため再接続しない
+                            console.error('Server error:', data.data)
+                            if (data.data.code === 'auth_failed') {
+                                reconnectAttempts.current = maxReconnectAttempts
+                            }
+                            setError(data.data.message)
+                            break
+                        }
                         default:
                             console.log('Unknown message type:', data.type)
                     }
@@ -136,7 +199,7 @@ export const useWebSocket = (url: string): UseWebSocketReturn => {
                         connect()
                     }, reconnectDelay)
                 } else {
-                    setError('Failed to connect after multiple attempts')
+                    setError((current) => current ?? 'Failed to connect after multiple attempts')
                 }
             }
 
@@ -3,3 +3,38 @@ import { StatusData } from "./status"
 export interface ClientData {
     [key: string]: StatusData
 }
+
+export interface StatusSnapshot {
+    seq: number
+    clients: ClientData
+}
+
+export interface HostDelta {
+    cpu?: StatusData["cpu"]
+    ram?: StatusData["ram"]
+    swap?: StatusData["swap"]
+    storages?: StatusData["storages"]
+    uptime?: number
+    loadavg?: number[]
+    gpus?: StatusData["gpus"]
+    histories_dropped?: number
+    histories?: StatusData["histories"]
+}
+
+export interface StatusDelta {
+    seq: number
+    added?: ClientData
+    updated?: { [key: string]: HostDelta }
+    removed?: string[]
+}
+
+export interface Alert {
+    rule: string
+    host: string
+    hostname: string
+    state: 'pending' | 'firing' | 'resolved'
+    value: number
+    threshold: number
+    message: string
+    since: string
+}
@@ -58,5 +58,9 @@ export interface StatusData {
     loadavg: number[]
     gpus: GPU[]
     networks: NetWorkData[]
+    index: number
     histories: HistoriesData[]
+    tags?: string[]
+    labels?: Record<string, string>
+    tenant?: string
 }
@@ -0,0 +1,97 @@
+//! ブロードキャスト時のシリアライズコストの比較
+//!
+//! 閲覧者ごとにシリアライズする従来の方式と、`SharedMessage`でエンコード結果を
+//! 共有する方式を、50台分のステータスを閲覧者数を変えて比較する。
+//!
+//! ```bash
+//! cargo bench -p pc-status-server --bench broadcast
+//! ```
+
+use axum::extract::ws::Message;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use pc_status_shared::{
+    ClientData, Cpu, CpuData, HistoriesData, Ram, ServerMessage, StatusData, Storage, Swap, WireFormat,
+};
+use std::hint::black_box;
+
+#[allow(dead_code)]
+#[path = "../src/frame.rs"]
+mod frame;
+
+use frame::SharedMessage;
+
+fn status(index: usize) -> StatusData {
+    let cpu = Cpu {
+        model: "AMD Ryzen 9 7950X".to_string(),
+        cpus: (0..16).map(|core| CpuData { cpu: (core * index % 100) as f64 }).collect(),
+    };
+    let ram = Ram { free: 8 << 30, total: 32 << 30 };
+    let swap = Swap { free: 2 << 30, total: 4 << 30 };
+    let storages = vec![Storage {
+        name: Some("/dev/nvme0n1p2".to_string()),
+        free: 200 << 30,
+        total: 1 << 40,
+    }];
+    let history = HistoriesData {
+        cpu: cpu.clone(),
+        ram: ram.clone(),
+        swap: swap.clone(),
+        storages: storages.clone(),
+        gpus: vec![],
+        uptime: 3600,
+    };
+
+    StatusData {
+        dev: Some(false),
+        os: "Linux 6.8".to_string(),
+        hostname: format!("host-{}", index),
+        version: "Rust client v1.0.0".to_string(),
+        cpu,
+        ram,
+        swap,
+        storages,
+        uptime: 3600,
+        loadavg: [1.0, 1.5, 2.0],
+        histories: vec![history; 10],
+        ..Default::default()
+    }
+}
+
+fn status_message() -> ServerMessage {
+    let clients: ClientData = (0..50).map(|i| (format!("machine-{}", i), status(i))).collect();
+    ServerMessage::Status(clients)
+}
+
+fn bench_broadcast(c: &mut Criterion) {
+    let message = status_message();
+    let mut group = c.benchmark_group("broadcast_status");
+
+    for viewers in [10, 100, 500] {
+        group.bench_with_input(BenchmarkId::new("per_viewer", viewers), &viewers, |b, &viewers| {
+            b.iter(|| {
+                for _ in 0..viewers {
+                    let json = message.to_json().unwrap();
+                    black_box(Message::Text(json.into()));
+                }
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("shared", viewers), &viewers, |b, &viewers| {
+            b.iter_batched(
+                || message.clone(),
+                |message| {
+                    let shared = SharedMessage::new(message);
+                    for _ in 0..viewers {
+                        black_box(shared.frame(WireFormat::Json));
+                    }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_broadcast);
+criterion_main!(benches);
@@ -0,0 +1,424 @@
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use pc_status_shared::{CommandResult, Selector, ToastData};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, warn};
+
+use crate::audit::{AuditAction, AuditEvent, AuditFilter, AuditLog};
+use crate::bans::Ban;
+use crate::rate_limit::{too_many_requests, AuthGuard};
+use crate::releases::Releases;
+use crate::rollout::{agent_version, Rollout};
+use crate::tenants::secret_eq;
+use crate::websocket::{CommandError, SessionInfo, WebSocketServer};
+
+/// 通知の既定の表示時間（ミリ秒）
+const DEFAULT_TOAST_TIME: u32 = 5000;
+/// 通知の既定の色
+const DEFAULT_TOAST_COLOR: &str = "#0508";
+/// 監査ログの検索で返す既定の件数
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+/// 監査ログの検索で返す最大件数
+const MAX_AUDIT_LIMIT: usize = 10000;
+
+/// 管理APIの共有状態
+#[derive(Clone)]
+pub struct AdminState {
+    pub ws_server: WebSocketServer,
+    /// 管理APIのトークン（未設定の場合は管理APIを無効にする）
+    pub token: Option<Arc<str>>,
+    pub audit: Arc<AuditLog>,
+    /// 管理トークンの認証の締め出し（エージェント・閲覧者の認証とは別に数える）
+    pub auth_guard: Arc<AuthGuard>,
+    /// 配布するエージェントの実行ファイル（未設定の場合は配布しない）
+    pub releases: Option<Arc<Releases>>,
+}
+
+type AdminError = (StatusCode, String);
+
+/// 管理者として認証した要求（`Authorization: Bearer <ADMIN_TOKEN>`）
+pub struct Admin {
+    /// 監査ログに記録する接続元
+    remote: Option<IpAddr>,
+}
+
+impl FromRequestParts<AdminState> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AdminState) -> Result<Self, Self::Rejection> {
+        let Some(expected) = &state.token else {
+            return Err((StatusCode::FORBIDDEN, "Admin API is disabled").into_response());
+        };
+        let guard = &state.auth_guard;
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let remote = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        if let Some(remaining) = remote.and_then(|ip| guard.locked(ip, Instant::now())) {
+            return Err(too_many_requests(remaining));
+        }
+        if !token.is_some_and(|token| secret_eq(token, expected)) {
+            let event = AuditEvent::new(AuditAction::AuthFailure, remote).target(parts.uri.path()).detail("admin token");
+            guard.record_failure(&state.audit, event).await;
+            return Err((StatusCode::UNAUTHORIZED, "Invalid or missing admin token").into_response());
+        }
+        if let Some(ip) = remote {
+            guard.success(ip, Instant::now());
+        }
+        Ok(Self { remote })
+    }
+}
+
+impl Admin {
+    fn event(&self, action: AuditAction) -> AuditEvent {
+        AuditEvent::new(action, self.remote)
+    }
+}
+
+/// 禁止を追加したときの応答
+#[derive(Debug, Serialize)]
+pub struct BanResponse {
+    pub ban: Ban,
+    /// 追加により切断した接続
+    pub disconnected: Vec<SessionInfo>,
+}
+
+/// `POST /api/admin/toast`の本文
+#[derive(Debug, Deserialize)]
+pub struct ToastRequest {
+    message: String,
+    color: Option<String>,
+    /// 表示時間（ミリ秒）
+    toast_time: Option<u32>,
+}
+
+/// `POST /api/admin/agents/{id}/commands`の本文
+#[derive(Debug, Deserialize)]
+pub struct CommandBody {
+    /// エージェントの許可リストに登録されたコマンドの名前
+    command: String,
+}
+
+/// `PUT /api/admin/rollout`の本文（省略した項目は変更しない）
+#[derive(Debug, Default, Deserialize)]
+pub struct RolloutBody {
+    /// 先に更新するホストのセレクター（空文字列で解除）
+    canary: Option<String>,
+    /// カナリア以外で更新するホストの割合（0-100）
+    percent: Option<u8>,
+    paused: Option<bool>,
+}
+
+/// 配布の状況
+#[derive(Debug, Serialize)]
+pub struct RolloutResponse {
+    #[serde(flatten)]
+    rollout: Rollout,
+    /// 結果ごとのエージェント数（報告前は`pending`）
+    counts: BTreeMap<String, usize>,
+}
+
+impl From<Rollout> for RolloutResponse {
+    fn from(rollout: Rollout) -> Self {
+        let counts = rollout.counts();
+        Self { rollout, counts }
+    }
+}
+
+/// 同じバージョンで動いているホスト
+#[derive(Debug, Serialize)]
+pub struct VersionHosts {
+    version: String,
+    count: usize,
+    hosts: Vec<String>,
+}
+
+/// 接続中のエージェントのバージョンの分布
+#[derive(Debug, Serialize)]
+pub struct VersionsResponse {
+    /// 配布中のバージョン（配布していない場合はNone）
+    latest: Option<String>,
+    /// 配布中のバージョンで動いていないホストの数
+    outdated: usize,
+    /// ホストの多い順
+    versions: Vec<VersionHosts>,
+}
+
+/// `GET /api/admin/audit`のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// 期間（`1h`など）またはRFC 3339の時刻
+    since: Option<String>,
+    action: Option<AuditAction>,
+    remote: Option<IpAddr>,
+    limit: Option<usize>,
+}
+
+/// 接続中のセッションの一覧（エージェントと閲覧者）
+pub async fn list_sessions(State(state): State<AdminState>, _admin: Admin) -> Json<Vec<SessionInfo>> {
+    Json(state.ws_server.sessions().await)
+}
+
+/// 接続を切断する（エージェントは通常の再接続で戻るため、戻さない場合は禁止も追加する）
+pub async fn disconnect_session(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Path(id): Path<String>,
+) -> Result<Json<SessionInfo>, AdminError> {
+    let session = state
+        .ws_server
+        .disconnect(&id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Session not found: {}", id)))?;
+    let mut event = admin.event(AuditAction::AdminDisconnect).target(id);
+    if let Some(hostname) = &session.hostname {
+        event = event.detail(format!("hostname={}", hostname));
+    }
+    state.audit.record(event).await;
+    Ok(Json(session))
+}
+
+pub async fn list_bans(State(state): State<AdminState>, _admin: Admin) -> Json<Vec<Ban>> {
+    Json(state.ws_server.get_bans().list().await)
+}
+
+/// 接続を禁止し、該当する接続を切断する
+pub async fn add_ban(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Json(ban): Json<Ban>,
+) -> Result<(StatusCode, Json<BanResponse>), AdminError> {
+    ban.validate().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let ban = ban.normalize();
+    let added = state.ws_server.get_bans().add(ban.clone()).await;
+    let disconnected = state.ws_server.disconnect_banned().await;
+    let event = admin
+        .event(AuditAction::AdminBan)
+        .target(ban_target(&ban))
+        .detail(format!("disconnected={}", disconnected.len()));
+    state.audit.record(event).await;
+    let status = if added { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(BanResponse { ban, disconnected })))
+}
+
+/// 禁止を解除する
+pub async fn remove_ban(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Json(ban): Json<Ban>,
+) -> Result<StatusCode, AdminError> {
+    if !state.ws_server.get_bans().remove(ban.clone()).await {
+        return Err((StatusCode::NOT_FOUND, format!("Ban not found: {}", ban_target(&ban))));
+    }
+    state.audit.record(admin.event(AuditAction::AdminUnban).target(ban_target(&ban))).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// すべての閲覧者に通知を送る
+pub async fn send_toast(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Json(request): Json<ToastRequest>,
+) -> Result<StatusCode, AdminError> {
+    if request.message.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Message must not be empty".to_string()));
+    }
+    state.audit.record(admin.event(AuditAction::AdminToast).detail(request.message.clone())).await;
+    state.ws_server.broadcast_toast(ToastData {
+        message: request.message,
+        color: request.color.unwrap_or_else(|| DEFAULT_TOAST_COLOR.to_string()),
+        toast_time: request.toast_time.unwrap_or(DEFAULT_TOAST_TIME),
+    });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// エージェントに許可リストのコマンドを実行させ、結果を返す
+pub async fn run_command(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Path(agent_id): Path<String>,
+    Json(body): Json<CommandBody>,
+) -> Result<Json<CommandResult>, AdminError> {
+    if body.command.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Command must not be empty".to_string()));
+    }
+    state
+        .audit
+        .record(admin.event(AuditAction::AdminCommand).target(agent_id.as_str()).detail(format!("command={}", body.command)))
+        .await;
+    match state.ws_server.run_command(&agent_id, &body.command, admin.remote).await {
+        Ok(result) => Ok(Json(result)),
+        Err(e) => {
+            let status = match e {
+                CommandError::NotConnected(_) => StatusCode::NOT_FOUND,
+                CommandError::NotSupported(_) => StatusCode::CONFLICT,
+                CommandError::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+                CommandError::Disconnected(_) => StatusCode::BAD_GATEWAY,
+                CommandError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+            };
+            Err((status, e.to_string()))
+        }
+    }
+}
+
+/// 接続中のエージェントのバージョンの分布と、配布中のバージョンとの差
+pub async fn list_versions(State(state): State<AdminState>, _admin: Admin) -> Json<VersionsResponse> {
+    let latest = match &state.releases {
+        Some(releases) => match releases.manifest().await {
+            Ok(manifest) => Some(manifest.version),
+            Err(e) => {
+                warn!("{:#}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut by_version: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for status in state.ws_server.get_client_manager().get_all_clients().await.into_values() {
+        by_version.entry(agent_version(&status.version).to_string()).or_default().push(status.hostname);
+    }
+    let outdated = match &latest {
+        Some(latest) => by_version.iter().filter(|(version, _)| *version != latest).map(|(_, hosts)| hosts.len()).sum(),
+        None => 0,
+    };
+    let mut versions: Vec<VersionHosts> = by_version
+        .into_iter()
+        .map(|(version, mut hosts)| {
+            hosts.sort();
+            VersionHosts { version, count: hosts.len(), hosts }
+        })
+        .collect();
+    versions.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.version.cmp(&b.version)));
+    Json(VersionsResponse { latest, outdated, versions })
+}
+
+pub async fn get_rollout(State(state): State<AdminState>, _admin: Admin) -> Result<Json<RolloutResponse>, AdminError> {
+    let rollout = state
+        .ws_server
+        .rollout()
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "No rollout in progress".to_string()))?;
+    Ok(Json(rollout.into()))
+}
+
+/// 配布中のマニフェストのバージョンの配布を開始する（配布中の場合はカナリア・割合・一時停止を変更する）
+pub async fn update_rollout(
+    State(state): State<AdminState>,
+    admin: Admin,
+    Json(body): Json<RolloutBody>,
+) -> Result<Json<RolloutResponse>, AdminError> {
+    let Some(releases) = &state.releases else {
+        return Err((StatusCode::CONFLICT, "Agent releases are not configured".to_string()));
+    };
+    let manifest = releases.manifest().await.map_err(|e| {
+        error!("{:#}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the release manifest".to_string())
+    })?;
+    if body.percent.is_some_and(|percent| percent > 100) {
+        return Err((StatusCode::BAD_REQUEST, "percent must be between 0 and 100".to_string()));
+    }
+    let canary = body
+        .canary
+        .as_deref()
+        .map(|canary| canary.parse::<Selector>())
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid canary: {}", e)))?;
+
+    let now = Utc::now();
+    state
+        .ws_server
+        .modify_rollout(|current| match current {
+            Some(rollout) if rollout.version == manifest.version => {
+                if let Some(canary) = canary {
+                    rollout.canary = Some(canary).filter(|canary| !canary.is_empty());
+                }
+                if let Some(percent) = body.percent {
+                    rollout.percent = percent;
+                }
+                if let Some(paused) = body.paused {
+                    rollout.paused = paused;
+                    rollout.paused_reason = None;
+                }
+                rollout.updated_at = now;
+            }
+            // 配布中のマニフェストが差し替えられた場合は新しい配布にする
+            _ => {
+                let mut rollout = Rollout::new(manifest.version.clone(), canary, body.percent.unwrap_or(0), now);
+                rollout.paused = body.paused.unwrap_or(false);
+                *current = Some(rollout);
+            }
+        })
+        .await;
+    let rollout = state
+        .ws_server
+        .rollout()
+        .await
+        .ok_or_else(|| (StatusCode::CONFLICT, "Rollout was cancelled".to_string()))?;
+
+    let canary = rollout.canary.as_ref().map(|canary| canary.to_string()).unwrap_or_default();
+    let detail = format!("canary={} percent={} paused={}", canary, rollout.percent, rollout.paused);
+    state.audit.record(admin.event(AuditAction::AdminRollout).target(rollout.version.as_str()).detail(detail)).await;
+    Ok(Json(rollout.into()))
+}
+
+/// 配布を中止する（更新済みのエージェントはそのまま）
+pub async fn cancel_rollout(State(state): State<AdminState>, admin: Admin) -> Result<StatusCode, AdminError> {
+    let rollout = state
+        .ws_server
+        .modify_rollout(Option::take)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "No rollout in progress".to_string()))?;
+    state
+        .audit
+        .record(admin.event(AuditAction::AdminRollout).target(rollout.version.as_str()).detail("cancelled"))
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 監査ログを検索する（古い順、新しいものから最大`limit`件）
+pub async fn list_audit(
+    State(state): State<AdminState>,
+    _admin: Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, AdminError> {
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            pc_status_shared::parse_since(since, Utc::now())
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Invalid since: {}", since)))
+        })
+        .transpose()?;
+    let filter = AuditFilter {
+        since,
+        action: query.action,
+        remote: query.remote,
+        limit: query.limit.unwrap_or(DEFAULT_AUDIT_LIMIT).min(MAX_AUDIT_LIMIT),
+    };
+    match state.audit.query(&filter).await {
+        Ok(events) => Ok(Json(events)),
+        Err(e) => {
+            error!("Failed to read audit log: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read audit log".to_string()))
+        }
+    }
+}
+
+fn ban_target(ban: &Ban) -> String {
+    match ban {
+        Ban::Hostname(hostname) => format!("hostname={}", hostname),
+        Ban::Ip(ip) => format!("ip={}", ip),
+    }
+}
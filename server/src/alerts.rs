@@ -0,0 +1,484 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use pc_status_shared::{glob_match, Alert, AlertState, ClientData, StatusData};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// 解消したアラートを一覧に残す秒数
+const RESOLVED_RETENTION_SECS: i64 = 300;
+/// 切断したホストを追跡し続ける秒数
+const FORGET_OFFLINE_SECS: i64 = 86400;
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// ルールで評価する値
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// CPU使用率の平均（%）
+    CpuUsage,
+    /// メモリ使用率（%）
+    RamUsedPercent,
+    /// スワップ使用率（%）
+    SwapUsedPercent,
+    /// 最も使用率の高いストレージの使用率（%）
+    DiskUsedPercent,
+    /// 最も空きの少ないストレージの空き容量（GB）
+    DiskFreeGb,
+    /// 最も使用率の高いGPUの使用率（%）
+    GpuUsage,
+    /// 最も使用率の高いGPUのメモリ使用率（%）
+    GpuMemoryUsedPercent,
+    /// 1分間のロードアベレージ
+    LoadAverage,
+    /// 切断してからの秒数（接続中は0）
+    OfflineSecs,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Self::CpuUsage => "cpu_usage",
+            Self::RamUsedPercent => "ram_used_percent",
+            Self::SwapUsedPercent => "swap_used_percent",
+            Self::DiskUsedPercent => "disk_used_percent",
+            Self::DiskFreeGb => "disk_free_gb",
+            Self::GpuUsage => "gpu_usage",
+            Self::GpuMemoryUsedPercent => "gpu_memory_used_percent",
+            Self::LoadAverage => "load_average",
+            Self::OfflineSecs => "offline_secs",
+        }
+    }
+
+    /// 接続中のホストの値（対象のデバイスがない場合はNone）
+    fn value(self, status: &StatusData) -> Option<f64> {
+        match self {
+            Self::CpuUsage => {
+                let cpus = &status.cpu.cpus;
+                (!cpus.is_empty()).then(|| cpus.iter().map(|cpu| cpu.cpu).sum::<f64>() / cpus.len() as f64)
+            }
+            Self::RamUsedPercent => used_percent(status.ram.free, status.ram.total),
+            Self::SwapUsedPercent => used_percent(status.swap.free, status.swap.total),
+            Self::DiskUsedPercent => status
+                .storages
+                .iter()
+                .filter_map(|storage| used_percent(storage.free, storage.total))
+                .reduce(f64::max),
+            Self::DiskFreeGb => status
+                .storages
+                .iter()
+                .filter(|storage| storage.total > 0)
+                .map(|storage| storage.free as f64 / GIB)
+                .reduce(f64::min),
+            Self::GpuUsage => status.gpus.iter().map(|gpu| gpu.usage).reduce(f64::max),
+            Self::GpuMemoryUsedPercent => status
+                .gpus
+                .iter()
+                .filter_map(|gpu| used_percent(gpu.memory.free, gpu.memory.total))
+                .reduce(f64::max),
+            Self::LoadAverage => Some(status.loadavg[0]),
+            Self::OfflineSecs => Some(0.0),
+        }
+    }
+}
+
+fn used_percent(free: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| total.saturating_sub(free) as f64 / total as f64 * 100.0)
+}
+
+/// 閾値との比較方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = "<")]
+    Below,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Above => ">",
+            Self::Below => "<",
+        }
+    }
+
+    fn breached(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::Below => value < threshold,
+        }
+    }
+}
+
+/// アラートルール
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: Metric,
+    pub op: Comparison,
+    pub threshold: f64,
+    /// 発報までに条件が継続すべき秒数
+    #[serde(default)]
+    pub for_secs: u64,
+    /// 解消とみなす閾値（未指定の場合は`threshold`）
+    ///
+    /// `threshold`より条件を満たしにくい側に置くと、閾値付近で値が揺れても
+    /// 発報と解消を繰り返さない。
+    #[serde(default)]
+    pub clear_threshold: Option<f64>,
+    /// 対象のホスト名（`*`をワイルドカードとして使用可能、空の場合はすべて）
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// 対象のホストが持つべきタグ（いずれかを持っていれば対象）
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 対象のホストが持つべきラベル（すべて一致すれば対象、値に`*`を使用可能）
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// メール通知の宛先（未指定の場合は`SMTP_TO`）
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+impl AlertRule {
+    fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("Alert rule name must not be empty");
+        }
+        if let Some(clear) = self.clear_threshold
+            && self.op.breached(clear, self.threshold)
+        {
+            bail!(
+                "Alert rule {}: clear_threshold {} must not satisfy the condition {} {}",
+                self.name, clear, self.op.symbol(), self.threshold
+            );
+        }
+        Ok(())
+    }
+
+    fn cleared(&self, value: f64) -> bool {
+        !self.op.breached(value, self.clear_threshold.unwrap_or(self.threshold))
+    }
+
+    fn matches(&self, host: &HostRecord) -> bool {
+        let host_matches =
+            self.hosts.is_empty() || self.hosts.iter().any(|pattern| glob_match(pattern, &host.hostname));
+        let tag_matches = self.tags.is_empty() || self.tags.iter().any(|tag| host.tags.contains(tag));
+        let label_matches = self.labels.iter().all(|(key, pattern)| {
+            host.labels.get(key).is_some_and(|value| glob_match(pattern, value))
+        });
+        host_matches && tag_matches && label_matches
+    }
+
+    fn describe(&self, hostname: &str, value: f64) -> String {
+        format!(
+            "{}: {} ({} {:.1} {} {})",
+            hostname, self.name, self.metric.name(), value, self.op.symbol(), self.threshold
+        )
+    }
+}
+
+/// JSONファイルからルールを読み込む
+pub fn load_rules(path: &Path) -> Result<Vec<AlertRule>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read alert rules from {}", path.display()))?;
+    let rules: Vec<AlertRule> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse alert rules in {}", path.display()))?;
+
+    let mut names = HashSet::new();
+    for rule in &rules {
+        rule.validate()?;
+        if !names.insert(rule.name.as_str()) {
+            bail!("Duplicate alert rule name: {}", rule.name);
+        }
+    }
+    Ok(rules)
+}
+
+struct HostRecord {
+    hostname: String,
+    tags: Vec<String>,
+    labels: BTreeMap<String, String>,
+    tenant: String,
+    last_seen: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct EngineState {
+    hosts: HashMap<String, HostRecord>,
+    /// キーは(ルール名, エージェントID)
+    alerts: HashMap<(String, String), Alert>,
+}
+
+/// 受信した状態にアラートルールを適用する
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    state: Mutex<EngineState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> Arc<Self> {
+        Arc::new(Self {
+            rules,
+            state: Mutex::new(EngineState::default()),
+        })
+    }
+
+    /// 現在の状態でルールを評価し、発報・解消したアラートを返す
+    pub async fn evaluate(&self, clients: &ClientData, now: DateTime<Utc>) -> Vec<Alert> {
+        if self.rules.is_empty() {
+            return vec![];
+        }
+
+        let mut state = self.state.lock().await;
+        let EngineState { hosts, alerts } = &mut *state;
+
+        for (id, status) in clients {
+            hosts.insert(
+                id.clone(),
+                HostRecord {
+                    hostname: status.hostname.clone(),
+                    tags: status.tags.clone(),
+                    labels: status.labels.clone(),
+                    tenant: status.tenant.clone(),
+                    last_seen: now,
+                },
+            );
+        }
+        hosts.retain(|_, host| (now - host.last_seen).num_seconds() < FORGET_OFFLINE_SECS);
+
+        let mut events = Vec::new();
+        for rule in &self.rules {
+            for (id, host) in hosts.iter() {
+                if !rule.matches(host) {
+                    continue;
+                }
+
+                let value = match clients.get(id) {
+                    Some(status) => rule.metric.value(status),
+                    None if rule.metric == Metric::OfflineSecs => {
+                        Some((now - host.last_seen).num_milliseconds() as f64 / 1000.0)
+                    }
+                    // 切断中は他の値が分からないため状態を維持する
+                    None => None,
+                };
+                let Some(value) = value else {
+                    continue;
+                };
+
+                let key = (rule.name.clone(), id.clone());
+                let breached = rule.op.breached(value, rule.threshold);
+                match alerts.get_mut(&key) {
+                    Some(alert) if alert.state == AlertState::Firing => {
+                        alert.value = value;
+                        if rule.cleared(value) {
+                            alert.state = AlertState::Resolved;
+                            alert.since = now;
+                            alert.message = format!("{} resolved", rule.describe(&host.hostname, value));
+                            info!("Alert resolved: {}", alert.message);
+                            events.push(alert.clone());
+                        } else {
+                            alert.message = rule.describe(&host.hostname, value);
+                        }
+                    }
+                    Some(alert) if alert.state == AlertState::Pending => {
+                        if !breached {
+                            alerts.remove(&key);
+                            continue;
+                        }
+                        alert.value = value;
+                        alert.message = rule.describe(&host.hostname, value);
+                        if (now - alert.since).num_seconds() >= rule.for_secs as i64 {
+                            alert.state = AlertState::Firing;
+                            alert.since = now;
+                            info!("Alert firing: {}", alert.message);
+                            events.push(alert.clone());
+                        }
+                    }
+                    _ if breached => {
+                        let alert = Alert {
+                            rule: rule.name.clone(),
+                            host: id.clone(),
+                            hostname: host.hostname.clone(),
+                            tenant: host.tenant.clone(),
+                            state: if rule.for_secs == 0 { AlertState::Firing } else { AlertState::Pending },
+                            value,
+                            threshold: rule.threshold,
+                            message: rule.describe(&host.hostname, value),
+                            since: now,
+                        };
+                        if alert.state == AlertState::Firing {
+                            info!("Alert firing: {}", alert.message);
+                            events.push(alert.clone());
+                        }
+                        alerts.insert(key, alert);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        alerts.retain(|(_, host), alert| {
+            hosts.contains_key(host)
+                && (alert.state != AlertState::Resolved || (now - alert.since).num_seconds() < RESOLVED_RETENTION_SECS)
+        });
+
+        events
+    }
+
+    /// 評価中・発報中・最近解消したアラートの一覧
+    pub async fn alerts(&self) -> Vec<Alert> {
+        let state = self.state.lock().await;
+        let mut alerts: Vec<Alert> = state.alerts.values().cloned().collect();
+        alerts.sort_by(|a, b| {
+            state_rank(a.state)
+                .cmp(&state_rank(b.state))
+                .then_with(|| a.rule.cmp(&b.rule))
+                .then_with(|| a.hostname.cmp(&b.hostname))
+        });
+        alerts
+    }
+}
+
+fn state_rank(state: AlertState) -> u8 {
+    match state {
+        AlertState::Firing => 0,
+        AlertState::Pending => 1,
+        AlertState::Resolved => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use pc_status_shared::{test_support, Ram};
+
+    fn status(hostname: &str, ram_used_percent: u64) -> StatusData {
+        StatusData { ram: Ram { free: 100 - ram_used_percent, total: 100 }, ..test_support::status(hostname) }
+    }
+
+    fn rule(json: &str) -> AlertRule {
+        let rule: AlertRule = serde_json::from_str(json).unwrap();
+        rule.validate().unwrap();
+        rule
+    }
+
+    fn clients(entries: &[(&str, StatusData)]) -> ClientData {
+        entries.iter().map(|(id, status)| (id.to_string(), status.clone())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_pending_then_firing_then_resolved() {
+        let engine = AlertEngine::new(vec![rule(
+            r#"{"name": "ram", "metric": "ram_used_percent", "op": ">", "threshold": 90, "for_secs": 300, "clear_threshold": 85}"#,
+        )]);
+        let start = Utc::now();
+
+        assert!(engine.evaluate(&clients(&[("a", status("web", 95))]), start).await.is_empty());
+        assert_eq!(engine.alerts().await[0].state, AlertState::Pending);
+
+        let events = engine.evaluate(&clients(&[("a", status("web", 95))]), start + TimeDelta::seconds(300)).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+
+        // 閾値を下回っても解消の閾値までは発報を続ける
+        let events = engine.evaluate(&clients(&[("a", status("web", 88))]), start + TimeDelta::seconds(310)).await;
+        assert!(events.is_empty());
+        assert_eq!(engine.alerts().await[0].state, AlertState::Firing);
+
+        let events = engine.evaluate(&clients(&[("a", status("web", 80))]), start + TimeDelta::seconds(320)).await;
+        assert_eq!(events[0].state, AlertState::Resolved);
+    }
+
+    #[tokio::test]
+    async fn test_pending_is_dropped_when_condition_clears() {
+        let engine = AlertEngine::new(vec![rule(
+            r#"{"name": "ram", "metric": "ram_used_percent", "op": ">", "threshold": 90, "for_secs": 60}"#,
+        )]);
+        let start = Utc::now();
+
+        engine.evaluate(&clients(&[("a", status("web", 95))]), start).await;
+        engine.evaluate(&clients(&[("a", status("web", 50))]), start + TimeDelta::seconds(30)).await;
+        let events = engine.evaluate(&clients(&[("a", status("web", 95))]), start + TimeDelta::seconds(60)).await;
+
+        assert!(events.is_empty());
+        assert_eq!(engine.alerts().await[0].state, AlertState::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_offline_host() {
+        let engine = AlertEngine::new(vec![rule(
+            r#"{"name": "offline", "metric": "offline_secs", "op": ">", "threshold": 120}"#,
+        )]);
+        let start = Utc::now();
+
+        engine.evaluate(&clients(&[("a", status("web", 0))]), start).await;
+        assert!(engine.evaluate(&ClientData::new(), start + TimeDelta::seconds(60)).await.is_empty());
+
+        let events = engine.evaluate(&ClientData::new(), start + TimeDelta::seconds(121)).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].hostname, "web");
+
+        let events = engine.evaluate(&clients(&[("a", status("web", 0))]), start + TimeDelta::seconds(130)).await;
+        assert_eq!(events[0].state, AlertState::Resolved);
+    }
+
+    #[tokio::test]
+    async fn test_host_selector() {
+        let engine = AlertEngine::new(vec![rule(
+            r#"{"name": "ram", "metric": "ram_used_percent", "op": ">", "threshold": 90, "hosts": ["web-*"]}"#,
+        )]);
+
+        let events = engine
+            .evaluate(&clients(&[("a", status("WEB-1", 95)), ("b", status("db-1", 95))]), Utc::now())
+            .await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].host, "a");
+    }
+
+    #[tokio::test]
+    async fn test_tag_and_label_selector() {
+        let engine = AlertEngine::new(vec![rule(
+            r#"{"name": "ram", "metric": "ram_used_percent", "op": ">", "threshold": 90, "tags": ["gpu", "ci"], "labels": {"site": "tok*"}}"#,
+        )]);
+        let tagged = |hostname: &str, tag: &str, site: &str| {
+            let mut status = status(hostname, 95);
+            status.tags = vec![tag.to_string()];
+            status.labels.insert("site".to_string(), site.to_string());
+            status
+        };
+
+        let events = engine
+            .evaluate(
+                &clients(&[("a", tagged("a", "ci", "tokyo")), ("b", tagged("b", "web", "tokyo")), ("c", tagged("c", "gpu", "osaka"))]),
+                Utc::now(),
+            )
+            .await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].host, "a");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("web-*", "web-01"));
+        assert!(glob_match("*-01", "web-01"));
+        assert!(glob_match("w*b*1", "web-01"));
+        assert!(glob_match("web", "WEB"));
+        assert!(!glob_match("web", "web2"));
+        assert!(!glob_match("db-*", "web-01"));
+    }
+
+    #[test]
+    fn test_clear_threshold_must_be_on_the_clear_side() {
+        let rule: AlertRule = serde_json::from_str(
+            r#"{"name": "disk", "metric": "disk_free_gb", "op": "<", "threshold": 10, "clear_threshold": 5}"#,
+        )
+        .unwrap();
+        assert!(rule.validate().is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use axum::{
+    body::Body,
+    extract::{FromRef, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use pc_status_shared::{Alert, HistorySample, HostInfo, Selector};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::alerts::AlertEngine;
+use crate::audit::AuditLog;
+use crate::client_manager::ClientManager;
+use crate::export::{self, ExportFormat};
+use crate::history::{host_matches, HistoryStore};
+use crate::rate_limit::AuthGuard;
+use crate::tenants::{Tenants, Viewer};
+
+/// REST APIの共有状態
+#[derive(Clone)]
+pub struct ApiState {
+    pub client_manager: Arc<ClientManager>,
+    pub history: Arc<HistoryStore>,
+    pub alert_engine: Arc<AlertEngine>,
+    pub tenants: Arc<Tenants>,
+    pub audit: Arc<AuditLog>,
+    pub auth_guard: Arc<AuthGuard>,
+}
+
+impl FromRef<ApiState> for Arc<Tenants> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.tenants.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<AuditLog> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.audit.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<AuthGuard> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.auth_guard.clone()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, String)>;
+
+/// `/api/hosts`のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+pub struct HostsQuery {
+    /// タグ・ラベルのセレクター（`gpu,role=ci`など）
+    selector: Option<String>,
+}
+
+/// `/api/hosts/{host}/history`のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// 期間（`1h`など）またはRFC 3339の時刻（未指定は保持しているすべて）
+    since: Option<String>,
+}
+
+/// `/api/hosts/{host}/export`のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    /// 開始（期間またはRFC 3339の時刻、未指定は保存しているすべて）
+    from: Option<String>,
+    /// 終了（期間またはRFC 3339の時刻、未指定は現在）
+    to: Option<String>,
+    /// カンマ区切りの列名
+    columns: Option<String>,
+    /// csvまたはndjson（未指定はcsv）
+    format: Option<String>,
+}
+
+fn bad_request(message: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message)
+}
+
+/// 期間またはRFC 3339の時刻を読む（未指定はdefault）
+fn parse_time(
+    name: &str,
+    value: Option<&str>,
+    now: DateTime<Utc>,
+    default: DateTime<Utc>,
+) -> Result<DateTime<Utc>, (StatusCode, String)> {
+    match value {
+        Some(value) => pc_status_shared::parse_since(value, now)
+            .ok_or_else(|| bad_request(format!("Invalid {}: {}", name, value))),
+        None => Ok(default),
+    }
+}
+
+fn not_found(host: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("Host not found: {}", host))
+}
+
+/// 接続中のホストの一覧（ホスト名順）
+pub async fn list_hosts(
+    State(state): State<ApiState>,
+    Viewer(scope): Viewer,
+    Query(query): Query<HostsQuery>,
+) -> ApiResult<Vec<HostInfo>> {
+    let selector: Selector = query.selector.as_deref().unwrap_or_default().parse().map_err(bad_request)?;
+    let mut hosts: Vec<HostInfo> = state
+        .client_manager
+        .get_all_clients()
+        .await
+        .into_iter()
+        .filter(|(_, status)| scope.allows(&status.tenant) && selector.matches(status))
+        .map(|(id, status)| HostInfo { id, status })
+        .collect();
+    hosts.sort_by(|a, b| {
+        a.status
+            .hostname
+            .to_lowercase()
+            .cmp(&b.status.hostname.to_lowercase())
+            .then(a.status.index.cmp(&b.status.index))
+    });
+    Ok(Json(hosts))
+}
+
+/// 接続中のホスト1台の状態
+pub async fn get_host(
+    State(state): State<ApiState>,
+    Viewer(scope): Viewer,
+    Path(host): Path<String>,
+) -> ApiResult<HostInfo> {
+    state
+        .client_manager
+        .get_all_clients()
+        .await
+        .into_iter()
+        .find(|(id, status)| scope.allows(&status.tenant) && host_matches(&host, id, &status.hostname, status.index))
+        .map(|(id, status)| Json(HostInfo { id, status }))
+        .ok_or_else(|| not_found(&host))
+}
+
+/// 評価中・発報中・最近解消したアラートの一覧
+pub async fn list_alerts(State(state): State<ApiState>, Viewer(scope): Viewer) -> Json<Vec<Alert>> {
+    let alerts = state.alert_engine.alerts().await;
+    Json(alerts.into_iter().filter(|alert| scope.allows(&alert.tenant)).collect())
+}
+
+/// ホストの時刻付き履歴（切断中のホストも保持期間内であれば返す）
+pub async fn get_history(
+    State(state): State<ApiState>,
+    Viewer(scope): Viewer,
+    Path(host): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult<Vec<HistorySample>> {
+    let since = parse_time("since", query.since.as_deref(), Utc::now(), DateTime::<Utc>::MIN_UTC)?;
+    state.history.samples(&host, since, &scope).await.map(Json).ok_or_else(|| not_found(&host))
+}
+
+/// ホストの履歴を指定した列・形式で書き出す
+///
+/// 1行ずつ変換しながら送るため、範囲が広くてもレスポンス全体をメモリに載せない。
+pub async fn export_history(
+    State(state): State<ApiState>,
+    Viewer(scope): Viewer,
+    Path(host): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let now = Utc::now();
+    let from = parse_time("from", query.from.as_deref(), now, DateTime::<Utc>::MIN_UTC)?;
+    let to = parse_time("to", query.to.as_deref(), now, now)?;
+    let columns = export::parse_columns(query.columns.as_deref()).map_err(bad_request)?;
+    let format = match query.format.as_deref() {
+        Some(format) => format.parse::<ExportFormat>().map_err(bad_request)?,
+        None => ExportFormat::Csv,
+    };
+
+    let samples = match state.history.export(&host, from, to, &scope).await {
+        Ok(Some(samples)) => samples,
+        Ok(None) => return Err(not_found(&host)),
+        Err(e) => {
+            error!("Failed to read history of {}: {}", host, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to read history".to_string()));
+        }
+    };
+
+    let body = export::encode(samples, columns, format).map(Ok::<_, Infallible>);
+    Ok(([(header::CONTENT_TYPE, format.content_type())], Body::from_stream(body)).into_response())
+}
@@ -0,0 +1,262 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{self, Stream};
+use pc_status_shared::{ClientData, HistorySample};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, Lines};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::history::host_matches;
+use crate::tenants::{ViewerScope, DEFAULT_TENANT};
+
+/// ホストのディレクトリに置くホスト名の情報
+const META_FILE: &str = "meta.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HostMeta {
+    hostname: String,
+    index: u32,
+    /// テナントを導入する前に保存したホストは`default`
+    #[serde(default = "default_tenant")]
+    tenant: String,
+}
+
+fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// 書き込み中の日ごとのファイル
+struct DayFile {
+    date: NaiveDate,
+    writer: BufWriter<File>,
+}
+
+/// 履歴をエージェントごと・日ごと（UTC）のNDJSONファイルに保存する
+///
+/// `{dir}/{エージェントID}/{YYYY-MM-DD}.ndjson`に1行1サンプルで追記し、
+/// `keep_days`日より古いファイルは日付が変わったときに削除する。
+pub struct HistoryArchive {
+    dir: PathBuf,
+    keep_days: u32,
+    files: Mutex<HashMap<String, DayFile>>,
+    pruned: Mutex<Option<NaiveDate>>,
+}
+
+impl HistoryArchive {
+    pub fn new(dir: PathBuf, keep_days: u32) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            keep_days,
+            files: Mutex::new(HashMap::new()),
+            pruned: Mutex::new(None),
+        })
+    }
+
+    /// 接続中の全エージェントのサンプルを追記する
+    pub async fn append(&self, clients: &ClientData, now: DateTime<Utc>) -> io::Result<()> {
+        let today = now.date_naive();
+        self.prune(today).await?;
+
+        let mut files = self.files.lock().await;
+        files.retain(|id, _| clients.contains_key(id));
+
+        for (id, status) in clients {
+            let sample = HistorySample::new(now, status);
+            let mut line = serde_json::to_string(&sample).map_err(io::Error::other)?;
+            line.push('\n');
+
+            let file = match files.get_mut(id) {
+                Some(file) if file.date == today => file,
+                _ => {
+                    let meta = HostMeta {
+                        hostname: status.hostname.clone(),
+                        index: status.index,
+                        tenant: status.tenant.clone(),
+                    };
+                    let file = self.open(id, today, &meta).await?;
+                    files.insert(id.clone(), file);
+                    files.get_mut(id).expect("inserted above")
+                }
+            };
+            file.writer.write_all(line.as_bytes()).await?;
+            file.writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn open(&self, id: &str, date: NaiveDate, meta: &HostMeta) -> io::Result<DayFile> {
+        let host_dir = self.dir.join(id);
+        fs::create_dir_all(&host_dir).await?;
+        fs::write(host_dir.join(META_FILE), serde_json::to_vec(meta).map_err(io::Error::other)?).await?;
+
+        let path = host_dir.join(format!("{}.ndjson", date));
+        debug!("Archiving history to {}", path.display());
+        let file = OpenOptions::new().create(true).append(true).open(&path).await?;
+        Ok(DayFile { date, writer: BufWriter::new(file) })
+    }
+
+    /// 保存期間を過ぎたファイルを1日1回削除する
+    async fn prune(&self, today: NaiveDate) -> io::Result<()> {
+        let mut pruned = self.pruned.lock().await;
+        if *pruned == Some(today) {
+            return Ok(());
+        }
+        *pruned = Some(today);
+
+        let oldest = today - chrono::Days::new(self.keep_days.into());
+        let mut hosts = fs::read_dir(&self.dir).await?;
+        while let Some(host) = hosts.next_entry().await? {
+            let mut remaining = 0;
+            for (date, path) in day_files(&host.path())? {
+                if date < oldest {
+                    info!("Removing expired history file: {}", path.display());
+                    fs::remove_file(&path).await?;
+                } else {
+                    remaining += 1;
+                }
+            }
+            if remaining == 0 {
+                fs::remove_dir_all(host.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `scope`で閲覧できる保存済みのホストのIDを探す
+    pub async fn find_host(&self, host: &str, scope: &ViewerScope) -> Option<String> {
+        let mut hosts = fs::read_dir(&self.dir).await.ok()?;
+        while let Ok(Some(entry)) = hosts.next_entry().await {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let Ok(meta) = fs::read(entry.path().join(META_FILE)).await else {
+                continue;
+            };
+            match serde_json::from_slice::<HostMeta>(&meta) {
+                Ok(meta) if scope.allows(&meta.tenant) && host_matches(host, &id, &meta.hostname, meta.index) => {
+                    return Some(id);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Invalid history metadata for {}: {}", id, e),
+            }
+        }
+        None
+    }
+
+    /// `from`から`to`までのサンプルを古い順に読む
+    ///
+    /// ファイルを1行ずつ読むため、範囲が広くてもメモリに全体を載せない。
+    pub fn samples(
+        &self,
+        id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> io::Result<impl Stream<Item = HistorySample> + Send + 'static> {
+        let files: VecDeque<PathBuf> = day_files(&self.dir.join(id))?
+            .into_iter()
+            .filter(|(date, _)| *date >= from.date_naive() && *date <= to.date_naive())
+            .map(|(_, path)| path)
+            .collect();
+
+        let state: (VecDeque<PathBuf>, Option<Lines<BufReader<File>>>) = (files, None);
+        Ok(stream::unfold(state, move |(mut files, mut lines)| async move {
+            loop {
+                if let Some(reader) = lines.as_mut() {
+                    match reader.next_line().await {
+                        Ok(Some(line)) => match serde_json::from_str::<HistorySample>(&line) {
+                            Ok(sample) if sample.time >= from && sample.time <= to => {
+                                return Some((sample, (files, lines)));
+                            }
+                            Ok(_) => continue,
+                            // 書き込み途中の行は読み飛ばす
+                            Err(e) => {
+                                debug!("Skipping invalid history line: {}", e);
+                                continue;
+                            }
+                        },
+                        Ok(None) => lines = None,
+                        Err(e) => {
+                            warn!("Failed to read history file: {}", e);
+                            lines = None;
+                        }
+                    }
+                }
+
+                let path = files.pop_front()?;
+                match File::open(&path).await {
+                    Ok(file) => lines = Some(BufReader::new(file).lines()),
+                    Err(e) => warn!("Failed to open {}: {}", path.display(), e),
+                }
+            }
+        }))
+    }
+}
+
+/// ホストのディレクトリにある日ごとのファイル（日付順）
+fn day_files(host_dir: &Path) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+    let entries = match std::fs::read_dir(host_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut files: Vec<(NaiveDate, PathBuf)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let date = path.file_name()?.to_str()?.strip_suffix(".ndjson")?.parse().ok()?;
+            Some((date, path))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use futures::StreamExt;
+    use pc_status_shared::test_support::status;
+    use pc_status_shared::{Cpu, CpuData, StatusData};
+
+    fn clients(cpu: f64) -> ClientData {
+        let status = StatusData { cpu: Cpu { model: "cpu".to_string(), cpus: vec![CpuData { cpu }] }, ..status("web") };
+        [("machine-1".to_string(), status)].into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn test_append_read_and_prune() {
+        let dir = std::env::temp_dir().join(format!("pc-status-archive-{}", uuid::Uuid::new_v4()));
+        let archive = HistoryArchive::new(dir.clone(), 1).unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 23, 59, 58).unwrap();
+
+        for (seconds, cpu) in [(0, 10.0), (1, 20.0), (2, 30.0), (3, 40.0)] {
+            archive.append(&clients(cpu), start + Duration::seconds(seconds)).await.unwrap();
+        }
+        assert_eq!(archive.find_host("WEB", &ViewerScope::All).await.as_deref(), Some("machine-1"));
+        assert_eq!(archive.find_host("db", &ViewerScope::All).await, None);
+        assert_eq!(archive.find_host("web", &ViewerScope::none()).await, None);
+
+        // 日をまたいだ範囲を1つのストリームとして読める
+        let cpus: Vec<f64> = archive
+            .samples("machine-1", start + Duration::seconds(1), start + Duration::seconds(2))
+            .unwrap()
+            .map(|sample| sample.data.cpu.cpus[0].cpu)
+            .collect()
+            .await;
+        assert_eq!(cpus, vec![20.0, 30.0]);
+
+        // 保存期間（1日）を過ぎた日のファイルを削除する
+        archive.append(&clients(50.0), start + Duration::days(2)).await.unwrap();
+        let dates: Vec<String> = day_files(&dir.join("machine-1"))
+            .unwrap()
+            .into_iter()
+            .map(|(date, _)| date.to_string())
+            .collect();
+        assert_eq!(dates, vec!["2024-01-02", "2024-01-03"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// ファイルに保存しない場合にメモリ上に残す件数
+const RECENT_EVENTS: usize = 1000;
+
+/// 監査ログに記録する操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// エージェントがパスワード認証に成功した
+    AuthSuccess,
+    /// パスワード・トークンの認証に失敗した
+    AuthFailure,
+    /// 認証の失敗が続いた接続元を一時的に締め出した
+    Lockout,
+    /// ホスト名の重複で接続を拒否した
+    DuplicateHostname,
+    /// 管理APIで接続を切断した
+    AdminDisconnect,
+    /// 管理APIで接続を禁止した
+    AdminBan,
+    /// 管理APIで接続の禁止を解除した
+    AdminUnban,
+    /// 管理APIで閲覧者に通知を送った
+    AdminToast,
+    /// 管理APIでエージェントにコマンドの実行を要求した
+    AdminCommand,
+    /// エージェントがコマンドの実行結果を返した
+    CommandResult,
+    /// 管理APIでエージェントの更新の配布を開始・変更・中止した
+    AdminRollout,
+    /// エージェントが更新の結果を報告した
+    UpdateReport,
+}
+
+/// 監査ログの1件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub action: AuditAction,
+    /// 接続元のアドレス
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<IpAddr>,
+    /// 操作の対象（接続ID、ホスト名など）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, remote: Option<IpAddr>) -> Self {
+        Self { time: Utc::now(), action, remote, target: None, detail: None }
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// 監査ログの検索条件
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub action: Option<AuditAction>,
+    pub remote: Option<IpAddr>,
+    /// 返す最大件数（新しいものから）
+    pub limit: usize,
+}
+
+impl AuditFilter {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        self.since.is_none_or(|since| event.time >= since)
+            && self.action.is_none_or(|action| event.action == action)
+            && self.remote.is_none_or(|remote| event.remote == Some(remote))
+    }
+}
+
+/// 書き込み中のファイル
+struct AuditWriter {
+    file: File,
+    size: u64,
+}
+
+/// 監査ログを保存するファイル
+///
+/// `max_bytes`を超えると`{path}.1`、`{path}.2`...に移し、書き込み中のファイルを含めて`keep`個より古いものは削除する。
+struct AuditFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    writer: Mutex<Option<AuditWriter>>,
+}
+
+impl AuditFile {
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    async fn append(&self, line: &str) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        if let Some(current) = writer.as_ref()
+            && current.size > 0
+            && current.size + line.len() as u64 > self.max_bytes
+        {
+            *writer = None;
+            self.rotate().await?;
+        }
+        if writer.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path).await?;
+            let size = file.metadata().await?.len();
+            *writer = Some(AuditWriter { file, size });
+        }
+        let current = writer.as_mut().expect("opened above");
+        current.file.write_all(line.as_bytes()).await?;
+        current.file.flush().await?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&self) -> io::Result<()> {
+        info!("Rotating audit log {}", self.path.display());
+        for index in (1..=self.keep).rev() {
+            let from = if index == 1 { self.path.clone() } else { self.rotated(index - 1) };
+            if fs::try_exists(&from).await? {
+                if index == self.keep {
+                    fs::remove_file(&from).await?;
+                } else {
+                    fs::rename(&from, self.rotated(index)).await?;
+                }
+            }
+        }
+        // keepが0の場合は現在のファイルを削除するだけ
+        if self.keep == 0 && fs::try_exists(&self.path).await? {
+            fs::remove_file(&self.path).await?;
+        }
+        Ok(())
+    }
+
+    /// 古いファイルから順に読む
+    async fn read(&self, filter: &AuditFilter) -> io::Result<VecDeque<AuditEvent>> {
+        let mut events = VecDeque::new();
+        let paths = (1..self.keep).rev().map(|index| self.rotated(index)).chain([self.path.clone()]);
+        for path in paths {
+            read_events(&path, filter, &mut events).await?;
+        }
+        Ok(events)
+    }
+}
+
+async fn read_events(path: &Path, filter: &AuditFilter, events: &mut VecDeque<AuditEvent>) -> io::Result<()> {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str::<AuditEvent>(&line) {
+            Ok(event) if filter.matches(&event) => {
+                if events.len() == filter.limit {
+                    events.pop_front();
+                }
+                events.push_back(event);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Skipping invalid audit log line in {}: {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// 監査ログ
+///
+/// `audit`ターゲットのログに出力し、ファイルを指定した場合はJSON Linesで追記する。
+/// ファイルを指定しない場合は直近の記録だけをメモリ上に残す。
+pub struct AuditLog {
+    file: Option<AuditFile>,
+    recent: Mutex<VecDeque<AuditEvent>>,
+}
+
+impl AuditLog {
+    /// メモリ上にだけ残す
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { file: None, recent: Mutex::new(VecDeque::new()) })
+    }
+
+    /// ファイルに追記する（`max_bytes`を超えたら書き込み中のファイルを含めて`keep`個までローテーションする）
+    pub fn with_file(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Arc<Self>> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = AuditFile { path, max_bytes, keep, writer: Mutex::new(None) };
+        Ok(Arc::new(Self { file: Some(file), recent: Mutex::new(VecDeque::new()) }))
+    }
+
+    pub async fn record(&self, event: AuditEvent) {
+        let mut line = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize audit event: {}", e);
+                return;
+            }
+        };
+        info!(target: "audit", "{}", line);
+
+        match &self.file {
+            Some(file) => {
+                line.push('\n');
+                if let Err(e) = file.append(&line).await {
+                    error!("Failed to write audit log {}: {}", file.path.display(), e);
+                }
+            }
+            None => {
+                let mut recent = self.recent.lock().await;
+                if recent.len() == RECENT_EVENTS {
+                    recent.pop_front();
+                }
+                recent.push_back(event);
+            }
+        }
+    }
+
+    /// 条件に一致する記録を古い順に返す（新しいものから最大`limit`件）
+    pub async fn query(&self, filter: &AuditFilter) -> io::Result<Vec<AuditEvent>> {
+        if filter.limit == 0 {
+            return Ok(vec![]);
+        }
+        let events = match &self.file {
+            Some(file) => file.read(filter).await?,
+            None => {
+                let recent = self.recent.lock().await;
+                let matching: Vec<&AuditEvent> = recent.iter().filter(|event| filter.matches(event)).collect();
+                let skip = matching.len().saturating_sub(filter.limit);
+                matching.into_iter().skip(skip).cloned().collect()
+            }
+        };
+        Ok(events.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(action: AuditAction, remote: &str) -> AuditEvent {
+        AuditEvent::new(action, Some(remote.parse().unwrap())).target("web")
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_recent_files() {
+        let dir = std::env::temp_dir().join(format!("pc-status-audit-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        // 1行が100バイト程度なので、ほぼ2件ごとにローテーションする
+        let log = AuditLog::with_file(path.clone(), 250, 2).unwrap();
+        for i in 0..10 {
+            let action = if i % 2 == 0 { AuditAction::AuthFailure } else { AuditAction::AuthSuccess };
+            log.record(event(action, "192.0.2.1").detail(i.to_string())).await;
+        }
+
+        assert!(path.exists());
+        assert!(dir.join("audit.jsonl.1").exists());
+        assert!(!dir.join("audit.jsonl.2").exists());
+
+        let all = log.query(&AuditFilter { limit: 100, ..Default::default() }).await.unwrap();
+        let details: Vec<&str> = all.iter().filter_map(|event| event.detail.as_deref()).collect();
+        assert_eq!(details.last(), Some(&"9"));
+        assert!(details.len() < 10);
+        assert!(details.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let failures = AuditFilter { action: Some(AuditAction::AuthFailure), limit: 1, ..Default::default() };
+        let failures = log.query(&failures).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].detail.as_deref(), Some("8"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_recent_events_in_memory() {
+        let log = AuditLog::new();
+        log.record(event(AuditAction::AuthFailure, "192.0.2.1")).await;
+        log.record(event(AuditAction::AuthFailure, "192.0.2.2")).await;
+        log.record(event(AuditAction::AdminBan, "192.0.2.1")).await;
+
+        let filter = AuditFilter { remote: Some("192.0.2.1".parse().unwrap()), limit: 10, ..Default::default() };
+        let events = log.query(&filter).await.unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.action).collect::<Vec<_>>(),
+            vec![AuditAction::AuthFailure, AuditAction::AdminBan]
+        );
+
+        let filter = AuditFilter { since: Some(Utc::now() + chrono::Duration::hours(1)), limit: 10, ..Default::default() };
+        assert!(log.query(&filter).await.unwrap().is_empty());
+    }
+}
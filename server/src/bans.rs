@@ -0,0 +1,98 @@
+use pc_status_shared::glob_match;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use tokio::sync::RwLock;
+
+/// 接続を禁止する対象
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ban {
+    /// ホスト名（`*`をワイルドカードとして使用可能、大文字小文字は区別しない）
+    Hostname(String),
+    /// 接続元のIPアドレス
+    Ip(IpAddr),
+}
+
+impl Ban {
+    /// 比較に使う形にする（ホスト名は小文字、IPv4射影アドレスはIPv4）
+    pub fn normalize(self) -> Self {
+        match self {
+            Self::Hostname(hostname) => Self::Hostname(hostname.trim().to_lowercase()),
+            Self::Ip(ip) => Self::Ip(ip.to_canonical()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Hostname(hostname) if hostname.trim().is_empty() => Err("Hostname must not be empty".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 管理APIで追加した接続禁止の一覧（サーバーの再起動で消える）
+#[derive(Debug, Default)]
+pub struct Bans {
+    bans: RwLock<BTreeSet<Ban>>,
+}
+
+impl Bans {
+    /// 追加する（既に登録済みの場合はfalse）
+    pub async fn add(&self, ban: Ban) -> bool {
+        self.bans.write().await.insert(ban.normalize())
+    }
+
+    /// 解除する（登録されていない場合はfalse）
+    pub async fn remove(&self, ban: Ban) -> bool {
+        self.bans.write().await.remove(&ban.normalize())
+    }
+
+    pub async fn list(&self) -> Vec<Ban> {
+        self.bans.read().await.iter().cloned().collect()
+    }
+
+    pub async fn is_ip_banned(&self, ip: IpAddr) -> bool {
+        self.bans.read().await.contains(&Ban::Ip(ip.to_canonical()))
+    }
+
+    pub async fn is_hostname_banned(&self, hostname: &str) -> bool {
+        self.bans.read().await.iter().any(|ban| match ban {
+            Ban::Hostname(pattern) => glob_match(pattern, hostname),
+            Ban::Ip(_) => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bans_match_hostnames_and_ips() {
+        let bans = Bans::default();
+        assert!(bans.add(Ban::Hostname("Web-*".to_string())).await);
+        assert!(!bans.add(Ban::Hostname("web-*".to_string())).await);
+        assert!(bans.add(Ban::Ip("10.0.0.5".parse().unwrap())).await);
+
+        assert!(bans.is_hostname_banned("WEB-1").await);
+        assert!(!bans.is_hostname_banned("db").await);
+        assert!(bans.is_ip_banned("10.0.0.5".parse().unwrap()).await);
+        // IPv4射影アドレスでも一致する
+        assert!(bans.is_ip_banned("::ffff:10.0.0.5".parse().unwrap()).await);
+        assert!(!bans.is_ip_banned("10.0.0.6".parse().unwrap()).await);
+
+        assert!(bans.remove(Ban::Hostname("WEB-*".to_string())).await);
+        assert!(!bans.is_hostname_banned("web-1").await);
+        assert_eq!(bans.list().await, vec![Ban::Ip("10.0.0.5".parse().unwrap())]);
+    }
+
+    #[test]
+    fn test_ban_json() {
+        let ban: Ban = serde_json::from_str(r#"{"hostname": "web-1"}"#).unwrap();
+        assert_eq!(ban, Ban::Hostname("web-1".to_string()));
+        let ban: Ban = serde_json::from_str(r#"{"ip": "192.168.0.1"}"#).unwrap();
+        assert_eq!(serde_json::to_string(&ban).unwrap(), r#"{"ip":"192.168.0.1"}"#);
+        assert!(Ban::Hostname(" ".to_string()).validate().is_err());
+    }
+}
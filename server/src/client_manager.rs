@@ -1,23 +1,202 @@
 use pc_status_shared::{StatusData, ClientData, HistoriesData};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::sync::RwLock;
 use tracing::{debug, info};
 
+/// 切断されたエージェントの履歴を保持する最大数（古いものから破棄する）
+const MAX_RETAINED_HISTORIES: usize = 1000;
+
+/// ホスト名が重複したときの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// 後から接続したエージェントを拒否する
+    Reject,
+    /// 古いセッションを切断して置き換える
+    Replace,
+    /// インスタンス番号を振って複数の接続を許可する
+    Multiple,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "replace" => Ok(Self::Replace),
+            "multiple" => Ok(Self::Multiple),
+            other => Err(format!("Unknown conflict policy: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RegisterError {
+    #[error("Duplicate hostname: {0}")]
+    DuplicateHostname(String),
+    #[error("Agent {0} is already connected from another address")]
+    DuplicateAgent(String),
+    #[error("Too many clients (limit: {0})")]
+    TooManyClients(usize),
+}
+
+/// 登録結果
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Registration {
+    /// 同じホスト名内でのインスタンス番号（最初のインスタンスは0）
+    pub index: u32,
+    /// 置き換えられたため切断すべき接続ID
+    pub replaced: Vec<String>,
+}
+
+/// 接続中のエージェント
+///
+/// キーはエージェントのマシンIDで、`connection_id`は現在そのIDを保持している
+/// WebSocket接続を表す。
+struct ClientEntry {
+    connection_id: String,
+    /// 接続元のアドレス（同じIDの引き継ぎを許可するかの判定に使う）
+    remote: Option<IpAddr>,
+    status: StatusData,
+}
+
+/// 切断されたエージェントの履歴（再接続時に引き継ぐ）
+///
+/// 旧エージェントは再接続のたびに新しいIDになるため、保持期間と件数で制限する。
+struct RetainedHistories {
+    entries: HashMap<String, (Instant, Vec<HistoriesData>)>,
+    retention: Duration,
+    capacity: usize,
+}
+
+impl RetainedHistories {
+    fn new(retention: Duration, capacity: usize) -> Self {
+        Self { entries: HashMap::new(), retention, capacity }
+    }
+
+    fn insert(&mut self, client_id: String, histories: Vec<HistoriesData>, now: Instant) {
+        self.entries.insert(client_id, (now, histories));
+        self.entries.retain(|_, (retained_at, _)| now.duration_since(*retained_at) < self.retention);
+        while self.entries.len() > self.capacity {
+            let Some(oldest) =
+                self.entries.iter().min_by_key(|(_, (retained_at, _))| *retained_at).map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn take(&mut self, client_id: &str, now: Instant) -> Option<Vec<HistoriesData>> {
+        let (retained_at, histories) = self.entries.remove(client_id)?;
+        (now.duration_since(retained_at) < self.retention).then_some(histories)
+    }
+}
+
 #[derive(Clone)]
 pub struct ClientManager {
-    clients: Arc<RwLock<HashMap<String, StatusData>>>,
+    clients: Arc<RwLock<HashMap<String, ClientEntry>>>,
+    retained_histories: Arc<RwLock<RetainedHistories>>,
+    conflict_policy: ConflictPolicy,
+    /// 同時に接続できるエージェント数の上限（Noneは無制限）
+    max_clients: Option<usize>,
+}
+
+/// 同じテナント内で同じホスト名か（別のテナントのホスト名は重複として扱わない）
+fn same_hostname(a: &StatusData, b: &StatusData) -> bool {
+    a.tenant == b.tenant && a.hostname.eq_ignore_ascii_case(&b.hostname)
 }
 
 impl ClientManager {
-    pub fn new() -> Arc<Self> {
+    /// `history_retention`は切断されたエージェントの履歴を引き継げる期間
+    pub fn new(conflict_policy: ConflictPolicy, max_clients: Option<usize>, history_retention: Duration) -> Arc<Self> {
         Arc::new(Self {
             clients: Arc::new(RwLock::new(HashMap::new())),
+            retained_histories: Arc::new(RwLock::new(RetainedHistories::new(
+                history_retention,
+                MAX_RETAINED_HISTORIES,
+            ))),
+            conflict_policy,
+            max_clients,
         })
     }
 
-    pub async fn add_client(&self, client_id: &str, mut status_data: StatusData) {
-        // 履歴データを初期化
+    /// エージェントを登録する
+    ///
+    /// ホスト名の重複は設定された`ConflictPolicy`に従って処理する。開発モードの
+    /// エージェントは常に`Multiple`として扱う。同じIDの古いセッションが残っている
+    /// 場合、同じアドレスからの再接続か`Replace`の場合だけ置き換え、以前の履歴を引き継ぐ。
+    /// マシンIDは閲覧者にも見えるため、別のアドレスからの引き継ぎは許可しない。
+    pub async fn register_client(
+        &self,
+        client_id: &str,
+        connection_id: &str,
+        remote: Option<IpAddr>,
+        mut status_data: StatusData,
+    ) -> Result<Registration, RegisterError> {
+        let policy = if status_data.dev.unwrap_or(false) {
+            ConflictPolicy::Multiple
+        } else {
+            self.conflict_policy
+        };
+
+        let mut clients = self.clients.write().await;
+        if let Some(existing) = clients.get(client_id)
+            && existing.connection_id != connection_id
+            && existing.remote != remote
+            && policy != ConflictPolicy::Replace
+        {
+            return Err(RegisterError::DuplicateAgent(client_id.to_string()));
+        }
+        let conflicting: Vec<String> = clients
+            .iter()
+            .filter(|(id, entry)| {
+                id.as_str() != client_id && same_hostname(&entry.status, &status_data)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        // 置き換えで空く分を除いた接続数が上限に達していれば新しいIDは受け付けない
+        if let Some(limit) = self.max_clients
+            && !clients.contains_key(client_id)
+        {
+            let freed = if policy == ConflictPolicy::Replace { conflicting.len() } else { 0 };
+            if clients.len() - freed >= limit {
+                return Err(RegisterError::TooManyClients(limit));
+            }
+        }
+
+        let mut registration = Registration::default();
+        match policy {
+            ConflictPolicy::Reject if !conflicting.is_empty() => {
+                return Err(RegisterError::DuplicateHostname(status_data.hostname));
+            }
+            ConflictPolicy::Reject => {}
+            ConflictPolicy::Replace => {
+                let mut retained = self.retained_histories.write().await;
+                for id in conflicting {
+                    if let Some(entry) = clients.remove(&id) {
+                        info!("Replacing client: {} ({})", id, entry.status.hostname);
+                        registration.replaced.push(entry.connection_id);
+                        retained.insert(id, entry.status.histories, Instant::now());
+                    }
+                }
+            }
+            ConflictPolicy::Multiple => {
+                let used: Vec<u32> = conflicting
+                    .iter()
+                    .filter_map(|id| clients.get(id))
+                    .map(|entry| entry.status.index)
+                    .collect();
+                registration.index = (0..).find(|index| !used.contains(index)).unwrap_or_default();
+            }
+        }
+
         let history = HistoriesData {
             cpu: status_data.cpu.clone(),
             ram: status_data.ram.clone(),
@@ -26,45 +205,66 @@ impl ClientManager {
             gpus: status_data.gpus.clone(),
             uptime: status_data.uptime,
         };
-        status_data.histories = vec![history];
 
-        let mut clients = self.clients.write().await;
-        clients.insert(client_id.to_string(), status_data);
-        info!("Added client: {}", client_id);
+        let mut histories = match clients.remove(client_id) {
+            Some(previous) => {
+                if previous.connection_id != connection_id {
+                    registration.replaced.push(previous.connection_id);
+                }
+                previous.status.histories
+            }
+            None => self
+                .retained_histories
+                .write()
+                .await
+                .take(client_id, Instant::now())
+                .unwrap_or_default(),
+        };
+        if histories.len() >= 10 {
+            histories.remove(0);
+        }
+        histories.push(history);
+        status_data.histories = histories;
+        status_data.index = registration.index;
+
+        clients.insert(
+            client_id.to_string(),
+            ClientEntry {
+                connection_id: connection_id.to_string(),
+                remote,
+                status: status_data,
+            },
+        );
+        info!("Added client: {} (connection: {})", client_id, connection_id);
+        Ok(registration)
     }
 
-    pub async fn remove_client(&self, client_id: &str) -> Option<StatusData> {
+    /// エージェントを削除する
+    ///
+    /// 別の接続が既に同じIDを引き継いでいる場合は何もしない。
+    pub async fn remove_client(&self, client_id: &str, connection_id: &str) -> Option<StatusData> {
         let mut clients = self.clients.write().await;
-        if let Some(client_data) = clients.remove(client_id) {
-            info!("Removed client: {} ({})", client_id, client_data.hostname);
-            Some(client_data)
-        } else {
-            None
+        if clients.get(client_id)?.connection_id != connection_id {
+            debug!("Client {} was taken over by another connection", client_id);
+            return None;
         }
+
+        let entry = clients.remove(client_id)?;
+        info!("Removed client: {} ({})", client_id, entry.status.hostname);
+        self.retained_histories
+            .write()
+            .await
+            .insert(client_id.to_string(), entry.status.histories.clone(), Instant::now());
+        Some(entry.status)
     }
 
-    pub async fn update_client(&self, client_id: &str, mut status_data: StatusData) {
+    /// 同期データを反映する
+    ///
+    /// ホスト名とインスタンス番号は登録時の値を維持する。
+    pub async fn update_client(&self, client_id: &str, status_data: StatusData) {
         let mut clients = self.clients.write().await;
-        
-        if let Some(existing_client) = clients.get_mut(client_id) {
-            // 既存のクライアントデータを更新
-            let _old_hostname = existing_client.hostname.clone();
-            
-            // インデックスが設定されている場合の処理
-            if existing_client.index > 0 {
-                if status_data.dev.unwrap_or(false) {
-                    status_data.index = existing_client.index;
-                    status_data.hostname = format!("[DEV] {}_{}", 
-                        status_data.hostname.replace("[DEV] ", "").split('_').next().unwrap_or(&status_data.hostname), 
-                        existing_client.index
-                    );
-                } else {
-                    // 開発モードでない場合は切断
-                    debug!("Non-dev client trying to sync with index > 0: {}", client_id);
-                    return;
-                }
-            }
 
+        if let Some(existing_client) = clients.get_mut(client_id).map(|entry| &mut entry.status) {
             // 履歴を更新
             let history = HistoriesData {
                 cpu: status_data.cpu.clone(),
@@ -89,7 +289,6 @@ impl ClientManager {
             existing_client.gpus = status_data.gpus;
             existing_client.uptime = status_data.uptime;
             existing_client.loadavg = status_data.loadavg;
-            existing_client.hostname = status_data.hostname;
 
             debug!("Updated client: {} ({})", client_id, existing_client.hostname);
         }
@@ -98,36 +297,213 @@ impl ClientManager {
     pub async fn get_all_clients(&self) -> ClientData {
         let clients = self.clients.read().await;
         let mut result = HashMap::new();
-        
-        for (id, mut status) in clients.clone() {
+
+        for (id, entry) in clients.iter() {
             // パスワードを除去してフロントエンドに送信
+            let mut status = entry.status.clone();
             status.pass = None;
-            result.insert(id, status);
+            result.insert(id.clone(), status);
         }
-        
+
         result
     }
 
-    pub async fn hostname_exists(&self, hostname: &str) -> bool {
+    #[allow(dead_code)]
+    pub async fn get_client_count(&self) -> usize {
         let clients = self.clients.read().await;
-        clients.values().any(|client| {
-            client.hostname.contains(hostname) || 
-            client.hostname.replace("[DEV] ", "").split('_').next().unwrap_or("") == hostname
-        })
+        clients.len()
     }
+}
 
-    pub async fn get_hostname_count(&self, hostname: &str) -> u32 {
-        let clients = self.clients.read().await;
-        clients.values()
-            .filter(|client| {
-                client.hostname.contains(hostname) || 
-                client.hostname.replace("[DEV] ", "").split('_').next().unwrap_or("") == hostname
-            })
-            .count() as u32
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pc_status_shared::test_support;
+
+    const RETENTION: Duration = Duration::from_secs(3600);
+
+    fn status(hostname: &str, dev: bool) -> StatusData {
+        StatusData { dev: Some(dev), ..test_support::status(hostname) }
     }
 
-    pub async fn get_client_count(&self) -> usize {
-        let clients = self.clients.read().await;
-        clients.len()
+    #[tokio::test]
+    async fn test_prefix_hostnames_do_not_conflict() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web2", false)).await.unwrap();
+        manager.register_client("b", "conn-b", None, status("web", false)).await.unwrap();
+        manager.register_client("c", "conn-c", None, status("[DEV] web_1", false)).await.unwrap();
+
+        assert_eq!(manager.get_client_count().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_reject_policy() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+
+        let result = manager.register_client("b", "conn-b", None, status("WEB", false)).await;
+        assert_eq!(result, Err(RegisterError::DuplicateHostname("WEB".to_string())));
+        assert_eq!(manager.get_client_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_hostnames_in_other_tenants_do_not_conflict() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+
+        let mut other = status("web", false);
+        other.tenant = "team-b".to_string();
+        let registration = manager.register_client("team-b.a", "conn-b", None, other).await.unwrap();
+        assert_eq!(registration.index, 0);
+        assert_eq!(manager.get_client_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_replace_policy() {
+        let manager = ClientManager::new(ConflictPolicy::Replace, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+
+        let registration = manager.register_client("b", "conn-b", None, status("web", false)).await.unwrap();
+        assert_eq!(registration.replaced, vec!["conn-a".to_string()]);
+
+        let clients = manager.get_all_clients().await;
+        assert!(clients.contains_key("b"));
+        assert!(!clients.contains_key("a"));
+
+        // 置き換えられた接続の後始末では新しいセッションを削除しない
+        assert!(manager.remove_client("a", "conn-a").await.is_none());
+        assert_eq!(manager.get_client_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_policy_assigns_lowest_free_index() {
+        let manager = ClientManager::new(ConflictPolicy::Multiple, None, RETENTION);
+        let first = manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+        let second = manager.register_client("b", "conn-b", None, status("web", false)).await.unwrap();
+        let third = manager.register_client("c", "conn-c", None, status("web", false)).await.unwrap();
+        assert_eq!((first.index, second.index, third.index), (0, 1, 2));
+
+        manager.remove_client("b", "conn-b").await.unwrap();
+        let fourth = manager.register_client("d", "conn-d", None, status("web", false)).await.unwrap();
+        assert_eq!(fourth.index, 1);
+
+        // インスタンス番号は表示名に埋め込まない
+        let clients = manager.get_all_clients().await;
+        assert_eq!(clients["d"].hostname, "web");
+        assert_eq!(clients["d"].index, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dev_agents_always_allow_multiple() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+
+        let registration = manager.register_client("b", "conn-b", None, status("web", true)).await.unwrap();
+        assert_eq!(registration.index, 1);
+    }
+
+    #[tokio::test]
+    async fn test_same_machine_reconnect_is_not_a_conflict() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        manager.register_client("a", "conn-1", None, status("web", false)).await.unwrap();
+
+        let registration = manager.register_client("a", "conn-2", None, status("web", false)).await.unwrap();
+        assert_eq!(registration.replaced, vec!["conn-1".to_string()]);
+        assert_eq!(manager.get_all_clients().await["a"].histories.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_same_id_from_another_address_is_not_taken_over() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        let agent: Option<IpAddr> = Some("192.0.2.1".parse().unwrap());
+        let other: Option<IpAddr> = Some("198.51.100.1".parse().unwrap());
+        manager.register_client("a", "conn-1", agent, status("web", false)).await.unwrap();
+
+        // マシンIDを知っていても別のアドレスからは引き継げない
+        let result = manager.register_client("a", "conn-2", other, status("evil", false)).await;
+        assert_eq!(result, Err(RegisterError::DuplicateAgent("a".to_string())));
+        assert_eq!(manager.get_all_clients().await["a"].hostname, "web");
+
+        // 同じアドレスからの再接続は引き継ぐ
+        let registration = manager.register_client("a", "conn-3", agent, status("web", false)).await.unwrap();
+        assert_eq!(registration.replaced, vec!["conn-1".to_string()]);
+
+        // 古いセッションが切断された後は別のアドレスからも接続できる
+        manager.remove_client("a", "conn-3").await.unwrap();
+        manager.register_client("a", "conn-4", other, status("web", false)).await.unwrap();
+
+        // Replaceの場合は別のアドレスからも置き換える
+        let manager = ClientManager::new(ConflictPolicy::Replace, None, RETENTION);
+        manager.register_client("a", "conn-1", agent, status("web", false)).await.unwrap();
+        let registration = manager.register_client("a", "conn-2", other, status("web", false)).await.unwrap();
+        assert_eq!(registration.replaced, vec!["conn-1".to_string()]);
+    }
+
+    #[test]
+    fn test_retained_histories_expire() {
+        let now = Instant::now();
+        let mut retained = RetainedHistories::new(Duration::from_secs(60), 10);
+        retained.insert("a".to_string(), vec![], now);
+        retained.insert("b".to_string(), vec![], now);
+
+        assert!(retained.take("a", now + Duration::from_secs(59)).is_some());
+        assert!(retained.take("b", now + Duration::from_secs(60)).is_none());
+
+        // 期限切れのものは次の追加で破棄する
+        retained.insert("c".to_string(), vec![], now);
+        retained.insert("d".to_string(), vec![], now + Duration::from_secs(61));
+        assert_eq!(retained.entries.keys().collect::<Vec<_>>(), vec!["d"]);
+    }
+
+    #[test]
+    fn test_retained_histories_are_capped() {
+        let now = Instant::now();
+        let mut retained = RetainedHistories::new(Duration::from_secs(3600), 2);
+        for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+            retained.insert(id.to_string(), vec![], now + Duration::from_secs(i as u64));
+        }
+        let mut ids: Vec<&String> = retained.entries.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_reconnects_do_not_grow_retained_histories() {
+        let manager = ClientManager::new(ConflictPolicy::Reject, None, RETENTION);
+        // 旧エージェントは接続ごとに新しいIDで登録される
+        for i in 0..MAX_RETAINED_HISTORIES + 10 {
+            let id = format!("conn-{}", i);
+            manager.register_client(&id, &id, None, status("web", false)).await.unwrap();
+            manager.remove_client(&id, &id).await.unwrap();
+        }
+        assert_eq!(manager.retained_histories.read().await.entries.len(), MAX_RETAINED_HISTORIES);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_registered_hostname() {
+        let manager = ClientManager::new(ConflictPolicy::Multiple, None, RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+        manager.register_client("b", "conn-b", None, status("web", false)).await.unwrap();
+
+        manager.update_client("b", status("renamed", false)).await;
+
+        let clients = manager.get_all_clients().await;
+        assert_eq!(clients["b"].hostname, "web");
+        assert_eq!(clients["b"].index, 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_limit() {
+        let manager = ClientManager::new(ConflictPolicy::Replace, Some(2), RETENTION);
+        manager.register_client("a", "conn-a", None, status("web", false)).await.unwrap();
+        manager.register_client("b", "conn-b", None, status("db", false)).await.unwrap();
+
+        let result = manager.register_client("c", "conn-c", None, status("cache", false)).await;
+        assert_eq!(result, Err(RegisterError::TooManyClients(2)));
+
+        // 再接続や置き換えは上限に達していても受け付ける
+        manager.register_client("a", "conn-a2", None, status("web", false)).await.unwrap();
+        manager.register_client("d", "conn-d", None, status("DB", false)).await.unwrap();
+        assert_eq!(manager.get_client_count().await, 2);
     }
 }
@@ -0,0 +1,442 @@
+use anyhow::{bail, Context, Result};
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use pc_status_shared::AlertState;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{debug, error, info, warn};
+
+use crate::notification::Notification;
+
+/// 送信待ちの通知を確認する間隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// 1通のまとめメールに含める通知の上限（超えた分は件数のみ記載）
+const MAX_DIGEST_ENTRIES: usize = 50;
+/// 同じ宛先へのメールの最小間隔の既定値
+const DEFAULT_MIN_INTERVAL_SECS: u64 = 300;
+
+/// SMTPサーバーとの接続の暗号化方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// 平文で接続してからSTARTTLSで暗号化する
+    StartTls,
+    /// 最初からTLSで接続する
+    Tls,
+    /// 暗号化しない（ローカルのリレー向け）
+    None,
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            "none" => Ok(Self::None),
+            other => Err(format!("Unknown SMTP security: {}", other)),
+        }
+    }
+}
+
+/// メール通知の設定
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub credentials: Option<(String, String)>,
+    pub from: Mailbox,
+    /// 切断と、宛先を指定していないルールのアラートの送信先
+    pub to: Vec<Mailbox>,
+    /// 同じ宛先へのメールの最小間隔（間に発生した通知はまとめて送る）
+    pub min_interval: Duration,
+}
+
+impl SmtpConfig {
+    /// 環境変数から設定を読み込む（`SMTP_HOST`が未設定の場合はNone）
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(host) = env::var("SMTP_HOST") else {
+            return Ok(None);
+        };
+
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => Some(port.parse::<u16>().with_context(|| format!("Invalid SMTP_PORT: {}", port))?),
+            Err(_) => None,
+        };
+        let security = match env::var("SMTP_SECURITY") {
+            Ok(value) => value.parse::<SmtpSecurity>().map_err(anyhow::Error::msg)?,
+            Err(_) => SmtpSecurity::StartTls,
+        };
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            (Ok(_), Err(_)) => bail!("SMTP_PASSWORD must be set together with SMTP_USERNAME"),
+            _ => None,
+        };
+        let from = env::var("SMTP_FROM")
+            .context("SMTP_FROM must be set when SMTP_HOST is set")?
+            .parse::<Mailbox>()
+            .context("Invalid SMTP_FROM")?;
+        let to = parse_mailboxes(env::var("SMTP_TO").unwrap_or_default().split(','))?;
+        let min_interval = env::var("SMTP_MIN_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MIN_INTERVAL_SECS);
+
+        Ok(Some(Self {
+            host,
+            port,
+            security,
+            credentials,
+            from,
+            to,
+            min_interval: Duration::from_secs(min_interval),
+        }))
+    }
+}
+
+/// メールアドレスを読み込む（空の要素は無視する）
+pub fn parse_mailboxes<'a>(addresses: impl IntoIterator<Item = &'a str>) -> Result<Vec<Mailbox>> {
+    addresses
+        .into_iter()
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(|address| address.parse::<Mailbox>().with_context(|| format!("Invalid email address: {}", address)))
+        .collect()
+}
+
+/// 宛先ごとの送信待ちの通知
+struct Outbox {
+    mailbox: Mailbox,
+    last_sent: Option<Instant>,
+    pending: Vec<Notification>,
+    /// 上限を超えて破棄した通知の数
+    omitted: usize,
+}
+
+/// 切断とアラートをメールで通知する
+///
+/// 同じ宛先には`min_interval`に1通までしか送らず、間に発生した通知は次の
+/// メールにまとめる。
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    default_recipients: Vec<Mailbox>,
+    rule_recipients: HashMap<String, Vec<Mailbox>>,
+    min_interval: Duration,
+    outboxes: HashMap<String, Outbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: SmtpConfig, rule_recipients: HashMap<String, Vec<Mailbox>>) -> Result<Self> {
+        let mut builder = match config.security {
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.from,
+            default_recipients: config.to,
+            rule_recipients,
+            min_interval: config.min_interval,
+            outboxes: HashMap::new(),
+        })
+    }
+
+    /// 通知の宛先（メールで送らない通知はNone）
+    fn recipients(&self, notification: &Notification) -> Option<&[Mailbox]> {
+        match notification {
+            Notification::Connected { .. } => None,
+            Notification::Disconnected { .. } => Some(&self.default_recipients),
+            Notification::Alert(alert) if alert.state == AlertState::Pending => None,
+            Notification::Alert(alert) => Some(
+                self.rule_recipients
+                    .get(&alert.rule)
+                    .filter(|recipients| !recipients.is_empty())
+                    .unwrap_or(&self.default_recipients),
+            ),
+        }
+    }
+
+    fn enqueue(&mut self, notification: Notification) {
+        let Some(recipients) = self.recipients(&notification).map(<[Mailbox]>::to_vec) else {
+            return;
+        };
+        for mailbox in recipients {
+            let outbox = self.outboxes.entry(mailbox.to_string()).or_insert_with(|| Outbox {
+                mailbox,
+                last_sent: None,
+                pending: Vec::new(),
+                omitted: 0,
+            });
+            if outbox.pending.len() < MAX_DIGEST_ENTRIES {
+                outbox.pending.push(notification.clone());
+            } else {
+                outbox.omitted += 1;
+            }
+        }
+    }
+
+    /// 最小間隔を過ぎた宛先に送信待ちの通知を送る
+    async fn flush(&mut self, now: Instant) {
+        let due: Vec<String> = self
+            .outboxes
+            .iter()
+            .filter(|(_, outbox)| {
+                !outbox.pending.is_empty()
+                    && outbox.last_sent.is_none_or(|sent| now.duration_since(sent) >= self.min_interval)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in due {
+            let Some(outbox) = self.outboxes.get_mut(&key) else {
+                continue;
+            };
+            // 失敗した場合も次の間隔までは再送しない
+            outbox.last_sent = Some(now);
+            let message = match build_message(&self.from, outbox) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Failed to build notification email for {}: {:#}", key, e);
+                    outbox.pending.clear();
+                    outbox.omitted = 0;
+                    continue;
+                }
+            };
+            match self.transport.send(message).await {
+                Ok(_) => {
+                    info!("Sent {} notifications by email to {}", outbox.pending.len() + outbox.omitted, key);
+                    outbox.pending.clear();
+                    outbox.omitted = 0;
+                }
+                Err(e) => warn!("Failed to send notification email to {}: {}", key, e),
+            }
+        }
+    }
+
+    /// 通知を受信してメールを送り続けるタスクを開始する
+    pub fn spawn(mut self, mut rx: Receiver<Notification>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok(notification) => self.enqueue(notification),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Email notifier fell behind, skipped {} notifications", skipped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = interval.tick() => self.flush(Instant::now()).await,
+                }
+            }
+            debug!("Email notifier stopped");
+        });
+    }
+}
+
+fn build_message(from: &Mailbox, outbox: &Outbox) -> Result<Message> {
+    let subject = match outbox.pending.as_slice() {
+        [notification] if outbox.omitted == 0 => format!("[PC Status] {}", notification.message()),
+        pending => format!("[PC Status] {} notifications", pending.len() + outbox.omitted),
+    };
+
+    let mut body: String = outbox
+        .pending
+        .iter()
+        .map(|notification| format!("{}  {}\n", notification.time().to_rfc3339(), notification.message()))
+        .collect();
+    if outbox.omitted > 0 {
+        body.push_str(&format!("... and {} more\n", outbox.omitted));
+    }
+
+    Ok(Message::builder()
+        .from(from.clone())
+        .to(outbox.mailbox.clone())
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use pc_status_shared::Alert;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// 受信したメール
+    #[derive(Debug, Clone, Default)]
+    struct ReceivedMail {
+        recipients: Vec<String>,
+        data: String,
+    }
+
+    /// 受信したメールを記録するSMTPサーバーの代役
+    async fn start_stand_in() -> (u16, Arc<Mutex<Vec<ReceivedMail>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let received = received_clone.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    let mut mail = ReceivedMail::default();
+                    writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                            b"250 localhost\r\n"
+                        } else if command.starts_with("RCPT TO:") {
+                            mail.recipients.push(line[8..].trim_matches(|c| c == '<' || c == '>').to_string());
+                            b"250 OK\r\n"
+                        } else if command == "DATA" {
+                            writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                            while let Ok(Some(data)) = lines.next_line().await {
+                                if data == "." {
+                                    break;
+                                }
+                                mail.data.push_str(&data);
+                                mail.data.push('\n');
+                            }
+                            received.lock().unwrap().push(std::mem::take(&mut mail));
+                            b"250 OK\r\n"
+                        } else if command == "QUIT" {
+                            writer.write_all(b"221 Bye\r\n").await.unwrap();
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        writer.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (port, received)
+    }
+
+    fn notifier(port: u16, rule_recipients: &[(&str, &str)]) -> EmailNotifier {
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            credentials: None,
+            from: "pc-status@example.com".parse().unwrap(),
+            to: parse_mailboxes(["oncall@example.com"]).unwrap(),
+            min_interval: Duration::from_secs(300),
+        };
+        let rule_recipients = rule_recipients
+            .iter()
+            .map(|(rule, to)| (rule.to_string(), parse_mailboxes(to.split(',')).unwrap()))
+            .collect();
+        EmailNotifier::new(config, rule_recipients).unwrap()
+    }
+
+    fn disconnected(hostname: &str) -> Notification {
+        Notification::Disconnected {
+            host: hostname.to_string(),
+            hostname: hostname.to_string(),
+            time: Utc::now(),
+        }
+    }
+
+    fn alert(rule: &str, state: AlertState) -> Notification {
+        Notification::Alert(Alert {
+            rule: rule.to_string(),
+            host: "machine-1".to_string(),
+            hostname: "web".to_string(),
+            tenant: String::new(),
+            state,
+            value: 95.0,
+            threshold: 90.0,
+            message: format!("web: {}", rule),
+            since: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_flapping_host_is_digested() {
+        let (port, received) = start_stand_in().await;
+        let mut notifier = notifier(port, &[]);
+        let start = Instant::now();
+
+        notifier.enqueue(disconnected("web"));
+        notifier.flush(start).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        for _ in 0..5 {
+            notifier.enqueue(disconnected("web"));
+        }
+        notifier.flush(start + Duration::from_secs(10)).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        notifier.flush(start + Duration::from_secs(300)).await;
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received[1].data.contains("Subject: [PC Status] 5 notifications"));
+        assert_eq!(received[1].data.matches("web is disconnected").count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_rule_recipients() {
+        let (port, received) = start_stand_in().await;
+        let mut notifier = notifier(port, &[("low-disk", "storage@example.com, infra@example.com")]);
+
+        notifier.enqueue(alert("low-disk", AlertState::Firing));
+        notifier.enqueue(alert("high-ram", AlertState::Firing));
+        notifier.flush(Instant::now()).await;
+
+        let mut recipients: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|mail| mail.recipients.clone())
+            .collect();
+        recipients.sort();
+        assert_eq!(recipients, vec!["infra@example.com", "oncall@example.com", "storage@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_connected_and_pending_are_not_mailed() {
+        let (port, received) = start_stand_in().await;
+        let mut notifier = notifier(port, &[]);
+
+        notifier.enqueue(Notification::Connected {
+            host: "web".to_string(),
+            hostname: "web".to_string(),
+            time: Utc::now(),
+        });
+        notifier.enqueue(alert("high-ram", AlertState::Pending));
+        notifier.flush(Instant::now()).await;
+
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_mailboxes() {
+        assert_eq!(parse_mailboxes(" a@example.com ,b@example.com,".split(',')).unwrap().len(), 2);
+        assert!(parse_mailboxes(["not an address"]).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use futures::stream::{Stream, StreamExt};
+use pc_status_shared::HistorySample;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+/// 1回の送信にまとめるサンプル数の上限
+const CHUNK_SAMPLES: usize = 256;
+
+/// 書き出す列
+///
+/// CSVでは各列を1つ以上のセルに展開し、NDJSONでは同名のキーに書き出す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// 全コアの平均使用率（%）
+    Cpu,
+    /// コアごとの使用率（%）
+    CpuCores,
+    /// メモリの使用量・総量（バイト）と使用率
+    Ram,
+    /// スワップの使用量・総量（バイト）と使用率
+    Swap,
+    /// ストレージごとの使用量・総量（バイト）と使用率
+    Storages,
+    /// GPUごとの使用率とメモリの使用量・総量
+    Gpus,
+    /// 1・5・15分のロードアベレージ
+    Load,
+    /// 稼働時間（秒）
+    Uptime,
+    /// タグ（CSVではカンマ区切り）
+    Tags,
+    /// ラベル（CSVでは`key=value`のカンマ区切り）
+    Labels,
+}
+
+/// 列を指定しなかったときに書き出す列
+pub const DEFAULT_COLUMNS: [Column; 5] = [Column::Cpu, Column::Ram, Column::Swap, Column::Load, Column::Uptime];
+
+const ALL_COLUMNS: [Column; 10] = [
+    Column::Cpu,
+    Column::CpuCores,
+    Column::Ram,
+    Column::Swap,
+    Column::Storages,
+    Column::Gpus,
+    Column::Load,
+    Column::Uptime,
+    Column::Tags,
+    Column::Labels,
+];
+
+impl Column {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu",
+            Self::CpuCores => "cpu_cores",
+            Self::Ram => "ram",
+            Self::Swap => "swap",
+            Self::Storages => "storages",
+            Self::Gpus => "gpus",
+            Self::Load => "load",
+            Self::Uptime => "uptime",
+            Self::Tags => "tags",
+            Self::Labels => "labels",
+        }
+    }
+
+    /// CSVのセル（ヘッダー名と値）
+    fn cells(self, sample: &HistorySample, cells: &mut Vec<(String, String)>) {
+        let data = &sample.data;
+        match self {
+            Self::Cpu => cells.push(("cpu".to_string(), format_float(average(&cpu_cores(sample))))),
+            Self::CpuCores => {
+                for (index, core) in data.cpu.cpus.iter().enumerate() {
+                    cells.push((format!("cpu{}", index), format_float(Some(core.cpu))));
+                }
+            }
+            Self::Ram => memory_cells("ram", data.ram.free, data.ram.total, cells),
+            Self::Swap => memory_cells("swap", data.swap.free, data.swap.total, cells),
+            Self::Storages => {
+                for storage in &data.storages {
+                    let name = format!("storage[{}]", storage.name.as_deref().unwrap_or("-"));
+                    memory_cells(&name, storage.free, storage.total, cells);
+                }
+            }
+            Self::Gpus => {
+                for (index, gpu) in data.gpus.iter().enumerate() {
+                    cells.push((format!("gpu{}_usage", index), format_float(Some(gpu.usage))));
+                    memory_cells(&format!("gpu{}_memory", index), gpu.memory.free, gpu.memory.total, cells);
+                }
+            }
+            Self::Load => {
+                for (name, value) in ["load1", "load5", "load15"].iter().zip(sample.loadavg) {
+                    cells.push((name.to_string(), format_float(Some(value))));
+                }
+            }
+            Self::Uptime => cells.push(("uptime".to_string(), data.uptime.to_string())),
+            Self::Tags => cells.push(("tags".to_string(), sample.tags.join(","))),
+            Self::Labels => {
+                let labels: Vec<String> = sample.labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+                cells.push(("labels".to_string(), labels.join(",")));
+            }
+        }
+    }
+
+    /// NDJSONの値
+    fn value(self, sample: &HistorySample) -> Value {
+        let data = &sample.data;
+        match self {
+            Self::Cpu => json!(average(&cpu_cores(sample))),
+            Self::CpuCores => json!(cpu_cores(sample)),
+            Self::Ram => memory_value(data.ram.free, data.ram.total),
+            Self::Swap => memory_value(data.swap.free, data.swap.total),
+            Self::Storages => Value::Array(
+                data.storages
+                    .iter()
+                    .map(|storage| {
+                        let mut value = memory_value(storage.free, storage.total);
+                        value["name"] = json!(storage.name);
+                        value
+                    })
+                    .collect(),
+            ),
+            Self::Gpus => Value::Array(
+                data.gpus
+                    .iter()
+                    .map(|gpu| {
+                        json!({
+                            "name": gpu.name,
+                            "usage": gpu.usage,
+                            "memory": memory_value(gpu.memory.free, gpu.memory.total),
+                        })
+                    })
+                    .collect(),
+            ),
+            Self::Load => json!(sample.loadavg),
+            Self::Uptime => json!(data.uptime),
+            Self::Tags => json!(sample.tags),
+            Self::Labels => json!(sample.labels),
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_COLUMNS.into_iter().find(|column| column.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = ALL_COLUMNS.iter().map(|column| column.name()).collect();
+            format!("Unknown column: {} (available: {})", s, names.join(", "))
+        })
+    }
+}
+
+/// カンマ区切りの列名を読む（空の場合は既定の列）
+pub fn parse_columns(value: Option<&str>) -> Result<Vec<Column>, String> {
+    let mut columns = vec![];
+    for name in value.unwrap_or_default().split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let column = name.parse()?;
+        if !columns.contains(&column) {
+            columns.push(column);
+        }
+    }
+    if columns.is_empty() {
+        columns = DEFAULT_COLUMNS.to_vec();
+    }
+    Ok(columns)
+}
+
+/// 書き出し形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            other => Err(format!("Unknown format: {} (available: csv, ndjson)", other)),
+        }
+    }
+}
+
+fn cpu_cores(sample: &HistorySample) -> Vec<f64> {
+    sample.data.cpu.cpus.iter().map(|core| core.cpu).collect()
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn used_percent(free: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| total.saturating_sub(free) as f64 / total as f64 * 100.0)
+}
+
+fn format_float(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.2}", value)).unwrap_or_default()
+}
+
+fn memory_cells(name: &str, free: u64, total: u64, cells: &mut Vec<(String, String)>) {
+    cells.push((format!("{}_used", name), total.saturating_sub(free).to_string()));
+    cells.push((format!("{}_total", name), total.to_string()));
+    cells.push((format!("{}_percent", name), format_float(used_percent(free, total))));
+}
+
+fn memory_value(free: u64, total: u64) -> Value {
+    json!({
+        "used": total.saturating_sub(free),
+        "total": total,
+        "percent": used_percent(free, total),
+    })
+}
+
+/// CSVのセルをエスケープする
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line<'a>(values: impl IntoIterator<Item = &'a str>) -> String {
+    let mut line = values.into_iter().map(escape_csv).collect::<Vec<_>>().join(",");
+    line.push('\n');
+    line
+}
+
+fn csv_cells(columns: &[Column], sample: &HistorySample) -> Vec<(String, String)> {
+    let mut cells = vec![("time".to_string(), sample.time.to_rfc3339())];
+    for column in columns {
+        column.cells(sample, &mut cells);
+    }
+    cells
+}
+
+fn ndjson_line(columns: &[Column], sample: &HistorySample) -> String {
+    let mut row = Map::new();
+    row.insert("time".to_string(), json!(sample.time));
+    for column in columns {
+        row.insert(column.name().to_string(), column.value(sample));
+    }
+    let mut line = Value::Object(row).to_string();
+    line.push('\n');
+    line
+}
+
+/// サンプルを1行ずつ書き出し形式に変換する
+///
+/// CSVのヘッダーはサンプルのセルから決める。コアやストレージの数が途中で
+/// 変わった場合は、空行に続けて新しいヘッダーを書き、以降の行はそのヘッダーに従う。
+pub fn encode(
+    samples: impl Stream<Item = HistorySample> + Send + 'static,
+    columns: Vec<Column>,
+    format: ExportFormat,
+) -> impl Stream<Item = String> + Send + 'static {
+    let mut header: Option<Vec<String>> = None;
+    samples
+        .map(move |sample| match format {
+            ExportFormat::Ndjson => ndjson_line(&columns, &sample),
+            ExportFormat::Csv => {
+                let cells = csv_cells(&columns, &sample);
+                let mut output = String::new();
+                let names = cells.iter().map(|(name, _)| name);
+                let changed = !header.as_ref().is_some_and(|header| header.iter().eq(names));
+                if changed {
+                    if header.is_some() {
+                        output.push('\n');
+                    }
+                    let names: Vec<String> = cells.iter().map(|(name, _)| name.clone()).collect();
+                    output.push_str(&csv_line(names.iter().map(String::as_str)));
+                    header = Some(names);
+                }
+                output.push_str(&csv_line(cells.iter().map(|(_, value)| value.as_str())));
+                output
+            }
+        })
+        .ready_chunks(CHUNK_SAMPLES)
+        .map(|lines| lines.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use futures::stream;
+    use pc_status_shared::{Cpu, CpuData, HistoriesData, Ram, Storage, Swap};
+
+    fn sample(second: u32, cores: &[f64]) -> HistorySample {
+        HistorySample {
+            time: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, second).unwrap(),
+            data: HistoriesData {
+                cpu: Cpu {
+                    model: "cpu".to_string(),
+                    cpus: cores.iter().map(|&cpu| CpuData { cpu }).collect(),
+                },
+                ram: Ram { free: 25, total: 100 },
+                swap: Swap { free: 0, total: 0 },
+                storages: vec![Storage { name: Some("C:\\, data".to_string()), free: 50, total: 200 }],
+                gpus: vec![],
+                uptime: 60,
+            },
+            loadavg: [0.5, 0.25, 0.0],
+            tags: vec!["gpu".to_string(), "ci".to_string()],
+            labels: [("site".to_string(), "osaka".to_string()), ("role".to_string(), "build".to_string())].into(),
+        }
+    }
+
+    async fn export(samples: Vec<HistorySample>, columns: &str, format: ExportFormat) -> String {
+        let columns = parse_columns(Some(columns)).unwrap();
+        encode(stream::iter(samples), columns, format).collect::<Vec<_>>().await.concat()
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!(parse_columns(None).unwrap(), DEFAULT_COLUMNS.to_vec());
+        assert_eq!(parse_columns(Some("ram, cpu,ram")).unwrap(), vec![Column::Ram, Column::Cpu]);
+        assert!(parse_columns(Some("cpu,disk")).unwrap_err().contains("available: cpu, cpu_cores"));
+    }
+
+    #[tokio::test]
+    async fn test_csv_header() {
+        let csv = export(
+            vec![sample(5, &[10.0, 30.0]), sample(6, &[20.0, 40.0])],
+            "cpu,cpu_cores,storages",
+            ExportFormat::Csv,
+        )
+        .await;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"time,cpu,cpu0,cpu1,"storage[C:\, data]_used","storage[C:\, data]_total","storage[C:\, data]_percent""#,
+                "2024-01-02T03:04:05+00:00,20.00,10.00,30.00,150,200,75.00",
+                "2024-01-02T03:04:06+00:00,30.00,20.00,40.00,150,200,75.00",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_csv_starts_a_new_header_when_hardware_changes() {
+        let mut with_disk = sample(7, &[40.0]);
+        with_disk.data.storages.push(Storage { name: Some("D:".to_string()), free: 0, total: 10 });
+        let csv = export(
+            vec![sample(5, &[10.0, 30.0]), sample(6, &[40.0]), with_disk, sample(8, &[50.0])],
+            "cpu_cores,storages",
+            ExportFormat::Csv,
+        )
+        .await;
+        let lines: Vec<&str> = csv.lines().collect();
+        let storage = r#""storage[C:\, data]_used","storage[C:\, data]_total","storage[C:\, data]_percent""#;
+        assert_eq!(
+            lines,
+            [
+                format!("time,cpu0,cpu1,{}", storage).as_str(),
+                "2024-01-02T03:04:05+00:00,10.00,30.00,150,200,75.00",
+                "",
+                &format!("time,cpu0,{}", storage),
+                "2024-01-02T03:04:06+00:00,40.00,150,200,75.00",
+                "",
+                &format!("time,cpu0,{},storage[D:]_used,storage[D:]_total,storage[D:]_percent", storage),
+                "2024-01-02T03:04:07+00:00,40.00,150,200,75.00,10,10,100.00",
+                "",
+                &format!("time,cpu0,{}", storage),
+                "2024-01-02T03:04:08+00:00,50.00,150,200,75.00",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tags_and_labels() {
+        let csv = export(vec![sample(5, &[10.0])], "cpu,tags,labels", ExportFormat::Csv).await;
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            ["time,cpu,tags,labels", r#"2024-01-02T03:04:05+00:00,10.00,"gpu,ci","role=build,site=osaka""#]
+        );
+
+        let ndjson = export(vec![sample(5, &[10.0])], "tags,labels", ExportFormat::Ndjson).await;
+        let row: Value = serde_json::from_str(ndjson.trim()).unwrap();
+        assert_eq!(row["tags"], json!(["gpu", "ci"]));
+        assert_eq!(row["labels"], json!({ "role": "build", "site": "osaka" }));
+    }
+
+    #[tokio::test]
+    async fn test_ndjson() {
+        let ndjson = export(vec![sample(5, &[10.0, 30.0]), sample(6, &[20.0])], "cpu_cores,ram,swap", ExportFormat::Ndjson)
+            .await;
+        let rows: Vec<Value> = ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["time"], "2024-01-02T03:04:05Z");
+        assert_eq!(rows[0]["cpu_cores"], json!([10.0, 30.0]));
+        assert_eq!(rows[0]["ram"], json!({ "used": 75, "total": 100, "percent": 75.0 }));
+        assert_eq!(rows[0]["swap"]["percent"], Value::Null);
+        assert!(rows[0].get("cpu").is_none());
+    }
+}
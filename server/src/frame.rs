@@ -0,0 +1,78 @@
+use axum::body::Bytes;
+use axum::extract::ws::{Message, Utf8Bytes};
+use pc_status_shared::{ServerMessage, WireFormat};
+use std::sync::{Arc, OnceLock};
+use tracing::error;
+
+/// 全接続で共有する送信メッセージ
+///
+/// エンコード結果は形式ごとに最初の送信時に一度だけ作成し、以降の接続は
+/// 参照カウント付きのバッファを使い回す。
+#[derive(Debug)]
+pub struct SharedMessage {
+    message: ServerMessage,
+    /// 特定のテナントだけに配信するメッセージのテナント（ホストの接続通知など）
+    tenant: Option<String>,
+    json: OnceLock<Option<Utf8Bytes>>,
+    msgpack: OnceLock<Option<Bytes>>,
+}
+
+impl SharedMessage {
+    pub fn new(message: ServerMessage) -> Arc<Self> {
+        Arc::new(Self {
+            message,
+            tenant: None,
+            json: OnceLock::new(),
+            msgpack: OnceLock::new(),
+        })
+    }
+
+    /// 指定したテナントを閲覧できる接続だけに配信するメッセージ
+    pub fn for_tenant(message: ServerMessage, tenant: &str) -> Arc<Self> {
+        Arc::new(Self {
+            message,
+            tenant: Some(tenant.to_string()),
+            json: OnceLock::new(),
+            msgpack: OnceLock::new(),
+        })
+    }
+
+    pub fn message(&self) -> &ServerMessage {
+        &self.message
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// JSONにエンコードしたメッセージ（エンコードに失敗した場合はNone）
+    pub fn json(&self) -> Option<Utf8Bytes> {
+        self.json
+            .get_or_init(|| match self.message.to_json() {
+                Ok(json) => Some(json.into()),
+                Err(e) => {
+                    error!("Failed to serialize broadcast message: {}", e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    /// 指定された形式のWebSocketフレームを返す（エンコードに失敗した場合はNone）
+    pub fn frame(&self, format: WireFormat) -> Option<Message> {
+        match format {
+            WireFormat::Json => self.json().map(Message::Text),
+            WireFormat::MessagePack => self
+                .msgpack
+                .get_or_init(|| match self.message.encode(format) {
+                    Ok(bytes) => Some(bytes.into()),
+                    Err(e) => {
+                        error!("Failed to serialize broadcast message: {}", e);
+                        None
+                    }
+                })
+                .clone()
+                .map(Message::Binary),
+        }
+    }
+}
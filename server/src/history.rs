@@ -0,0 +1,178 @@
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use pc_status_shared::{ClientData, HistorySample};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::error;
+
+use crate::archive::HistoryArchive;
+use crate::tenants::ViewerScope;
+
+/// 1台分の履歴
+struct HostHistory {
+    hostname: String,
+    index: u32,
+    tenant: String,
+    samples: VecDeque<HistorySample>,
+}
+
+/// REST API向けの時刻付き履歴
+///
+/// 閲覧者に配信する直近10件の`histories`とは別に、保持期間内のサンプルを
+/// エージェントIDごとに保持する。切断したエージェントの履歴も保持期間が
+/// 過ぎるまでは残す。`archive`を指定した場合は保持期間を超える書き出し用に
+/// ファイルにも保存する。
+pub struct HistoryStore {
+    hosts: RwLock<HashMap<String, HostHistory>>,
+    retention: Duration,
+    archive: Option<HistoryArchive>,
+}
+
+/// 指定されたホストがエージェントを指しているか
+///
+/// エージェントID、ホスト名（大文字小文字を区別しない）、
+/// 2台目以降は`hostname#index`のいずれでも指定できる。
+pub fn host_matches(query: &str, id: &str, hostname: &str, index: u32) -> bool {
+    if query == id {
+        return true;
+    }
+    match query.rsplit_once('#') {
+        Some((name, number)) if number.parse() == Ok(index) => name.eq_ignore_ascii_case(hostname),
+        _ => index == 0 && query.eq_ignore_ascii_case(hostname),
+    }
+}
+
+impl HistoryStore {
+    pub fn new(retention: Duration, archive: Option<HistoryArchive>) -> Arc<Self> {
+        Arc::new(Self {
+            hosts: RwLock::new(HashMap::new()),
+            retention,
+            archive,
+        })
+    }
+
+    /// 接続中の全エージェントのサンプルを記録し、保持期間を過ぎたものを捨てる
+    pub async fn record(&self, clients: &ClientData, now: DateTime<Utc>) {
+        if let Some(archive) = &self.archive
+            && let Err(e) = archive.append(clients, now).await
+        {
+            error!("Failed to archive history: {}", e);
+        }
+
+        let mut hosts = self.hosts.write().await;
+        for (id, status) in clients {
+            let host = hosts.entry(id.clone()).or_insert_with(|| HostHistory {
+                hostname: status.hostname.clone(),
+                index: status.index,
+                tenant: status.tenant.clone(),
+                samples: VecDeque::new(),
+            });
+            host.hostname.clone_from(&status.hostname);
+            host.index = status.index;
+            host.tenant.clone_from(&status.tenant);
+            host.samples.push_back(HistorySample::new(now, status));
+        }
+
+        let oldest = now - self.retention;
+        hosts.retain(|_, host| {
+            while host.samples.front().is_some_and(|sample| sample.time < oldest) {
+                host.samples.pop_front();
+            }
+            !host.samples.is_empty()
+        });
+    }
+
+    /// `scope`で閲覧できるホストを探す
+    fn find<'a>(
+        hosts: &'a HashMap<String, HostHistory>,
+        host: &str,
+        scope: &ViewerScope,
+    ) -> Option<(&'a String, &'a HostHistory)> {
+        hosts.iter().find(|(id, history)| {
+            scope.allows(&history.tenant) && host_matches(host, id, &history.hostname, history.index)
+        })
+    }
+
+    /// `since`以降のサンプル（古い順、ホストが見つからない場合はNone）
+    pub async fn samples(&self, host: &str, since: DateTime<Utc>, scope: &ViewerScope) -> Option<Vec<HistorySample>> {
+        let hosts = self.hosts.read().await;
+        let (_, history) = Self::find(&hosts, host, scope)?;
+        Some(history.samples.iter().filter(|sample| sample.time >= since).cloned().collect())
+    }
+
+    /// 書き出し用に`from`から`to`までのサンプルを古い順に返す（ホストが見つからない場合はNone）
+    ///
+    /// ファイルに保存している場合はファイルから順に読み、そうでなければ
+    /// メモリ上の履歴を返す。
+    pub async fn export(
+        &self,
+        host: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        scope: &ViewerScope,
+    ) -> std::io::Result<Option<BoxStream<'static, HistorySample>>> {
+        let Some(archive) = &self.archive else {
+            let samples = self.samples(host, from, scope).await.map(|samples| {
+                let samples = samples.into_iter().filter(move |sample| sample.time <= to);
+                stream::iter(samples).boxed()
+            });
+            return Ok(samples);
+        };
+
+        let connected = Self::find(&*self.hosts.read().await, host, scope).map(|(id, _)| id.clone());
+        let id = match connected {
+            Some(id) => id,
+            None => match archive.find_host(host, scope).await {
+                Some(id) => id,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(archive.samples(&id, from, to)?.boxed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pc_status_shared::{test_support, Cpu, CpuData, StatusData};
+
+    fn status(hostname: &str, index: u32, cpu: f64) -> StatusData {
+        StatusData {
+            cpu: Cpu { model: "cpu".to_string(), cpus: vec![CpuData { cpu }] },
+            index,
+            ..test_support::status(hostname)
+        }
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("machine-1", "machine-1", "web", 0));
+        assert!(host_matches("WEB", "machine-1", "web", 0));
+        assert!(!host_matches("web", "machine-2", "web", 1));
+        assert!(host_matches("web#1", "machine-2", "web", 1));
+        assert!(!host_matches("web#2", "machine-2", "web", 1));
+    }
+
+    #[tokio::test]
+    async fn test_record_and_expire() {
+        let store = HistoryStore::new(Duration::seconds(60), None);
+        let start = Utc::now();
+        let web: ClientData = [("a".to_string(), status("web", 0, 10.0))].into_iter().collect();
+        let db: ClientData = [("b".to_string(), status("db", 0, 20.0))].into_iter().collect();
+
+        store.record(&web, start).await;
+        store.record(&db, start + Duration::seconds(30)).await;
+        store.record(&web, start + Duration::seconds(40)).await;
+
+        assert_eq!(store.samples("web", start, &ViewerScope::All).await.unwrap().len(), 2);
+        assert_eq!(store.samples("web", start + Duration::seconds(1), &ViewerScope::All).await.unwrap().len(), 1);
+        assert!(store.samples("cache", start, &ViewerScope::All).await.is_none());
+        assert!(store.samples("web", start, &ViewerScope::none()).await.is_none());
+
+        // 最初のサンプルは保持期間を過ぎ、dbは最後のサンプルも過ぎたため消える
+        store.record(&web, start + Duration::seconds(100)).await;
+        assert_eq!(store.samples("web", start, &ViewerScope::All).await.unwrap().len(), 2);
+        assert!(store.samples("db", start, &ViewerScope::All).await.is_none());
+    }
+}
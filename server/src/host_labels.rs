@@ -0,0 +1,112 @@
+use anyhow::{bail, Context, Result};
+use pc_status_shared::{glob_match, StatusData};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// サーバー側でホストに付けるタグ・ラベル
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostLabelRule {
+    /// 対象のホスト名（`*`をワイルドカードとして使用可能、空の場合はすべて）
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// 追加するタグ
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 設定するラベル（エージェントが同じキーを送った場合も上書きする）
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl HostLabelRule {
+    fn validate(&self) -> Result<()> {
+        if self.tags.is_empty() && self.labels.is_empty() {
+            bail!("Host label rule for {:?} has neither tags nor labels", self.hosts);
+        }
+        if self.labels.keys().any(|key| key.trim().is_empty()) {
+            bail!("Host label rule for {:?} has an empty label name", self.hosts);
+        }
+        Ok(())
+    }
+
+    fn matches(&self, hostname: &str) -> bool {
+        self.hosts.is_empty() || self.hosts.iter().any(|pattern| glob_match(pattern, hostname))
+    }
+}
+
+/// ホスト名に応じてタグ・ラベルを付ける
+#[derive(Debug, Clone, Default)]
+pub struct HostLabels {
+    rules: Vec<HostLabelRule>,
+}
+
+impl HostLabels {
+    pub fn new(rules: Vec<HostLabelRule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// 一致するルールのタグ・ラベルを適用する（後のルールほど優先）
+    pub fn apply(&self, status: &mut StatusData) {
+        for rule in self.rules.iter().filter(|rule| rule.matches(&status.hostname)) {
+            for tag in &rule.tags {
+                if !status.tags.contains(tag) {
+                    status.tags.push(tag.clone());
+                }
+            }
+            status.labels.extend(rule.labels.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+    }
+}
+
+/// JSONファイルからルールを読み込む
+pub fn load_host_labels(path: &Path) -> Result<HostLabels> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read host labels from {}", path.display()))?;
+    let rules: Vec<HostLabelRule> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse host labels in {}", path.display()))?;
+    for rule in &rules {
+        rule.validate()?;
+    }
+    Ok(HostLabels::new(rules))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pc_status_shared::test_support;
+
+    fn status(hostname: &str) -> StatusData {
+        StatusData {
+            tags: vec!["ci".to_string()],
+            labels: [("site".to_string(), "osaka".to_string()), ("role".to_string(), "ci".to_string())].into(),
+            ..test_support::status(hostname)
+        }
+    }
+
+    #[test]
+    fn test_server_labels_override_agent_labels() {
+        let rules: Vec<HostLabelRule> = serde_json::from_str(
+            r#"[
+                {"hosts": ["gpu-*"], "tags": ["gpu", "ci"], "labels": {"site": "tokyo"}},
+                {"tags": ["managed"]}
+            ]"#,
+        )
+        .unwrap();
+        let labels = HostLabels::new(rules);
+
+        let mut gpu = status("GPU-1");
+        labels.apply(&mut gpu);
+        assert_eq!(gpu.tags, vec!["ci", "gpu", "managed"]);
+        assert_eq!(gpu.labels["site"], "tokyo");
+        assert_eq!(gpu.labels["role"], "ci");
+
+        let mut web = status("web-1");
+        labels.apply(&mut web);
+        assert_eq!(web.tags, vec!["ci", "managed"]);
+        assert_eq!(web.labels["site"], "osaka");
+    }
+}
@@ -1,8 +1,11 @@
 use axum::{
-    routing::get,
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
+use std::net::IpAddr;
 use std::path::Path;
+use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::{
     cors::{CorsLayer, Any},
@@ -10,9 +13,22 @@ use tower_http::{
 };
 use tracing::{info, warn};
 
+use crate::admin::{self, AdminState};
+use crate::alerts::AlertEngine;
+use crate::api::{self, ApiState};
+use crate::history::HistoryStore;
+use crate::rate_limit::{self, AuthGuard};
+use crate::releases::Releases;
+use crate::sse;
 use crate::websocket::WebSocketServer;
 
-pub fn create_http_server(ws_server: WebSocketServer) -> Router {
+pub fn create_http_server(
+    ws_server: WebSocketServer,
+    alert_engine: Arc<AlertEngine>,
+    history: Arc<HistoryStore>,
+    admin_token: Option<String>,
+    releases: Option<Arc<Releases>>,
+) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -21,11 +37,63 @@ pub fn create_http_server(ws_server: WebSocketServer) -> Router {
     // フロントエンドの静的ファイルディレクトリを検索
     let static_dir = find_frontend_directory();
 
-    let router = Router::new()
+    let api_state = ApiState {
+        client_manager: ws_server.get_client_manager(),
+        history,
+        alert_engine,
+        tenants: ws_server.get_tenants(),
+        audit: ws_server.get_audit_log(),
+        auth_guard: ws_server.get_auth_guard(),
+    };
+    let limits = ws_server.get_limits();
+    let trusted_proxies: Arc<[IpAddr]> = limits.trusted_proxies.clone().into();
+    let admin_state = AdminState {
+        ws_server: ws_server.clone(),
+        token: admin_token.map(Arc::from),
+        audit: ws_server.get_audit_log(),
+        auth_guard: Arc::new(AuthGuard::new(&limits)),
+        releases: releases.clone(),
+    };
+
+    let mut router = Router::new()
         .route("/ws", get(WebSocketServer::handle_websocket_upgrade))
         .route("/server", get(WebSocketServer::handle_websocket_upgrade))
+        .route("/api/events", get(sse::handle_events))
         .with_state(ws_server)
-        .layer(ServiceBuilder::new().layer(cors));
+        .merge(
+            Router::new()
+                .route("/api/alerts", get(api::list_alerts))
+                .route("/api/hosts", get(api::list_hosts))
+                .route("/api/hosts/{host}", get(api::get_host))
+                .route("/api/hosts/{host}/history", get(api::get_history))
+                .route("/api/hosts/{host}/export", get(api::export_history))
+                .with_state(api_state),
+        )
+        .merge(
+            Router::new()
+                .route("/api/admin/sessions", get(admin::list_sessions))
+                .route("/api/admin/sessions/{id}", delete(admin::disconnect_session))
+                .route("/api/admin/bans", get(admin::list_bans).post(admin::add_ban).delete(admin::remove_ban))
+                .route("/api/admin/toast", post(admin::send_toast))
+                .route("/api/admin/agents/{id}/commands", post(admin::run_command))
+                .route("/api/admin/versions", get(admin::list_versions))
+                .route(
+                    "/api/admin/rollout",
+                    get(admin::get_rollout).put(admin::update_rollout).delete(admin::cancel_rollout),
+                )
+                .route("/api/admin/audit", get(admin::list_audit))
+                .with_state(admin_state),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(cors)
+                .layer(middleware::from_fn_with_state(trusted_proxies, rate_limit::resolve_client_addr)),
+        );
+
+    // エージェントの実行ファイルは署名で検証されるため、認証なしで配信する
+    if let Some(releases) = releases {
+        router = router.nest_service("/agent", ServeDir::new(releases.dir()));
+    }
 
     // 静的ファイルディレクトリが見つかった場合のみfallback_serviceを追加
     if let Some(dir) = static_dir {
@@ -1,22 +1,58 @@
+mod admin;
+mod alerts;
+mod api;
+mod archive;
+mod audit;
+mod bans;
 mod websocket;
+mod email;
+mod export;
 mod http_server;
+mod notification;
+mod rate_limit;
+mod releases;
+mod rollout;
+mod sse;
+mod webhook;
 mod client_manager;
+mod frame;
+mod history;
+mod host_labels;
+mod stats;
+mod status_stream;
+mod tenants;
 
 use anyhow::Result;
 use dotenvy::dotenv;
 use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing::{debug, info, warn};
-use tracing_subscriber;
 
+use crate::alerts::AlertEngine;
+use crate::archive::HistoryArchive;
+use crate::audit::AuditLog;
+use crate::email::{EmailNotifier, SmtpConfig};
 use crate::websocket::WebSocketServer;
 use crate::http_server::create_http_server;
-use crate::client_manager::ClientManager;
+use crate::client_manager::{ClientManager, ConflictPolicy};
+use crate::frame::SharedMessage;
+use crate::history::HistoryStore;
+use crate::tenants::Tenants;
+use crate::notification::Notification;
+use crate::releases::Releases;
+use crate::webhook::Webhook;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // 環境変数を読み込み
     dotenv().ok();
+
+    // rustlsのデフォルトCryptoProviderを初期化（Webhookの送信に使用）
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
     
     // ログ設定
     tracing_subscriber::fmt::init();
@@ -29,17 +65,177 @@ async fn main() -> Result<()> {
     let password = env::var("PASS")
         .expect("PASS environment variable must be set");
 
+    // ホスト名重複時のポリシー（reject/replace/multiple）
+    let conflict_policy = env::var("HOSTNAME_CONFLICT")
+        .ok()
+        .and_then(|value| match value.parse::<ConflictPolicy>() {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                warn!("{}, falling back to reject", e);
+                None
+            }
+        })
+        .unwrap_or(ConflictPolicy::Reject);
+
+    // 接続を受け付けるエージェントの最小プロトコルバージョン
+    let min_agent_protocol = env::var("MIN_AGENT_PROTOCOL")
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(pc_status_shared::LEGACY_PROTOCOL_VERSION)
+        .min(pc_status_shared::PROTOCOL_VERSION);
+
+    // 同時に接続できるエージェント数の上限（未設定は無制限）
+    let max_agents = env::var("MAX_AGENTS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|&limit| limit > 0);
+
+    // REST APIで返す履歴の保持期間（秒）
+    let history_retention = env::var("HISTORY_RETENTION_SECS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(3600);
+
+    // 書き出し用に履歴を保存するディレクトリと保存日数（未設定の場合は保存しない）
+    let history_dir = env::var("HISTORY_DIR").ok().filter(|dir| !dir.is_empty());
+    let history_keep_days = env::var("HISTORY_KEEP_DAYS")
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(30);
+
+    // アラートルール（未設定の場合はアラートを無効にする）
+    let alert_rules = match env::var("ALERT_RULES_FILE") {
+        Ok(path) => alerts::load_rules(std::path::Path::new(&path))?,
+        Err(_) => vec![],
+    };
+
+    // サーバー側でホストに付けるタグ・ラベル（未設定の場合はエージェントが送ったものだけ）
+    let host_labels = match env::var("HOST_LABELS_FILE") {
+        Ok(path) => host_labels::load_host_labels(std::path::Path::new(&path))?,
+        Err(_) => host_labels::HostLabels::default(),
+    };
+
+    // テナントごとのエージェントのパスワードと閲覧者のトークン（未設定の場合はPASSのみ）
+    let tenants = match env::var("TENANTS_FILE") {
+        Ok(path) => Tenants::new(password, tenants::load_tenants(std::path::Path::new(&path))?)?,
+        Err(_) => Tenants::single(password),
+    };
+
+    // 監査ログを保存するファイル（未設定の場合は直近の記録だけをメモリ上に残す）
+    let audit_log_file = env::var("AUDIT_LOG_FILE").ok().filter(|path| !path.is_empty());
+    let audit_log_max_bytes = env::var("AUDIT_LOG_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|&bytes| bytes > 0)
+        .unwrap_or(10 * 1024 * 1024);
+    let audit_log_keep = env::var("AUDIT_LOG_KEEP")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(5);
+
+    // 接続・認証・メッセージの制限
+    let limits = rate_limit::Limits::from_env();
+
+    // 管理APIのトークン（未設定の場合は管理APIを無効にする）
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+
+    // エージェントに配布する実行ファイルのディレクトリ（未設定の場合は配布しない）
+    let releases = env::var("AGENT_RELEASES_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Arc::new(Releases::new(dir.into())));
+
+    // 通知を送るWebhook（未設定の場合は送信しない）
+    let webhooks = match env::var("WEBHOOKS_FILE") {
+        Ok(path) => webhook::load_webhooks(std::path::Path::new(&path))?,
+        Err(_) => vec![],
+    };
+
+    // メール通知（SMTP_HOSTが未設定の場合は送信しない）
+    let smtp_config = SmtpConfig::from_env()?;
+    let rule_recipients = alert_rules
+        .iter()
+        .map(|rule| Ok((rule.name.clone(), email::parse_mailboxes(rule.recipients.iter().map(String::as_str))?)))
+        .collect::<Result<std::collections::HashMap<_, _>>>()?;
+
     info!("Starting PC Status Server on port {}", port);
+    info!("Hostname conflict policy: {:?}", conflict_policy);
+    info!("Protocol version: {} (minimum agent version: {})", pc_status_shared::PROTOCOL_VERSION, min_agent_protocol);
+    if let Some(limit) = max_agents {
+        info!("Maximum agents: {}", limit);
+    }
+    info!("History retention: {}s", history_retention);
+    if let Some(dir) = &history_dir {
+        info!("Archiving history to {} for {} days", dir, history_keep_days);
+    }
+    if tenants.is_isolated() {
+        info!("Tenants: {}", tenants.names().join(", "));
+    }
+    info!("Admin API: {}", if admin_token.is_some() { "enabled" } else { "disabled" });
+    if let Some(releases) = &releases {
+        info!("Agent releases: {}", releases.dir().display());
+    }
+    if let Some(path) = &audit_log_file {
+        info!("Audit log: {} (rotate at {} bytes, keep {})", path, audit_log_max_bytes, audit_log_keep);
+    }
+    info!(
+        "Rate limits: {}/min connections, {}/min auth attempts, lockout after {} failures for {}s",
+        limits.connections_per_minute,
+        limits.auth_attempts_per_minute,
+        limits.max_auth_failures,
+        limits.lockout.as_secs()
+    );
+    info!("Message limits: {} bytes, {} syncs/s per agent", limits.max_message_bytes, limits.syncs_per_sec);
+    info!("Host label rules: {}", host_labels.len());
+    info!("Alert rules: {}", alert_rules.len());
+    info!("Webhooks: {}", webhooks.len());
+    if let Some(config) = &smtp_config {
+        info!("Email notifications via {} ({:?})", config.host, config.security);
+    }
 
     // クライアント管理を初期化
-    let client_manager = ClientManager::new();
+    // 切断されたエージェントの履歴もREST APIの履歴と同じ期間だけ引き継ぐ
+    let client_manager =
+        ClientManager::new(conflict_policy, max_agents, std::time::Duration::from_secs(history_retention as u64));
     
+    let audit = match audit_log_file {
+        Some(path) => AuditLog::with_file(path.into(), audit_log_max_bytes, audit_log_keep)?,
+        None => AuditLog::new(),
+    };
+
     // WebSocketサーバーを初期化
-    let ws_server = WebSocketServer::new(client_manager.clone(), password);
+    let ws_server = WebSocketServer::new(
+        client_manager.clone(),
+        Arc::new(tenants),
+        min_agent_protocol,
+        host_labels,
+        audit,
+        limits,
+    );
+
+    let alert_engine = AlertEngine::new(alert_rules);
+    let archive = history_dir
+        .map(|dir| HistoryArchive::new(dir.into(), history_keep_days))
+        .transpose()?;
+    let history = HistoryStore::new(chrono::Duration::seconds(history_retention), archive);
+
+    // 通知の送信先を開始
+    let notifier = ws_server.get_notifier();
+    for config in webhooks {
+        Webhook::new(config)?.spawn(notifier.subscribe());
+    }
+    if let Some(config) = smtp_config {
+        EmailNotifier::new(config, rule_recipients)?.spawn(notifier.subscribe());
+    }
 
     // 定期的なデータ送信タスクを開始
     let broadcast_sender = ws_server.get_broadcast_sender();
+    let status_stream = ws_server.get_status_stream();
+    let viewer_stats = ws_server.get_viewer_stats();
     let client_manager_clone = client_manager.clone();
+    let alert_engine_clone = alert_engine.clone();
+    let history_clone = history.clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
         let mut broadcast_count = 0u64;
@@ -50,19 +246,41 @@ async fn main() -> Result<()> {
 
             // すべてのクライアントデータを取得してブロードキャスト
             let clients = client_manager_clone.get_all_clients().await;
+
+            // 差分購読者向けに変更点を配信
+            status_stream.publish(clients.clone()).await;
+
+            // REST API向けに時刻付きの履歴を記録
+            history_clone.record(&clients, chrono::Utc::now()).await;
+
+            // アラートルールを評価し、発報・解消を通知
+            for alert in alert_engine_clone.evaluate(&clients, chrono::Utc::now()).await {
+                notifier.notify(Notification::Alert(alert.clone()));
+                let message = SharedMessage::new(pc_status_shared::ServerMessage::Alert(alert));
+                if let Err(e) = broadcast_sender.send(message) {
+                    warn!("Failed to broadcast alert: {}", e);
+                }
+            }
+
             if !clients.is_empty() {
                 broadcast_count += 1;
                 debug!("Broadcasting status data for {} clients (count: {})", clients.len(), broadcast_count);
 
                 // 10秒ごとに統計情報をログ出力
-                if broadcast_count % 10 == 0 {
+                if broadcast_count.is_multiple_of(10) {
                     let elapsed = start_time.elapsed();
                     let avg_interval = elapsed.as_millis() as f64 / broadcast_count as f64;
                     info!("Broadcast stats: {} messages in {:.2}s (avg: {:.1}ms interval)",
                           broadcast_count, elapsed.as_secs_f64(), avg_interval);
+
+                    let viewers = viewer_stats.snapshot();
+                    if viewers.lagging > 0 || viewers.lag_events > 0 {
+                        warn!("Viewer stats: {} lagging now, {} lag events, {} messages dropped, {} slow disconnects",
+                              viewers.lagging, viewers.lag_events, viewers.dropped_messages, viewers.disconnected);
+                    }
                 }
 
-                let message = pc_status_shared::ServerMessage::Status(clients);
+                let message = SharedMessage::new(pc_status_shared::ServerMessage::Status(clients));
                 if let Err(e) = broadcast_sender.send(message) {
                     warn!("Failed to broadcast status: {}", e);
                 }
@@ -71,12 +289,13 @@ async fn main() -> Result<()> {
     });
 
     // HTTPサーバーとWebSocketサーバーを統合
-    let app = create_http_server(ws_server);
+    let app = create_http_server(ws_server, alert_engine, history, admin_token, releases);
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     info!("Server listening on http://0.0.0.0:{}", port);
 
-    axum::serve(listener, app).await?;
+    // 管理APIと監査ログのために接続元のアドレスを取得する
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
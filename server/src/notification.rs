@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use pc_status_shared::{Alert, AlertState};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// 通知キューの容量（これを超えて遅れた送信先は古い通知を読み飛ばす）
+const NOTIFICATION_CAPACITY: usize = 256;
+
+/// 外部に通知するイベント
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    Connected {
+        host: String,
+        hostname: String,
+        time: DateTime<Utc>,
+    },
+    Disconnected {
+        host: String,
+        hostname: String,
+        time: DateTime<Utc>,
+    },
+    Alert(Alert),
+}
+
+impl Notification {
+    /// 送信先の`events`で指定する種類名
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connected { .. } => "connected",
+            Self::Disconnected { .. } => "disconnected",
+            Self::Alert(_) => "alert",
+        }
+    }
+
+    pub fn hostname(&self) -> &str {
+        match self {
+            Self::Connected { hostname, .. } | Self::Disconnected { hostname, .. } => hostname,
+            Self::Alert(alert) => &alert.hostname,
+        }
+    }
+
+    /// イベントが発生した時刻
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Self::Connected { time, .. } | Self::Disconnected { time, .. } => *time,
+            Self::Alert(alert) => alert.since,
+        }
+    }
+
+    /// 人が読むための一行の説明
+    pub fn message(&self) -> String {
+        match self {
+            Self::Connected { hostname, .. } => format!("{} is connected", hostname),
+            Self::Disconnected { hostname, .. } => format!("{} is disconnected", hostname),
+            Self::Alert(alert) => match alert.state {
+                AlertState::Firing => format!("[FIRING] {}", alert.message),
+                AlertState::Resolved => format!("[RESOLVED] {}", alert.message),
+                AlertState::Pending => format!("[PENDING] {}", alert.message),
+            },
+        }
+    }
+
+    /// テンプレートで使用できる変数
+    pub fn variables(&self) -> Vec<(&'static str, String)> {
+        let mut variables = vec![
+            ("event", self.kind().to_string()),
+            ("message", self.message()),
+            ("hostname", self.hostname().to_string()),
+        ];
+        match self {
+            Self::Connected { host, .. } | Self::Disconnected { host, .. } => {
+                variables.push(("host", host.clone()));
+                variables.push(("time", self.time().to_rfc3339()));
+            }
+            Self::Alert(alert) => {
+                variables.push(("host", alert.host.clone()));
+                variables.push(("time", self.time().to_rfc3339()));
+                variables.push(("rule", alert.rule.clone()));
+                variables.push(("state", format!("{:?}", alert.state).to_lowercase()));
+                variables.push(("value", format!("{:.1}", alert.value)));
+                variables.push(("threshold", alert.threshold.to_string()));
+            }
+        }
+        variables
+    }
+}
+
+/// 通知を各送信先に配る
+#[derive(Clone)]
+pub struct Notifier {
+    tx: broadcast::Sender<Notification>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(NOTIFICATION_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn notify(&self, notification: Notification) {
+        // 送信先が設定されていない場合は誰も受信しない
+        if self.tx.send(notification).is_err() {
+            debug!("No notification sinks configured");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
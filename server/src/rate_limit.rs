@@ -0,0 +1,391 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+
+/// 接続元ごとの記録がこの数を超えたら古いものを削除する
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+/// 接続・認証・メッセージの制限
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// 接続元ごとの1分あたりの接続数（0は無制限）
+    pub connections_per_minute: u32,
+    /// 接続元ごとの1分あたりの認証の試行回数（0は無制限）
+    pub auth_attempts_per_minute: u32,
+    /// 締め出すまでの認証の連続失敗回数（0は締め出さない）
+    pub max_auth_failures: u32,
+    /// 締め出す時間
+    pub lockout: Duration,
+    /// 受信する1メッセージの最大サイズ（バイト）
+    pub max_message_bytes: usize,
+    /// エージェントごとの1秒あたりのSync数（超えた分は破棄する、0は無制限）
+    pub syncs_per_sec: u32,
+    /// X-Forwarded-Forを信頼するリバースプロキシのアドレス（空の場合は直接の接続元を使う）
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            connections_per_minute: 60,
+            auth_attempts_per_minute: 20,
+            max_auth_failures: 10,
+            lockout: Duration::from_secs(300),
+            max_message_bytes: 1024 * 1024,
+            syncs_per_sec: 2,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Limits {
+    /// 環境変数から読み込む（未設定の値は既定値）
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            env::var(name).ok().and_then(|value| value.parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            connections_per_minute: var("RATE_LIMIT_CONNECTIONS").unwrap_or(default.connections_per_minute),
+            auth_attempts_per_minute: var("RATE_LIMIT_AUTH_ATTEMPTS").unwrap_or(default.auth_attempts_per_minute),
+            max_auth_failures: var("AUTH_MAX_FAILURES").unwrap_or(default.max_auth_failures),
+            lockout: var("AUTH_LOCKOUT_SECS").map(Duration::from_secs).unwrap_or(default.lockout),
+            max_message_bytes: var("MAX_MESSAGE_BYTES")
+                .filter(|&bytes| bytes > 0)
+                .unwrap_or(default.max_message_bytes),
+            syncs_per_sec: var("MAX_SYNCS_PER_SEC").unwrap_or(default.syncs_per_sec),
+            trusted_proxies: env::var("TRUSTED_PROXIES").map(|value| parse_proxies(&value)).unwrap_or_default(),
+        }
+    }
+}
+
+/// カンマ区切りのアドレスを読む（読めないものは警告して無視する）
+fn parse_proxies(value: &str) -> Vec<IpAddr> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.parse::<IpAddr>() {
+            Ok(ip) => Some(ip.to_canonical()),
+            Err(_) => {
+                warn!("Ignoring invalid address in TRUSTED_PROXIES: {}", entry);
+                None
+            }
+        })
+        .collect()
+}
+
+/// 要求の接続元のアドレス
+///
+/// 信頼するプロキシからの要求だけX-Forwarded-Forを右から見て、最初の信頼しないアドレスを接続元とする。
+/// それより左はクライアントが自由に書けるため使わない。
+pub fn client_addr(peer: SocketAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> SocketAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.contains(&ip.to_canonical());
+    if !is_trusted(peer.ip()) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    for entry in forwarded.into_iter().rev() {
+        match entry.parse::<IpAddr>() {
+            Ok(ip) if is_trusted(ip) => continue,
+            Ok(ip) => return SocketAddr::new(ip.to_canonical(), 0),
+            Err(_) => break,
+        }
+    }
+    peer
+}
+
+/// 信頼するプロキシを経由した要求の`ConnectInfo`を実際の接続元に置き換えるミドルウェア
+///
+/// 禁止・接続数の制限・認証の締め出し・監査ログはすべて`ConnectInfo`の接続元を使う。
+pub async fn resolve_client_addr(
+    State(trusted_proxies): State<Arc<[IpAddr]>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !trusted_proxies.is_empty()
+        && let Some(&ConnectInfo(peer)) = request.extensions().get::<ConnectInfo<SocketAddr>>()
+    {
+        let client = client_addr(peer, request.headers(), &trusted_proxies);
+        request.extensions_mut().insert(ConnectInfo(client));
+    }
+    next.run(request).await
+}
+
+/// トークンバケット（`capacity`回まで連続で許可し、1秒に`per_sec`回分ずつ回復する）
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, per_sec: f64, now: Instant) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        Self { capacity, per_sec, tokens: capacity, updated: now }
+    }
+
+    /// 1回分を使う（残っていない場合はfalse）
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.per_sec >= self.capacity
+    }
+}
+
+/// 接続元ごとに1分あたりの回数を制限する
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// `per_minute`が0の場合は制限しない
+    pub fn new(per_minute: u32) -> Self {
+        Self { per_minute, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// 1回分を使う（上限を超えた場合はfalse）
+    pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        if buckets.len() >= MAX_TRACKED_ADDRESSES {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.per_minute, f64::from(self.per_minute) / 60.0, now))
+            .try_take(now)
+    }
+}
+
+/// 接続元ごとの認証の連続失敗
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// 認証の試行回数を制限し、失敗が続いた接続元を一時的に締め出す
+#[derive(Debug)]
+pub struct AuthGuard {
+    attempts: RateLimiter,
+    max_failures: u32,
+    lockout: Duration,
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl AuthGuard {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            attempts: RateLimiter::new(limits.auth_attempts_per_minute),
+            max_failures: limits.max_auth_failures,
+            lockout: limits.lockout,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 締め出し中の場合は残り時間
+    pub fn locked(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let failures = self.failures.lock().expect("auth guard lock poisoned");
+        let locked_until = failures.get(&ip)?.locked_until?;
+        (locked_until > now).then(|| locked_until - now)
+    }
+
+    /// 認証を試みてよいか（締め出し中または試行回数の上限を超えた場合は待つべき時間）
+    pub fn attempt(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if let Some(remaining) = self.locked(ip, now) {
+            return Err(remaining);
+        }
+        if !self.attempts.check(ip, now) {
+            return Err(Duration::from_secs(60));
+        }
+        Ok(())
+    }
+
+    /// 失敗を記録する（この失敗で締め出しを始めた場合はtrue）
+    pub fn failure(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.max_failures == 0 {
+            return false;
+        }
+        let mut failures = self.failures.lock().expect("auth guard lock poisoned");
+        if failures.len() >= MAX_TRACKED_ADDRESSES {
+            let lockout = self.lockout;
+            failures.retain(|_, record| now.saturating_duration_since(record.last) < lockout);
+        }
+        let record = failures.entry(ip).or_insert(Failures { count: 0, last: now, locked_until: None });
+        // 締め出しの時間より前の失敗は数えない
+        if now.saturating_duration_since(record.last) >= self.lockout {
+            *record = Failures { count: 0, last: now, locked_until: None };
+        }
+        record.count += 1;
+        record.last = now;
+        if record.count >= self.max_failures {
+            record.count = 0;
+            record.locked_until = Some(now + self.lockout);
+            return true;
+        }
+        false
+    }
+
+    /// 認証の失敗を監査ログに記録して数える（締め出しを始めた場合はそれも記録する）
+    pub async fn record_failure(&self, audit: &AuditLog, event: AuditEvent) {
+        let remote = event.remote;
+        audit.record(event).await;
+        let Some(ip) = remote else {
+            return;
+        };
+        if self.failure(ip, Instant::now()) {
+            warn!("Locking out {} for {:?} after repeated authentication failures", ip, self.lockout);
+            let event = AuditEvent::new(AuditAction::Lockout, Some(ip)).detail(format!("{}s", self.lockout.as_secs()));
+            audit.record(event).await;
+        }
+    }
+
+    /// 成功した接続元の失敗回数を消す（締め出し中は消さない）
+    pub fn success(&self, ip: IpAddr, now: Instant) {
+        let mut failures = self.failures.lock().expect("auth guard lock poisoned");
+        if failures.get(&ip).is_some_and(|record| record.locked_until.is_none_or(|until| until <= now)) {
+            failures.remove(&ip);
+        }
+    }
+}
+
+/// 429 Too Many Requests（`Retry-After`に待つべき秒数を付ける）
+pub fn too_many_requests(retry: Duration) -> Response {
+    let secs = retry.as_secs().max(1);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, secs.to_string())],
+        format!("Too many requests, retry after {} seconds", secs),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1.0, start);
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        assert!(bucket.try_take(start + Duration::from_millis(1100)));
+    }
+
+    #[test]
+    fn test_rate_limiter_is_per_address() {
+        let now = Instant::now();
+        let limiter = RateLimiter::new(2);
+        assert!(limiter.check(ip("192.0.2.1"), now));
+        assert!(limiter.check(ip("192.0.2.1"), now));
+        assert!(!limiter.check(ip("192.0.2.1"), now));
+        assert!(limiter.check(ip("192.0.2.2"), now));
+        assert!(limiter.check(ip("192.0.2.1"), now + Duration::from_secs(30)));
+
+        let unlimited = RateLimiter::new(0);
+        assert!((0..1000).all(|_| unlimited.check(ip("192.0.2.1"), now)));
+    }
+
+    #[test]
+    fn test_lockout_after_repeated_failures() {
+        let limits = Limits { max_auth_failures: 3, lockout: Duration::from_secs(60), ..Default::default() };
+        let guard = AuthGuard::new(&limits);
+        let attacker = ip("192.0.2.1");
+        let now = Instant::now();
+
+        assert!(!guard.failure(attacker, now));
+        assert!(!guard.failure(attacker, now));
+        assert!(guard.failure(attacker, now));
+        assert_eq!(guard.attempt(attacker, now + Duration::from_secs(10)), Err(Duration::from_secs(50)));
+        assert!(guard.attempt(ip("192.0.2.2"), now).is_ok());
+        // 締め出し中に成功しても解除しない
+        guard.success(attacker, now);
+        assert!(guard.locked(attacker, now).is_some());
+        assert!(guard.attempt(attacker, now + Duration::from_secs(61)).is_ok());
+
+        // 成功すると失敗回数を数え直す
+        let user = ip("192.0.2.3");
+        guard.failure(user, now);
+        guard.failure(user, now);
+        guard.success(user, now);
+        assert!(!guard.failure(user, now));
+    }
+
+    #[test]
+    fn test_forwarded_for_is_used_only_from_trusted_proxies() {
+        let proxy: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let trusted = parse_proxies("10.0.0.1, 10.0.0.2, not-an-ip");
+        assert_eq!(trusted, vec![ip("10.0.0.1"), ip("10.0.0.2")]);
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", value.parse().unwrap());
+            headers
+        };
+
+        // プロキシが付け足した右端のアドレスを使い、クライアントが書いた左側は使わない
+        let forwarded = headers("192.0.2.99, 198.51.100.7");
+        assert_eq!(client_addr(proxy, &forwarded, &trusted).ip(), ip("198.51.100.7"));
+        // 多段のプロキシは読み飛ばす
+        let chained = headers("198.51.100.7, 10.0.0.2");
+        assert_eq!(client_addr(proxy, &chained, &trusted).ip(), ip("198.51.100.7"));
+        // 信頼しない接続元のX-Forwarded-Forは無視する
+        let direct: SocketAddr = "203.0.113.5:5000".parse().unwrap();
+        assert_eq!(client_addr(direct, &forwarded, &trusted), direct);
+        // ヘッダーがない・読めない場合はプロキシのアドレスのまま
+        assert_eq!(client_addr(proxy, &HeaderMap::new(), &trusted), proxy);
+        assert_eq!(client_addr(proxy, &headers("unknown"), &trusted), proxy);
+    }
+
+    #[test]
+    fn test_auth_attempts_are_rate_limited() {
+        let limits = Limits { auth_attempts_per_minute: 2, ..Default::default() };
+        let guard = AuthGuard::new(&limits);
+        let now = Instant::now();
+        assert!(guard.attempt(ip("192.0.2.1"), now).is_ok());
+        assert!(guard.attempt(ip("192.0.2.1"), now).is_ok());
+        assert!(guard.attempt(ip("192.0.2.1"), now).is_err());
+    }
+}
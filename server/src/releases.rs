@@ -0,0 +1,34 @@
+use anyhow::{Context, Result};
+use pc_status_shared::{SignedManifest, UpdateManifest};
+use std::path::{Path, PathBuf};
+
+/// エージェントに知らせるマニフェストのパス（`AGENT_RELEASES_DIR`を`/agent`で配信する）
+pub const MANIFEST_PATH: &str = "/agent/manifest.json";
+
+/// サーバーで配布するエージェントの実行ファイル
+///
+/// ディレクトリには`manifest.json`（`SignedManifest`）と、マニフェストから相対パスで参照する実行ファイルを置く。
+/// 署名はエージェントが固定の公開鍵で検証するため、サーバーは検証しない。
+#[derive(Debug)]
+pub struct Releases {
+    dir: PathBuf,
+}
+
+impl Releases {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 配布中のマニフェスト（差し替えを反映するため毎回読み込む）
+    pub async fn manifest(&self) -> Result<UpdateManifest> {
+        let path = self.dir.join("manifest.json");
+        let json = tokio::fs::read(&path).await.with_context(|| format!("Failed to read {}", path.display()))?;
+        let signed: SignedManifest =
+            serde_json::from_slice(&json).with_context(|| format!("Failed to parse {}", path.display()))?;
+        signed.parse_unverified().with_context(|| format!("Failed to parse the manifest in {}", path.display()))
+    }
+}
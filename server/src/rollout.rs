@@ -0,0 +1,212 @@
+use chrono::{DateTime, Utc};
+use pc_status_shared::{Selector, StatusData, UpdateOutcome, UpdateReport};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// 配布中のエージェントの更新
+///
+/// カナリアのセレクターに一致するホストと、エージェントIDから決まる順位が`percent`未満のホストを更新する。
+/// 順位はバージョンごとに固定のため、`percent`を上げても更新済みのホストはそのまま対象に残る。
+#[derive(Debug, Clone, Serialize)]
+pub struct Rollout {
+    /// 更新先のバージョン（配布するマニフェストのバージョン）
+    pub version: String,
+    /// 先に更新するホストのセレクター
+    #[serde(serialize_with = "serialize_selector", skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Selector>,
+    /// カナリア以外で更新するホストの割合（0-100）
+    pub percent: u8,
+    pub paused: bool,
+    /// 一時停止した理由（更新の失敗で自動的に止めた場合など）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused_reason: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// 更新を指示したエージェントの進み具合（キーはエージェントID）
+    pub agents: BTreeMap<String, AgentProgress>,
+}
+
+/// エージェントごとの更新の進み具合
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentProgress {
+    pub hostname: String,
+    pub offered_at: DateTime<Utc>,
+    /// 更新を指示した接続（同じ接続に重ねて指示しない）
+    #[serde(skip)]
+    pub connection_id: String,
+    /// エージェントから報告された結果（報告前はNone）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<UpdateOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reported_at: Option<DateTime<Utc>>,
+}
+
+fn serialize_selector<S: serde::Serializer>(selector: &Option<Selector>, serializer: S) -> Result<S::Ok, S::Error> {
+    match selector {
+        Some(selector) => serializer.collect_str(selector),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl Rollout {
+    pub fn new(version: String, canary: Option<Selector>, percent: u8, now: DateTime<Utc>) -> Self {
+        Self {
+            version,
+            canary: canary.filter(|selector| !selector.is_empty()),
+            percent: percent.min(100),
+            paused: false,
+            paused_reason: None,
+            started_at: now,
+            updated_at: now,
+            agents: BTreeMap::new(),
+        }
+    }
+
+    /// 接続中のエージェントに更新を指示するか
+    pub fn targets(&self, agent_id: &str, connection_id: &str, status: &StatusData) -> bool {
+        if self.paused || agent_version(&status.version) == self.version {
+            return false;
+        }
+        // 結果を報告したエージェントと、この接続で指示済みのエージェントには再び指示しない
+        if let Some(progress) = self.agents.get(agent_id)
+            && (progress.outcome.is_some() || progress.connection_id == connection_id)
+        {
+            return false;
+        }
+        self.canary.as_ref().is_some_and(|canary| canary.matches(status))
+            || rank(&self.version, agent_id) < self.percent
+    }
+
+    /// 更新を指示したことを記録する
+    pub fn offered(&mut self, agent_id: &str, connection_id: &str, hostname: &str, now: DateTime<Utc>) {
+        self.agents.insert(
+            agent_id.to_string(),
+            AgentProgress {
+                hostname: hostname.to_string(),
+                offered_at: now,
+                connection_id: connection_id.to_string(),
+                outcome: None,
+                message: None,
+                reported_at: None,
+            },
+        );
+    }
+
+    /// エージェントの報告を記録する（失敗した場合は配布を一時停止し、trueを返す）
+    pub fn record(&mut self, agent_id: &str, hostname: &str, report: &UpdateReport, now: DateTime<Utc>) -> bool {
+        let progress = self.agents.entry(agent_id.to_string()).or_insert_with(|| AgentProgress {
+            hostname: hostname.to_string(),
+            offered_at: now,
+            connection_id: String::new(),
+            outcome: None,
+            message: None,
+            reported_at: None,
+        });
+        progress.outcome = Some(report.outcome);
+        progress.message = report.message.clone();
+        progress.reported_at = Some(now);
+
+        let failed = matches!(report.outcome, UpdateOutcome::Failed | UpdateOutcome::RolledBack);
+        if failed && !self.paused {
+            self.paused = true;
+            self.paused_reason = Some(format!("{} reported {}", hostname, report.outcome));
+            self.updated_at = now;
+            return true;
+        }
+        false
+    }
+
+    /// 結果ごとのエージェント数
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for progress in self.agents.values() {
+            let key = progress.outcome.map(|outcome| outcome.to_string()).unwrap_or_else(|| "pending".to_string());
+            *counts.entry(key).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// エージェントが送るバージョン（`Rust client v1.2.0`の形式）から配布のバージョンを取り出す
+pub fn agent_version(version: &str) -> &str {
+    version.strip_prefix("Rust client ").unwrap_or(version)
+}
+
+/// エージェントの順位（0-99、バージョンとエージェントIDから決まる）
+pub fn rank(version: &str, agent_id: &str) -> u8 {
+    let digest = ring::digest::digest(&ring::digest::SHA256, format!("{}\0{}", version, agent_id).as_bytes());
+    let value = u64::from_be_bytes(digest.as_ref()[..8].try_into().expect("SHA-256 is 32 bytes"));
+    (value % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pc_status_shared::test_support;
+
+    fn status(hostname: &str, version: &str, tags: &[&str]) -> StatusData {
+        StatusData {
+            version: version.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..test_support::status(hostname)
+        }
+    }
+
+    fn report(outcome: UpdateOutcome) -> UpdateReport {
+        UpdateReport { version: "v2.0.0".to_string(), outcome, message: None }
+    }
+
+    #[test]
+    fn test_stages_widen_without_dropping_agents() {
+        let agents: Vec<String> = (0..1000).map(|i| format!("agent-{}", i)).collect();
+        let targeted = |percent: u8| -> Vec<&String> {
+            let rollout = Rollout::new("v2.0.0".to_string(), None, percent, Utc::now());
+            agents.iter().filter(|id| rollout.targets(id, "conn", &status("host", "v1.0.0", &[]))).collect()
+        };
+
+        assert!(targeted(0).is_empty());
+        let ten = targeted(10);
+        let fifty = targeted(50);
+        assert!((50..150).contains(&ten.len()), "{}", ten.len());
+        assert!(ten.iter().all(|id| fifty.contains(id)));
+        assert_eq!(targeted(100).len(), agents.len());
+    }
+
+    #[test]
+    fn test_canary_and_current_version() {
+        let canary = "canary".parse::<Selector>().unwrap();
+        let rollout = Rollout::new("v2.0.0".to_string(), Some(canary), 0, Utc::now());
+        assert!(rollout.targets("a", "conn-a", &status("web", "v1.0.0", &["canary"])));
+        assert!(!rollout.targets("b", "conn-b", &status("db", "v1.0.0", &[])));
+        // 既に更新先のバージョンで動いているホストは対象外
+        assert!(!rollout.targets("a", "conn-a", &status("web", "v2.0.0", &["canary"])));
+        assert!(!rollout.targets("a", "conn-a", &status("web", "Rust client v2.0.0", &["canary"])));
+    }
+
+    #[test]
+    fn test_offers_once_per_connection_and_pauses_on_failure() {
+        let now = Utc::now();
+        let mut rollout = Rollout::new("v2.0.0".to_string(), None, 100, now);
+        let web = status("web", "v1.0.0", &[]);
+
+        rollout.offered("a", "conn-1", "web", now);
+        assert!(!rollout.targets("a", "conn-1", &web));
+        // 再接続した場合は改めて指示する
+        assert!(rollout.targets("a", "conn-2", &web));
+
+        assert!(!rollout.record("a", "web", &report(UpdateOutcome::Installed), now));
+        assert!(!rollout.targets("a", "conn-3", &web));
+        assert!(rollout.targets("b", "conn-4", &web));
+
+        assert!(rollout.record("b", "db", &report(UpdateOutcome::RolledBack), now));
+        assert!(rollout.paused);
+        assert_eq!(rollout.paused_reason.as_deref(), Some("db reported rolled_back"));
+        assert!(!rollout.targets("c", "conn-5", &web));
+
+        let counts = rollout.counts();
+        assert_eq!(counts.get("installed"), Some(&1));
+        assert_eq!(counts.get("rolled_back"), Some(&1));
+    }
+}
@@ -0,0 +1,247 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::stream::{self, Stream, StreamExt};
+use pc_status_shared::{glob_match, Alert, ClientData, Selector, ServerMessage};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error};
+
+use crate::frame::SharedMessage;
+use crate::tenants::{Viewer, ViewerScope};
+use crate::websocket::WebSocketServer;
+
+/// `/api/events`のクエリパラメータ
+#[derive(Debug, Default, Deserialize)]
+pub struct EventQuery {
+    /// 対象のホスト名（カンマ区切り、`*`をワイルドカードとして使用可能）
+    hosts: Option<String>,
+    /// タグ・ラベルのセレクター（`gpu,role=ci`など）
+    selector: Option<String>,
+}
+
+/// 配信するホストの絞り込み
+#[derive(Debug, Clone)]
+struct HostFilter {
+    /// トークンで許可されたテナント
+    scope: ViewerScope,
+    patterns: Vec<String>,
+    selector: Selector,
+    /// セレクターで絞り込んだ直近の状態に含まれるホストのID（アラートの絞り込みに使う）
+    visible: HashSet<String>,
+}
+
+impl HostFilter {
+    fn new(scope: ViewerScope, query: &EventQuery) -> Result<Self, String> {
+        let selector = query.selector.as_deref().unwrap_or_default().parse()?;
+        let patterns = query
+            .hosts
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Self { scope, patterns, selector, visible: HashSet::new() })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.scope.is_all() && self.patterns.is_empty() && self.selector.is_empty()
+    }
+
+    fn matches(&self, hostname: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|pattern| glob_match(pattern, hostname))
+    }
+
+    fn clients(&mut self, clients: &ClientData) -> ClientData {
+        let clients: ClientData = clients
+            .iter()
+            .filter(|(_, status)| {
+                self.scope.allows(&status.tenant) && self.matches(&status.hostname) && self.selector.matches(status)
+            })
+            .map(|(id, status)| (id.clone(), status.clone()))
+            .collect();
+        self.visible = clients.keys().cloned().collect();
+        clients
+    }
+
+    fn alert_matches(&self, alert: &Alert) -> bool {
+        self.scope.allows(&alert.tenant)
+            && self.matches(&alert.hostname)
+            && (self.selector.is_empty() || self.visible.contains(&alert.host))
+    }
+
+    /// 配信するイベントに変換する（対象外のメッセージはNone）
+    ///
+    /// トーストは特定のホストに紐付かないため、テナント以外の絞り込みに関係なく配信する。
+    fn event(&mut self, shared: &SharedMessage) -> Option<Event> {
+        let (name, data) = match shared.message() {
+            ServerMessage::Status(clients) if !self.is_empty() => {
+                ("Status", encode(&ServerMessage::Status(self.clients(clients)))?)
+            }
+            ServerMessage::Status(_) => ("Status", shared.json()?.to_string()),
+            ServerMessage::Toast(_) if shared.tenant().is_none_or(|tenant| self.scope.allows(tenant)) => {
+                ("Toast", shared.json()?.to_string())
+            }
+            ServerMessage::Alert(alert) if self.alert_matches(alert) => {
+                ("Alert", shared.json()?.to_string())
+            }
+            _ => return None,
+        };
+        Some(Event::default().event(name).data(data))
+    }
+}
+
+fn encode(message: &ServerMessage) -> Option<String> {
+    match message.to_json() {
+        Ok(json) => Some(json),
+        Err(e) => {
+            error!("Failed to serialize event: {}", e);
+            None
+        }
+    }
+}
+
+/// 状態・トースト・アラートをServer-Sent Eventsで配信する
+///
+/// 接続直後に現在の状態を送り、以降はWebSocketと同じブロードキャストを流す。
+pub async fn handle_events(
+    State(server): State<WebSocketServer>,
+    Viewer(scope): Viewer,
+    Query(query): Query<EventQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let mut filter = HostFilter::new(scope, &query).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    debug!("New SSE subscriber (hosts: {:?}, selector: {})", filter.patterns, filter.selector);
+
+    // 初回の状態より後のメッセージを取りこぼさないよう、先に購読する
+    let broadcast_rx = server.get_broadcast_sender().subscribe();
+    let clients = server.get_client_manager().get_all_clients().await;
+    let initial = filter.event(&SharedMessage::new(ServerMessage::Status(clients)));
+
+    let updates = stream::unfold((broadcast_rx, filter), |(mut broadcast_rx, mut filter)| async move {
+        loop {
+            match broadcast_rx.recv().await {
+                Ok(shared) => {
+                    if let Some(event) = filter.event(&shared) {
+                        return Some((event, (broadcast_rx, filter)));
+                    }
+                }
+                // 状態は毎秒全体を送るため、遅れた分は読み飛ばすだけでよい
+                Err(RecvError::Lagged(skipped)) => debug!("SSE subscriber skipped {} messages", skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = stream::iter(initial).chain(updates).map(Ok);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use pc_status_shared::test_support::status;
+    use pc_status_shared::{AlertState, ToastData};
+    use std::collections::HashSet;
+
+    fn host_alert(host: &str, tenant: &str) -> Alert {
+        Alert {
+            rule: "ram".to_string(),
+            host: host.to_string(),
+            hostname: host.to_string(),
+            tenant: tenant.to_string(),
+            state: AlertState::Firing,
+            value: 95.0,
+            threshold: 90.0,
+            message: "ram".to_string(),
+            since: Utc::now(),
+        }
+    }
+
+    fn filter(hosts: &str) -> HostFilter {
+        HostFilter::new(ViewerScope::All, &EventQuery { hosts: Some(hosts.to_string()), selector: None }).unwrap()
+    }
+
+    #[test]
+    fn test_status_is_filtered_by_hostname() {
+        let clients: ClientData = [("a", "web-1"), ("b", "web-2"), ("c", "db")]
+            .into_iter()
+            .map(|(id, hostname)| (id.to_string(), status(hostname)))
+            .collect();
+
+        let filtered = filter("web-*, DB").clients(&clients);
+        assert_eq!(filtered.len(), 3);
+
+        let filtered = filter("web-1").clients(&clients);
+        assert_eq!(filtered.keys().collect::<Vec<_>>(), vec!["a"]);
+
+        assert_eq!(filter("").clients(&clients).len(), 3);
+    }
+
+    #[test]
+    fn test_only_viewer_events_are_streamed() {
+        let alert = |hostname: &str| {
+            SharedMessage::new(ServerMessage::Alert(Alert {
+                rule: "ram".to_string(),
+                host: "a".to_string(),
+                hostname: hostname.to_string(),
+                tenant: String::new(),
+                state: AlertState::Firing,
+                value: 95.0,
+                threshold: 90.0,
+                message: "ram".to_string(),
+                since: Utc::now(),
+            }))
+        };
+        let mut filter = filter("web");
+
+        assert!(filter.event(&alert("web")).is_some());
+        assert!(filter.event(&alert("db")).is_none());
+        assert!(filter.event(&SharedMessage::new(ServerMessage::Sync("sync".to_string()))).is_none());
+        assert!(filter.event(&SharedMessage::new(ServerMessage::Close)).is_none());
+    }
+
+    #[test]
+    fn test_selector_filters_status_and_alerts() {
+        let mut gpu = status("gpu-1");
+        gpu.tags = vec!["gpu".to_string()];
+        let clients: ClientData = [("gpu-1".to_string(), gpu), ("web".to_string(), status("web"))].into();
+        let query = EventQuery { hosts: None, selector: Some("gpu".to_string()) };
+        let mut filter = HostFilter::new(ViewerScope::All, &query).unwrap();
+
+        assert_eq!(filter.clients(&clients).keys().collect::<Vec<_>>(), vec!["gpu-1"]);
+        assert!(filter.alert_matches(&host_alert("gpu-1", "")));
+        assert!(!filter.alert_matches(&host_alert("web", "")));
+
+        let query = EventQuery { hosts: None, selector: Some("=x".to_string()) };
+        assert!(HostFilter::new(ViewerScope::All, &query).is_err());
+    }
+
+    #[test]
+    fn test_other_tenants_are_not_streamed() {
+        let mut team_a = status("web");
+        team_a.tenant = "team-a".to_string();
+        let mut team_b = status("web");
+        team_b.tenant = "team-b".to_string();
+        let clients: ClientData = [("a".to_string(), team_a), ("b".to_string(), team_b)].into();
+        let scope = ViewerScope::Tenants(HashSet::from(["team-a".to_string()]));
+        let mut filter = HostFilter::new(scope, &EventQuery::default()).unwrap();
+
+        assert_eq!(filter.clients(&clients).keys().collect::<Vec<_>>(), vec!["a"]);
+        assert!(filter.alert_matches(&host_alert("a", "team-a")));
+        assert!(!filter.alert_matches(&host_alert("b", "team-b")));
+
+        let toast = |tenant: &str| {
+            let toast = ToastData { message: "connected".to_string(), color: "green".to_string(), toast_time: 5000 };
+            SharedMessage::for_tenant(ServerMessage::Toast(toast), tenant)
+        };
+        assert!(filter.event(&toast("team-a")).is_some());
+        assert!(filter.event(&toast("team-b")).is_none());
+    }
+}
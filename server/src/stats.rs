@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// 閲覧者への配信状況のカウンター
+#[derive(Debug, Default)]
+pub struct ViewerStats {
+    /// 現在ブロードキャストに追いつけていない接続数
+    lagging: AtomicUsize,
+    /// 遅延が発生した回数
+    lag_events: AtomicU64,
+    /// 遅延やキューあふれで破棄したメッセージ数
+    dropped_messages: AtomicU64,
+    /// 送信が詰まったため切断した接続数
+    disconnected: AtomicU64,
+}
+
+/// ある時点でのカウンターの値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewerStatsSnapshot {
+    pub lagging: usize,
+    pub lag_events: u64,
+    pub dropped_messages: u64,
+    pub disconnected: u64,
+}
+
+impl ViewerStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn lag_started(&self) {
+        self.lagging.fetch_add(1, Ordering::Relaxed);
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn lag_ended(&self) {
+        self.lagging.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, count: u64) {
+        self.dropped_messages.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn slow_disconnect(&self) {
+        self.disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ViewerStatsSnapshot {
+        ViewerStatsSnapshot {
+            lagging: self.lagging.load(Ordering::Relaxed),
+            lag_events: self.lag_events.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
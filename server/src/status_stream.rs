@@ -0,0 +1,58 @@
+use pc_status_shared::{ClientData, ServerMessage, StatusDelta, StatusSnapshot};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, warn};
+
+use crate::frame::SharedMessage;
+
+struct StreamState {
+    seq: u64,
+    clients: ClientData,
+}
+
+/// 差分配信の状態
+///
+/// 最後に配信した状態とシーケンス番号を保持し、新しい状態との差分を
+/// `ServerMessage::Delta`としてブロードキャストする。
+pub struct StatusStream {
+    state: Mutex<StreamState>,
+    broadcast_tx: broadcast::Sender<Arc<SharedMessage>>,
+}
+
+impl StatusStream {
+    pub fn new(broadcast_tx: broadcast::Sender<Arc<SharedMessage>>) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(StreamState {
+                seq: 0,
+                clients: ClientData::new(),
+            }),
+            broadcast_tx,
+        })
+    }
+
+    /// 新しい状態を記録し、変更があれば差分をブロードキャストする
+    pub async fn publish(&self, clients: ClientData) {
+        // 差分の順序とスナップショットの整合性を保つため、送信までロックを保持する
+        let mut state = self.state.lock().await;
+        let delta = StatusDelta::between(state.seq + 1, &state.clients, &clients);
+        if delta.is_empty() {
+            return;
+        }
+
+        state.seq = delta.seq;
+        state.clients = clients;
+        debug!("Broadcasting status delta (seq: {})", delta.seq);
+        if let Err(e) = self.broadcast_tx.send(SharedMessage::new(ServerMessage::Delta(delta))) {
+            warn!("Failed to broadcast status delta: {}", e);
+        }
+    }
+
+    /// 最後に配信した状態のスナップショット
+    pub async fn snapshot(&self) -> StatusSnapshot {
+        let state = self.state.lock().await;
+        StatusSnapshot {
+            seq: state.seq,
+            clients: state.clients.clone(),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts, Query},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::rate_limit::{too_many_requests, AuthGuard};
+
+/// `PASS`で接続したエージェントが所属するテナント
+pub const DEFAULT_TENANT: &str = "default";
+
+/// テナントの設定
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    pub name: String,
+    /// このテナントのエージェントが使うパスワード
+    #[serde(default)]
+    pub agent_passwords: Vec<String>,
+    /// このテナントのホストを閲覧できるトークン（複数のテナントに同じトークンを書くと両方を閲覧できる）
+    #[serde(default)]
+    pub viewer_tokens: Vec<String>,
+}
+
+impl TenantConfig {
+    fn validate(&self) -> Result<()> {
+        if !is_valid_tenant_name(&self.name) {
+            bail!("Invalid tenant name: {:?} (use letters, digits, '-' and '_')", self.name);
+        }
+        if self.agent_passwords.iter().chain(&self.viewer_tokens).any(|secret| secret.is_empty()) {
+            bail!("Tenant {} has an empty password or token", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// パスワード・トークンを比較する
+///
+/// 一致するまでの時間から推測されないよう、SHA-256のハッシュを全バイト比較する。
+pub fn secret_eq(a: &str, b: &str) -> bool {
+    let a = ring::digest::digest(&ring::digest::SHA256, a.as_bytes());
+    let b = ring::digest::digest(&ring::digest::SHA256, b.as_bytes());
+    let diff = a.as_ref().iter().zip(b.as_ref()).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+fn is_valid_tenant_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 接続が受け取れるホストの範囲
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViewerScope {
+    /// すべてのテナント（テナントを設定していない場合）
+    All,
+    /// 指定したテナントのみ（空の場合は何も受け取らない）
+    Tenants(HashSet<String>),
+}
+
+impl ViewerScope {
+    pub fn none() -> Self {
+        Self::Tenants(HashSet::new())
+    }
+
+    pub fn is_all(&self) -> bool {
+        matches!(self, Self::All)
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, Self::Tenants(tenants) if tenants.is_empty())
+    }
+
+    pub fn allows(&self, tenant: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Tenants(tenants) => tenants.contains(tenant),
+        }
+    }
+}
+
+/// エージェントの認証と閲覧者の権限
+///
+/// テナントを設定した場合、閲覧者はトークンで許可されたテナントのホストだけを受け取る。
+/// 設定しない場合は従来どおり`PASS`だけで認証し、閲覧者はすべてのホストを受け取る。
+#[derive(Debug, Default)]
+pub struct Tenants {
+    /// キーはパスワード、値はテナント名
+    agent_passwords: HashMap<String, String>,
+    /// キーはトークン、値は閲覧できるテナント名
+    viewer_tokens: HashMap<String, HashSet<String>>,
+    /// 閲覧者にトークンを要求するか
+    isolated: bool,
+}
+
+impl Tenants {
+    /// テナントを設定しない場合（`PASS`のみ）
+    pub fn single(password: String) -> Self {
+        Self {
+            agent_passwords: HashMap::from([(password, DEFAULT_TENANT.to_string())]),
+            ..Default::default()
+        }
+    }
+
+    /// `PASS`を`default`テナントのパスワードとしてテナントを設定する
+    pub fn new(password: String, configs: Vec<TenantConfig>) -> Result<Self> {
+        let mut tenants = Self::single(password);
+        tenants.isolated = true;
+
+        let mut names = HashSet::new();
+        for config in configs {
+            config.validate()?;
+            if !names.insert(config.name.clone()) {
+                bail!("Duplicate tenant name: {}", config.name);
+            }
+            for password in config.agent_passwords {
+                if let Some(existing) = tenants.agent_passwords.get(&password) {
+                    bail!("Tenant {} reuses the agent password of tenant {}", config.name, existing);
+                }
+                tenants.agent_passwords.insert(password, config.name.clone());
+            }
+            for token in config.viewer_tokens {
+                tenants.viewer_tokens.entry(token).or_default().insert(config.name.clone());
+            }
+        }
+        Ok(tenants)
+    }
+
+    pub fn is_isolated(&self) -> bool {
+        self.isolated
+    }
+
+    /// テナント名の一覧（`default`を含む）
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .agent_passwords
+            .values()
+            .chain(self.viewer_tokens.values().flatten())
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// パスワードに対応するテナント（認証に失敗した場合はNone）
+    ///
+    /// 一致した時点で打ち切らず、すべてのパスワードと比較する。
+    pub fn agent_tenant(&self, password: &str) -> Option<&str> {
+        self.agent_passwords
+            .iter()
+            .filter(|(candidate, _)| secret_eq(candidate, password))
+            .fold(None, |_, (_, tenant)| Some(tenant.as_str()))
+    }
+
+    /// エージェントのIDを決める
+    ///
+    /// 別のテナントのマシンIDと衝突して状態や履歴を引き継がないよう、
+    /// `default`以外のテナントでは`テナント名.マシンID`とする。
+    pub fn agent_id(&self, tenant: &str, machine_id: &str) -> String {
+        if tenant == DEFAULT_TENANT {
+            machine_id.to_string()
+        } else {
+            format!("{}.{}", tenant, machine_id)
+        }
+    }
+
+    /// エージェントの接続が受け取れる範囲（自分のテナントのみ）
+    pub fn agent_scope(&self, tenant: &str) -> ViewerScope {
+        if self.isolated {
+            ViewerScope::Tenants(HashSet::from([tenant.to_string()]))
+        } else {
+            ViewerScope::All
+        }
+    }
+
+    /// 閲覧者のトークンが受け取れる範囲（トークンが必要なのに無効な場合はNone）
+    pub fn viewer_scope(&self, token: Option<&str>) -> Option<ViewerScope> {
+        if !self.isolated {
+            return Some(ViewerScope::All);
+        }
+        let token = token?;
+        self.viewer_tokens
+            .iter()
+            .filter(|(candidate, _)| secret_eq(candidate, token))
+            .fold(None, |_, (_, tenants)| Some(ViewerScope::Tenants(tenants.clone())))
+    }
+}
+
+/// JSONファイルからテナントを読み込む
+pub fn load_tenants(path: &Path) -> Result<Vec<TenantConfig>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read tenants from {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse tenants in {}", path.display()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// リクエストの閲覧者トークン（`Authorization: Bearer`またはクエリパラメータ`token`）
+///
+/// ブラウザのWebSocketはヘッダーを付けられないため、クエリパラメータでも受け付ける。
+pub fn request_token(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    bearer.or_else(|| Query::<TokenQuery>::try_from_uri(uri).ok()?.0.token)
+}
+
+/// 閲覧者の権限を取り出すエクストラクター（トークンが無効な場合は401、締め出し中は429）
+pub struct Viewer(pub ViewerScope);
+
+impl<S> FromRequestParts<S> for Viewer
+where
+    Arc<Tenants>: FromRef<S>,
+    Arc<AuditLog>: FromRef<S>,
+    Arc<AuthGuard>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let tenants = Arc::<Tenants>::from_ref(state);
+        let guard = Arc::<AuthGuard>::from_ref(state);
+        let token = request_token(&parts.headers, &parts.uri);
+        let remote = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        if token.is_some()
+            && let Some(remaining) = remote.and_then(|ip| guard.locked(ip, Instant::now()))
+        {
+            return Err(too_many_requests(remaining));
+        }
+        if let Some(scope) = tenants.viewer_scope(token.as_deref()) {
+            return Ok(Viewer(scope));
+        }
+        // トークンを付けずに開いただけの要求は記録しない
+        if token.is_some() {
+            let event = AuditEvent::new(AuditAction::AuthFailure, remote).target(parts.uri.path()).detail("viewer token");
+            guard.record_failure(&Arc::<AuditLog>::from_ref(state), event).await;
+        }
+        Err((StatusCode::UNAUTHORIZED, "Invalid or missing viewer token").into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenants() -> Tenants {
+        let configs: Vec<TenantConfig> = serde_json::from_str(
+            r#"[
+                {"name": "team-a", "agent_passwords": ["a-pass"], "viewer_tokens": ["a-token", "ops"]},
+                {"name": "team-b", "agent_passwords": ["b-pass"], "viewer_tokens": ["b-token", "ops"]},
+                {"name": "default", "viewer_tokens": ["ops"]}
+            ]"#,
+        )
+        .unwrap();
+        Tenants::new("pass".to_string(), configs).unwrap()
+    }
+
+    #[test]
+    fn test_agent_passwords_select_tenant() {
+        let tenants = tenants();
+        assert_eq!(tenants.agent_tenant("pass"), Some(DEFAULT_TENANT));
+        assert_eq!(tenants.agent_tenant("b-pass"), Some("team-b"));
+        assert_eq!(tenants.agent_tenant("a-token"), None);
+        assert_eq!(tenants.agent_id("team-b", "machine"), "team-b.machine");
+        assert_eq!(tenants.agent_id(DEFAULT_TENANT, "machine"), "machine");
+        assert_eq!(tenants.names(), vec!["default", "team-a", "team-b"]);
+    }
+
+    #[test]
+    fn test_viewer_tokens_limit_scope() {
+        let tenants = tenants();
+        let scope = tenants.viewer_scope(Some("a-token")).unwrap();
+        assert!(scope.allows("team-a"));
+        assert!(!scope.allows("team-b"));
+        assert!(!scope.allows(DEFAULT_TENANT));
+
+        let ops = tenants.viewer_scope(Some("ops")).unwrap();
+        assert!(ops.allows("team-a") && ops.allows("team-b") && ops.allows(DEFAULT_TENANT));
+
+        assert_eq!(tenants.viewer_scope(None), None);
+        assert_eq!(tenants.viewer_scope(Some("wrong")), None);
+        assert_eq!(Tenants::single("pass".to_string()).viewer_scope(None), Some(ViewerScope::All));
+    }
+
+    #[test]
+    fn test_invalid_tenants_are_rejected() {
+        let config = |json: &str| serde_json::from_str::<Vec<TenantConfig>>(json).unwrap();
+        assert!(Tenants::new("pass".to_string(), config(r#"[{"name": "a/b"}]"#)).is_err());
+        assert!(Tenants::new("pass".to_string(), config(r#"[{"name": "a"}, {"name": "a"}]"#)).is_err());
+        assert!(Tenants::new("pass".to_string(), config(r#"[{"name": "a", "agent_passwords": ["pass"]}]"#)).is_err());
+    }
+
+    #[test]
+    fn test_secret_eq() {
+        assert!(secret_eq("secret", "secret"));
+        assert!(!secret_eq("secret", "Secret"));
+        assert!(!secret_eq("secret", "secret-but-longer"));
+        assert!(!secret_eq("", "secret"));
+    }
+
+    #[test]
+    fn test_request_token() {
+        let uri: Uri = "/ws?token=from-query".parse().unwrap();
+        assert_eq!(request_token(&HeaderMap::new(), &uri).as_deref(), Some("from-query"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer from-header".parse().unwrap());
+        assert_eq!(request_token(&headers, &uri).as_deref(), Some("from-header"));
+        assert_eq!(request_token(&HeaderMap::new(), &"/ws".parse().unwrap()), None);
+    }
+}
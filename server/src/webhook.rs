@@ -0,0 +1,333 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{header::RETRY_AFTER, Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::sleep;
+use tracing::{debug, error, warn};
+
+use crate::notification::Notification;
+
+/// 1回のリクエストにかけられる最大時間
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// 再送間隔の上限
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// `events`で指定できる種類
+const EVENT_KINDS: &[&str] = &["connected", "disconnected", "alert"];
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
+/// 組み込みのペイロード形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    Slack,
+    Discord,
+}
+
+impl Preset {
+    fn template(self) -> Value {
+        match self {
+            Self::Slack => json!({ "text": "{{message}}" }),
+            Self::Discord => json!({ "content": "{{message}}" }),
+        }
+    }
+}
+
+/// Webhookの送信先
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub preset: Option<Preset>,
+    /// 送信するJSON（文字列中の`{{変数}}`を置き換える）
+    ///
+    /// `preset`と`template`のどちらも指定しない場合は通知をそのままJSONで送る。
+    #[serde(default)]
+    pub template: Option<Value>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// 送信するイベントの種類（空の場合はすべて）
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// 失敗時に再送する回数
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// 最初の再送までの待ち時間（以降は倍にしていく）
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+impl WebhookConfig {
+    fn validate(&self) -> Result<()> {
+        reqwest::Url::parse(&self.url).with_context(|| format!("Invalid webhook URL: {}", self.url))?;
+        if self.preset.is_some() && self.template.is_some() {
+            bail!("Webhook {}: preset and template cannot be used together", self.url);
+        }
+        if let Some(kind) = self.events.iter().find(|kind| !EVENT_KINDS.contains(&kind.as_str())) {
+            bail!("Webhook {}: unknown event {} (expected one of {:?})", self.url, kind, EVENT_KINDS);
+        }
+        Ok(())
+    }
+}
+
+/// JSONファイルから送信先を読み込む
+pub fn load_webhooks(path: &Path) -> Result<Vec<WebhookConfig>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read webhooks from {}", path.display()))?;
+    let webhooks: Vec<WebhookConfig> = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse webhooks in {}", path.display()))?;
+    for webhook in &webhooks {
+        webhook.validate()?;
+    }
+    Ok(webhooks)
+}
+
+/// テンプレート内の文字列に含まれる`{{変数}}`を置き換える
+fn render_template(template: &Value, variables: &[(&str, String)]) -> Value {
+    match template {
+        Value::String(text) => {
+            let mut rendered = text.clone();
+            for (name, value) in variables {
+                rendered = rendered.replace(&format!("{{{{{}}}}}", name), value);
+            }
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_template(item, variables)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_template(value, variables)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// 再送すれば成功する可能性のある応答か
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT
+}
+
+pub struct Webhook {
+    config: WebhookConfig,
+    client: Client,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create webhook HTTP client")?;
+        Ok(Self { config, client })
+    }
+
+    fn accepts(&self, notification: &Notification) -> bool {
+        self.config.events.is_empty() || self.config.events.iter().any(|kind| kind == notification.kind())
+    }
+
+    fn payload(&self, notification: &Notification) -> Result<Value> {
+        let template = self.config.template.clone().or_else(|| self.config.preset.map(Preset::template));
+        Ok(match template {
+            Some(template) => render_template(&template, &notification.variables()),
+            None => serde_json::to_value(notification)?,
+        })
+    }
+
+    /// 通知を送信する（失敗した場合は間隔を空けて再送する）
+    pub async fn send(&self, notification: &Notification) -> Result<()> {
+        let payload = self.payload(notification)?;
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+
+        for attempt in 0..=self.config.max_retries {
+            let mut request = self.client.post(&self.config.url).json(&payload);
+            for (name, value) in &self.config.headers {
+                request = request.header(name, value);
+            }
+
+            let retry_after = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Delivered {} notification to {}", notification.kind(), self.config.url);
+                    return Ok(());
+                }
+                Ok(response) if is_retryable(response.status()) => {
+                    warn!("Webhook {} responded {} (attempt {})", self.config.url, response.status(), attempt + 1);
+                    response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .map(Duration::from_secs)
+                }
+                Ok(response) => bail!("Webhook {} rejected notification: {}", self.config.url, response.status()),
+                Err(e) => {
+                    warn!("Webhook {} request failed (attempt {}): {}", self.config.url, attempt + 1, e);
+                    None
+                }
+            };
+
+            if attempt < self.config.max_retries {
+                sleep(retry_after.unwrap_or(backoff).min(MAX_BACKOFF)).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+
+        bail!(
+            "Giving up on webhook {} after {} attempts",
+            self.config.url,
+            self.config.max_retries + 1
+        )
+    }
+
+    /// 通知を受信して送信し続けるタスクを開始する
+    pub fn spawn(self, mut rx: Receiver<Notification>) {
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(notification) => {
+                        if self.accepts(&notification)
+                            && let Err(e) = self.send(&notification).await
+                        {
+                            error!("{:#}", e);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Webhook {} fell behind, skipped {} notifications", self.config.url, skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode as HttpStatus, routing::post, Json, Router};
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// 受信したリクエストを記録し、指定回数だけ失敗を返すWebhookの代役
+    #[derive(Clone, Default)]
+    struct StandIn {
+        received: Arc<Mutex<Vec<Value>>>,
+        failures: Arc<AtomicUsize>,
+        failure_status: u16,
+    }
+
+    async fn receive(State(stand_in): State<StandIn>, Json(body): Json<Value>) -> HttpStatus {
+        stand_in.received.lock().unwrap().push(body);
+        let remaining = stand_in.failures.load(Ordering::SeqCst);
+        if remaining > 0 {
+            stand_in.failures.store(remaining - 1, Ordering::SeqCst);
+            return HttpStatus::from_u16(stand_in.failure_status).unwrap();
+        }
+        HttpStatus::NO_CONTENT
+    }
+
+    async fn start_stand_in(failures: usize, failure_status: u16) -> (String, StandIn) {
+        let stand_in = StandIn {
+            failures: Arc::new(AtomicUsize::new(failures)),
+            failure_status,
+            ..Default::default()
+        };
+        let app = Router::new().route("/hook", post(receive)).with_state(stand_in.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, stand_in)
+    }
+
+    fn webhook(url: &str, extra: Value) -> Webhook {
+        let mut config = json!({ "url": url, "backoff_ms": 10 });
+        config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let config: WebhookConfig = serde_json::from_value(config).unwrap();
+        config.validate().unwrap();
+        Webhook::new(config).unwrap()
+    }
+
+    fn connected() -> Notification {
+        Notification::Connected {
+            host: "machine-1".to_string(),
+            hostname: "web".to_string(),
+            time: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slack_preset() {
+        let (url, stand_in) = start_stand_in(0, 500).await;
+        webhook(&url, json!({ "preset": "slack" })).send(&connected()).await.unwrap();
+
+        assert_eq!(*stand_in.received.lock().unwrap(), vec![json!({ "text": "web is connected" })]);
+    }
+
+    #[tokio::test]
+    async fn test_raw_notification_without_template() {
+        let (url, stand_in) = start_stand_in(0, 500).await;
+        webhook(&url, json!({})).send(&connected()).await.unwrap();
+
+        let received = stand_in.received.lock().unwrap();
+        assert_eq!(received[0]["event"], "connected");
+        assert_eq!(received[0]["host"], "machine-1");
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let (url, stand_in) = start_stand_in(2, 503).await;
+        webhook(&url, json!({ "max_retries": 3 })).send(&connected()).await.unwrap();
+
+        assert_eq!(stand_in.received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (url, stand_in) = start_stand_in(10, 500).await;
+        let result = webhook(&url, json!({ "max_retries": 2 })).send(&connected()).await;
+
+        assert!(result.is_err());
+        assert_eq!(stand_in.received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let (url, stand_in) = start_stand_in(1, 400).await;
+        let result = webhook(&url, json!({ "max_retries": 3 })).send(&connected()).await;
+
+        assert!(result.is_err());
+        assert_eq!(stand_in.received.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = json!({ "title": "{{event}}: {{hostname}}", "fields": [{ "value": "{{message}}" }], "count": 1 });
+        let rendered = render_template(&template, &connected().variables());
+
+        assert_eq!(
+            rendered,
+            json!({ "title": "connected: web", "fields": [{ "value": "web is connected" }], "count": 1 })
+        );
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let hook = webhook("http://127.0.0.1/hook", json!({ "events": ["disconnected"] }));
+        assert!(!hook.accepts(&connected()));
+
+        let config: WebhookConfig =
+            serde_json::from_value(json!({ "url": "http://127.0.0.1/hook", "events": ["typo"] })).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
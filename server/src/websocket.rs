@@ -2,57 +2,545 @@ use anyhow::Result;
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, FromRef, State,
     },
-    response::Response,
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use futures_util::{SinkExt, StreamExt};
-use pc_status_shared::{ClientMessage, ServerMessage, StatusData};
+use pc_status_shared::{
+    sanitize_status, Capability, ClientData, ClientMessage, CommandRequest, CommandResult, ErrorCode, Selector,
+    ServerHello, ServerMessage, StatusData, StatusSnapshot, ToastData, UpdateOffer, UpdateOutcome, UpdateReport,
+    WireFormat, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex, RwLock};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::client_manager::ClientManager;
+use crate::audit::{AuditAction, AuditEvent, AuditLog};
+use crate::bans::Bans;
+use crate::client_manager::{ClientManager, RegisterError};
+use crate::frame::SharedMessage;
+use crate::host_labels::HostLabels;
+use crate::notification::{Notification, Notifier};
+use crate::rate_limit::{too_many_requests, AuthGuard, Limits, RateLimiter, TokenBucket};
+use crate::releases::MANIFEST_PATH;
+use crate::rollout::Rollout;
+use crate::stats::ViewerStats;
+use crate::status_stream::StatusStream;
+use crate::tenants::{request_token, Tenants, ViewerScope};
+
+/// ブロードキャストチャネルの容量（これを超えて遅れた接続は再同期する）
+const BROADCAST_CAPACITY: usize = 64;
+/// 接続ごとの個別メッセージキューの容量
+const OUTBOUND_QUEUE_SIZE: usize = 32;
+/// 1フレームの送信にかけられる最大時間（超えた接続は切断する）
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// 切断を指示してから送信待ちのメッセージを送り切るまで待つ最大時間
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+/// サーバーが対応している機能
+const SERVER_CAPABILITIES: &[Capability] =
+    &[Capability::Binary, Capability::Delta, Capability::Commands, Capability::Updates];
+/// エージェントのコマンドの実行結果を待つ最大時間（エージェント側の制限時間より長くする）
+const COMMAND_REPLY_TIMEOUT: Duration = Duration::from_secs(330);
+/// ホスト名が重複したエージェントに指示する再接続までの秒数
+const DUPLICATE_RETRY_SECS: u64 = 60;
+/// 接続数の上限に達したときに指示する再接続までの秒数
+const OVERLOADED_RETRY_SECS: u64 = 30;
+/// 接続を禁止されたエージェントに指示する再接続までの秒数
+const BANNED_RETRY_SECS: u64 = 300;
+/// 不正なデータを送ったエージェントに指示する再接続までの秒数
+const INVALID_DATA_RETRY_SECS: u64 = 300;
+
+/// 管理APIで返す接続の情報
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    /// 接続ID
+    pub id: String,
+    pub remote: SocketAddr,
+    pub connected_at: DateTime<Utc>,
+    /// Hiで登録したエージェントID（閲覧者の場合はNone）
+    pub agent_id: Option<String>,
+    pub hostname: Option<String>,
+    pub tenant: Option<String>,
+    /// Hiで合意した機能
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<Capability>,
+}
+
+/// 接続中のセッション
+struct Session {
+    /// 個別メッセージの送信口
+    tx: mpsc::Sender<Arc<SharedMessage>>,
+    /// 切断の指示（送信待ちのキューが詰まっていても届く）
+    close: watch::Sender<bool>,
+    info: SessionInfo,
+}
+
+/// 結果を待っているコマンド
+struct PendingCommand {
+    /// 要求を送った接続
+    connection_id: String,
+    agent_id: String,
+    command: String,
+    reply: oneshot::Sender<CommandResult>,
+}
+
+/// エージェントにコマンドを実行させられなかった理由
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Agent {0} is not connected")]
+    NotConnected(String),
+    #[error("Agent {0} does not accept commands")]
+    NotSupported(String),
+    #[error("Agent {0} is busy")]
+    Busy(String),
+    #[error("Agent {0} disconnected before replying")]
+    Disconnected(String),
+    #[error("Agent {0} did not reply in time")]
+    TimedOut(String),
+}
+
+/// 受信したメッセージの処理で更新する接続ごとの状態
+struct ClientState {
+    /// Hiで登録されたエージェントID（閲覧者の場合はNone）
+    agent_id: Option<String>,
+    /// 送信時のエンコード形式（HiまたはSubscribeと同じ形式に切り替える）
+    format_tx: watch::Sender<WireFormat>,
+    /// 配信するホストの絞り込み（HiとOnlyで変更する）
+    filter_tx: watch::Sender<ConnectionFilter>,
+    /// Syncの受信頻度の制限（無制限の場合はNone）
+    sync_limit: Option<TokenBucket>,
+}
+
+/// 接続ごとの配信の絞り込み
+#[derive(Debug, Clone)]
+struct ConnectionFilter {
+    /// 受信できるテナント（エージェントはHiで自分のテナントに切り替える）
+    scope: ViewerScope,
+    /// Onlyで指定されたセレクター
+    selector: Selector,
+}
+
+impl ConnectionFilter {
+    fn is_unrestricted(&self) -> bool {
+        self.scope.is_all() && self.selector.is_empty()
+    }
+
+    fn matches(&self, status: &StatusData) -> bool {
+        self.scope.allows(&status.tenant) && self.selector.matches(status)
+    }
+}
 
 #[derive(Clone)]
 pub struct WebSocketServer {
     client_manager: Arc<ClientManager>,
-    password: String,
-    broadcast_tx: broadcast::Sender<ServerMessage>,
+    /// エージェントのパスワードと閲覧者のトークン
+    tenants: Arc<Tenants>,
+    /// 接続を受け付けるエージェントの最小プロトコルバージョン
+    min_agent_protocol: u32,
+    /// サーバー側で付けるタグ・ラベル
+    host_labels: Arc<HostLabels>,
+    broadcast_tx: broadcast::Sender<Arc<SharedMessage>>,
+    status_stream: Arc<StatusStream>,
+    /// 接続中のセッション（キーは接続ID）
+    sessions: Arc<RwLock<HashMap<String, Session>>>,
+    /// 管理APIで追加した接続禁止
+    bans: Arc<Bans>,
+    /// 結果を待っているコマンド（キーは要求ID）
+    pending_commands: Arc<Mutex<HashMap<String, PendingCommand>>>,
+    /// 配布中のエージェントの更新
+    rollout: Arc<RwLock<Option<Rollout>>>,
+    audit: Arc<AuditLog>,
+    /// 接続・メッセージの制限
+    limits: Arc<Limits>,
+    /// 接続元ごとの接続数の制限
+    connection_limiter: Arc<RateLimiter>,
+    /// 認証の試行回数の制限と締め出し
+    auth_guard: Arc<AuthGuard>,
+    viewer_stats: Arc<ViewerStats>,
+    notifier: Notifier,
 }
 
 impl WebSocketServer {
-    pub fn new(client_manager: Arc<ClientManager>, password: String) -> Self {
-        let (broadcast_tx, _) = broadcast::channel(1000);
-        
+    pub fn new(
+        client_manager: Arc<ClientManager>,
+        tenants: Arc<Tenants>,
+        min_agent_protocol: u32,
+        host_labels: HostLabels,
+        audit: Arc<AuditLog>,
+        limits: Limits,
+    ) -> Self {
+        let (broadcast_tx, _) = broadcast::channel(BROADCAST_CAPACITY);
+        let status_stream = StatusStream::new(broadcast_tx.clone());
+
         Self {
             client_manager,
-            password,
+            tenants,
+            min_agent_protocol,
+            host_labels: Arc::new(host_labels),
             broadcast_tx,
+            status_stream,
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            bans: Arc::new(Bans::default()),
+            pending_commands: Arc::new(Mutex::new(HashMap::new())),
+            rollout: Arc::new(RwLock::new(None)),
+            audit,
+            connection_limiter: Arc::new(RateLimiter::new(limits.connections_per_minute)),
+            auth_guard: Arc::new(AuthGuard::new(&limits)),
+            limits: Arc::new(limits),
+            viewer_stats: ViewerStats::new(),
+            notifier: Notifier::new(),
         }
     }
 
-    pub fn get_broadcast_sender(&self) -> broadcast::Sender<ServerMessage> {
+    pub fn get_client_manager(&self) -> Arc<ClientManager> {
+        self.client_manager.clone()
+    }
+
+    pub fn get_broadcast_sender(&self) -> broadcast::Sender<Arc<SharedMessage>> {
         self.broadcast_tx.clone()
     }
 
+    pub fn get_status_stream(&self) -> Arc<StatusStream> {
+        self.status_stream.clone()
+    }
+
+    pub fn get_viewer_stats(&self) -> Arc<ViewerStats> {
+        self.viewer_stats.clone()
+    }
+
+    pub fn get_notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    pub fn get_tenants(&self) -> Arc<Tenants> {
+        self.tenants.clone()
+    }
+
+    pub fn get_bans(&self) -> Arc<Bans> {
+        self.bans.clone()
+    }
+
+    pub fn get_audit_log(&self) -> Arc<AuditLog> {
+        self.audit.clone()
+    }
+
+    pub fn get_auth_guard(&self) -> Arc<AuthGuard> {
+        self.auth_guard.clone()
+    }
+
+    pub fn get_limits(&self) -> Arc<Limits> {
+        self.limits.clone()
+    }
+
+    /// 接続元のIPアドレス（監査ログに記録する）
+    async fn remote_ip(&self, connection_id: &str) -> Option<IpAddr> {
+        self.sessions.read().await.get(connection_id).map(|session| session.info.remote.ip())
+    }
+
+    /// 接続中のセッションの一覧（接続の古い順）
+    pub async fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> =
+            self.sessions.read().await.values().map(|session| session.info.clone()).collect();
+        sessions.sort_by(|a, b| a.connected_at.cmp(&b.connected_at).then_with(|| a.id.cmp(&b.id)));
+        sessions
+    }
+
+    /// 接続を切断する（接続が見つからない場合はNone）
+    pub async fn disconnect(&self, connection_id: &str) -> Option<SessionInfo> {
+        let info = self.sessions.read().await.get(connection_id)?.info.clone();
+        info!("Disconnecting {} on admin request", connection_id);
+        self.close(connection_id).await;
+        Some(info)
+    }
+
+    /// 禁止されたホスト名・IPアドレスの接続を切断する
+    pub async fn disconnect_banned(&self) -> Vec<SessionInfo> {
+        let mut banned = vec![];
+        for session in self.sessions().await {
+            let hostname_banned = match &session.hostname {
+                Some(hostname) => self.bans.is_hostname_banned(hostname).await,
+                None => false,
+            };
+            if hostname_banned || self.bans.is_ip_banned(session.remote.ip()).await {
+                banned.push(session);
+            }
+        }
+        for session in &banned {
+            let message = "Banned by administrator".to_string();
+            self.reject(&session.id, ErrorCode::Banned, message, Some(BANNED_RETRY_SECS)).await;
+        }
+        banned
+    }
+
+    /// エージェントに許可リストのコマンドを実行させ、結果を待つ
+    ///
+    /// 実行するかはエージェントが自身の許可リストで判断する。
+    pub async fn run_command(
+        &self,
+        agent_id: &str,
+        command: &str,
+        requested_by: Option<IpAddr>,
+    ) -> Result<CommandResult, CommandError> {
+        let id = Uuid::new_v4().to_string();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let request = CommandRequest {
+            id: id.clone(),
+            command: command.to_string(),
+            requested_by: requested_by.map(|ip| ip.to_string()),
+        };
+        {
+            let sessions = self.sessions.read().await;
+            let session = sessions
+                .values()
+                .find(|session| session.info.agent_id.as_deref() == Some(agent_id))
+                .ok_or_else(|| CommandError::NotConnected(agent_id.to_string()))?;
+            if !session.info.capabilities.contains(&Capability::Commands) {
+                return Err(CommandError::NotSupported(agent_id.to_string()));
+            }
+            let pending = PendingCommand {
+                connection_id: session.info.id.clone(),
+                agent_id: agent_id.to_string(),
+                command: command.to_string(),
+                reply: reply_tx,
+            };
+            self.pending_commands.lock().await.insert(id.clone(), pending);
+            if session.tx.try_send(SharedMessage::new(ServerMessage::Command(request))).is_err() {
+                self.pending_commands.lock().await.remove(&id);
+                return Err(CommandError::Busy(agent_id.to_string()));
+            }
+        }
+        info!("Sent command {} ({}) to {}", command, id, agent_id);
+
+        let result = tokio::time::timeout(COMMAND_REPLY_TIMEOUT, reply_rx).await;
+        self.pending_commands.lock().await.remove(&id);
+        match result {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(_)) => Err(CommandError::Disconnected(agent_id.to_string())),
+            Err(_) => Err(CommandError::TimedOut(agent_id.to_string())),
+        }
+    }
+
+    /// エージェントから受け取ったコマンドの実行結果を待っている要求に渡す
+    async fn handle_command_result(&self, connection_id: &str, result: CommandResult) {
+        let mut pending_commands = self.pending_commands.lock().await;
+        // 別の接続に送った要求の結果は受け付けない
+        if pending_commands.get(&result.id).is_none_or(|pending| pending.connection_id != connection_id) {
+            warn!("Unexpected command result {} from {}", result.id, connection_id);
+            return;
+        }
+        let pending = pending_commands.remove(&result.id).expect("checked above");
+        drop(pending_commands);
+
+        let mut detail = format!("command={} status={}", pending.command, result.status);
+        if let Some(code) = result.exit_code {
+            detail.push_str(&format!(" exit_code={}", code));
+        }
+        let event = AuditEvent::new(AuditAction::CommandResult, self.remote_ip(connection_id).await)
+            .target(pending.agent_id.as_str())
+            .detail(detail);
+        self.audit.record(event).await;
+        let _ = pending.reply.send(result);
+    }
+
+    pub async fn rollout(&self) -> Option<Rollout> {
+        self.rollout.read().await.clone()
+    }
+
+    /// 更新の配布を開始・変更・中止し、新たに対象になったエージェントに更新を指示する
+    pub async fn modify_rollout<R>(&self, f: impl FnOnce(&mut Option<Rollout>) -> R) -> R {
+        let result = f(&mut *self.rollout.write().await);
+        self.offer_updates().await;
+        result
+    }
+
+    /// 配布の対象になった接続中のエージェントに更新を指示する
+    async fn offer_updates(&self) {
+        let clients = self.client_manager.get_all_clients().await;
+        let sessions: Vec<SessionInfo> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .filter(|session| session.info.capabilities.contains(&Capability::Updates))
+            .map(|session| session.info.clone())
+            .collect();
+        for session in sessions {
+            if let Some(agent_id) = &session.agent_id
+                && let Some(status) = clients.get(agent_id)
+            {
+                self.offer_update(agent_id, &session.id, status).await;
+            }
+        }
+    }
+
+    /// エージェントが配布の対象であれば更新を指示する
+    async fn offer_update(&self, agent_id: &str, connection_id: &str, status: &StatusData) {
+        let offer = {
+            let mut rollout = self.rollout.write().await;
+            let Some(rollout) = rollout.as_mut().filter(|rollout| rollout.targets(agent_id, connection_id, status))
+            else {
+                return;
+            };
+            rollout.offered(agent_id, connection_id, &status.hostname, Utc::now());
+            UpdateOffer { version: rollout.version.clone(), manifest: MANIFEST_PATH.to_string() }
+        };
+        info!("Offering update {} to {} ({})", offer.version, agent_id, status.hostname);
+        self.send_to(connection_id, ServerMessage::Update(offer)).await;
+    }
+
+    /// エージェントが報告した更新の結果を記録する（失敗した場合は配布を一時停止する）
+    async fn handle_update_report(&self, agent_id: &str, connection_id: &str, report: UpdateReport) {
+        let hostname = self
+            .sessions
+            .read()
+            .await
+            .get(connection_id)
+            .and_then(|session| session.info.hostname.clone())
+            .unwrap_or_default();
+        let paused = match self.rollout.write().await.as_mut() {
+            Some(rollout) if rollout.version == report.version => {
+                rollout.record(agent_id, &hostname, &report, Utc::now())
+            }
+            _ => false,
+        };
+        match report.outcome {
+            UpdateOutcome::Installed => info!("{} ({}) updated to {}", agent_id, hostname, report.version),
+            outcome => warn!(
+                "{} ({}) reported {} for {}: {}",
+                agent_id,
+                hostname,
+                outcome,
+                report.version,
+                report.message.as_deref().unwrap_or("-")
+            ),
+        }
+        if paused {
+            warn!("Paused rollout of {} after a failed update on {}", report.version, hostname);
+        }
+
+        let mut detail = format!("version={} outcome={}", report.version, report.outcome);
+        if let Some(message) = &report.message {
+            detail.push_str(&format!(" message={}", message));
+        }
+        let event = AuditEvent::new(AuditAction::UpdateReport, self.remote_ip(connection_id).await)
+            .target(agent_id)
+            .detail(detail);
+        self.audit.record(event).await;
+    }
+
+    /// すべての閲覧者に通知を送る
+    pub fn broadcast_toast(&self, toast: ToastData) {
+        let _ = self.broadcast_tx.send(SharedMessage::new(ServerMessage::Toast(toast)));
+    }
+
+    /// 特定の接続にメッセージを送信する（接続を閉じる場合は`close`を使う）
+    async fn send_to(&self, connection_id: &str, message: ServerMessage) {
+        if let Some(session) = self.sessions.read().await.get(connection_id)
+            && let Err(mpsc::error::TrySendError::Full(_)) = session.tx.try_send(SharedMessage::new(message))
+        {
+            warn!("Outbound queue full for client {}, dropping message", connection_id);
+            self.viewer_stats.dropped(1);
+        }
+    }
+
+    /// 接続を閉じる
+    ///
+    /// 送信待ちのメッセージを送ってから閉じるが、送れない場合も`CLOSE_GRACE`後に閉じる。
+    async fn close(&self, connection_id: &str) {
+        if let Some(session) = self.sessions.read().await.get(connection_id) {
+            let _ = session.tx.try_send(SharedMessage::new(ServerMessage::Close));
+            session.close.send_replace(true);
+        }
+    }
+
+    /// エージェントのデータを検証して直す（受け付けられない場合は接続を拒否してfalse）
+    async fn sanitize(&self, client_id: &str, connection_id: &str, data: &mut StatusData) -> bool {
+        match sanitize_status(data) {
+            Ok(fixed) => {
+                if !fixed.is_empty() {
+                    debug!("Sanitized data from {}: {}", client_id, fixed.join(", "));
+                }
+                true
+            }
+            Err(e) => {
+                let message = format!("Invalid data: {}", e);
+                self.reject(connection_id, ErrorCode::InvalidData, message, Some(INVALID_DATA_RETRY_SECS)).await;
+                false
+            }
+        }
+    }
+
+    /// エラーを通知してから接続を閉じる
+    async fn reject(&self, connection_id: &str, code: ErrorCode, message: String, retry: Option<u64>) {
+        warn!("Rejecting client {}: {}", connection_id, message);
+        self.send_to(connection_id, ServerMessage::Error { code, message, retry }).await;
+        self.close(connection_id).await;
+    }
+
+    /// WebSocket接続を受け付ける
+    ///
+    /// エージェントはHiで認証するため、トークンのない接続も受け付ける。
+    /// テナントを設定している場合、トークンのない接続はHiで認証するまで何も受け取らない。
     pub async fn handle_websocket_upgrade(
         State(server): State<WebSocketServer>,
+        ConnectInfo(remote): ConnectInfo<SocketAddr>,
+        headers: HeaderMap,
+        uri: Uri,
         ws: WebSocketUpgrade,
     ) -> Response {
-        ws.on_upgrade(move |socket| server.handle_websocket(socket))
+        if server.bans.is_ip_banned(remote.ip()).await {
+            warn!("Rejecting WebSocket connection from banned address {}", remote);
+            return (StatusCode::FORBIDDEN, "Banned by administrator").into_response();
+        }
+        let now = Instant::now();
+        if let Some(remaining) = server.auth_guard.locked(remote.ip(), now) {
+            debug!("Rejecting WebSocket connection from locked out address {}", remote);
+            return too_many_requests(remaining);
+        }
+        if !server.connection_limiter.check(remote.ip(), now) {
+            warn!("Too many WebSocket connections from {}", remote);
+            return too_many_requests(Duration::from_secs(60));
+        }
+        let token = request_token(&headers, &uri);
+        let scope = match (server.tenants.viewer_scope(token.as_deref()), token) {
+            (Some(scope), _) => scope,
+            (None, Some(_)) => {
+                let event = AuditEvent::new(AuditAction::AuthFailure, Some(remote.ip()))
+                    .target(uri.path())
+                    .detail("viewer token");
+                server.auth_guard.record_failure(&server.audit, event).await;
+                return (StatusCode::UNAUTHORIZED, "Invalid viewer token").into_response();
+            }
+            (None, None) => ViewerScope::none(),
+        };
+        let max_message_bytes = server.limits.max_message_bytes;
+        ws.max_message_size(max_message_bytes)
+            .max_frame_size(max_message_bytes)
+            .on_upgrade(move |socket| server.handle_websocket(socket, remote, scope))
     }
 
-    async fn handle_websocket(self, socket: WebSocket) {
-        let client_id = Uuid::new_v4().to_string();
-        info!("New WebSocket connection: {}", client_id);
-
+    async fn handle_websocket(self, socket: WebSocket, remote: SocketAddr, scope: ViewerScope) {
+        let connection_id = Uuid::new_v4().to_string();
+        info!("New WebSocket connection: {} from {}", connection_id, remote);
         let (mut sender, mut receiver) = socket.split();
         let mut broadcast_rx = self.broadcast_tx.subscribe();
 
         // 接続時の挨拶
-        let hello_message = ServerMessage::Hi("hello".to_string());
+        let hello_message = ServerMessage::Hi(ServerHello {
+            message: "hello".to_string(),
+            protocol: PROTOCOL_VERSION,
+            capabilities: SERVER_CAPABILITIES.to_vec(),
+        });
         let hello_json = hello_message.to_json().unwrap_or_default();
         debug!("Sending hello message: {}", hello_json);
 
@@ -64,82 +552,313 @@ impl WebSocketServer {
             return;
         }
 
+        let (direct_tx, mut direct_rx) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (close_tx, mut close_rx) = watch::channel(false);
+        let info = SessionInfo {
+            id: connection_id.clone(),
+            remote,
+            connected_at: Utc::now(),
+            agent_id: None,
+            hostname: None,
+            tenant: None,
+            capabilities: vec![],
+        };
+        self.sessions.write().await.insert(connection_id.clone(), Session { tx: direct_tx, close: close_tx, info });
+        // 差分配信の購読状態（最後に送信したシーケンス番号）
+        let mut delta_seq: Option<u64> = None;
+        let (format_tx, format_rx) = watch::channel(WireFormat::Json);
+        let (filter_tx, mut filter_rx) = watch::channel(ConnectionFilter { scope, selector: Selector::default() });
+        // 絞り込み中に送信したホストのID
+        let mut visible: HashSet<String> = HashSet::new();
+        // ブロードキャストに追いつけていない状態か
+        let mut lagging = false;
+        // Syncの受信頻度は短時間の集中を5秒分まで許す
+        let syncs_per_sec = self.limits.syncs_per_sec;
+        let sync_limit = (syncs_per_sec > 0)
+            .then(|| TokenBucket::new(syncs_per_sec * 5, f64::from(syncs_per_sec), Instant::now()));
+        let mut client = ClientState { agent_id: None, format_tx, filter_tx, sync_limit };
+
         // 並行してメッセージを処理
         tokio::select! {
             // クライアントからのメッセージを処理
             _ = async {
                 while let Some(msg) = receiver.next().await {
-                    match msg {
+                    let result = match msg {
                         Ok(Message::Text(text)) => {
-                            if let Err(e) = self.handle_text_message(&client_id, &text).await {
-                                error!("Error handling message: {}", e);
-                                break;
-                            }
+                            debug!("Received message from {}: {}", connection_id, text);
+                            self.handle_client_message(&connection_id, &mut client, text.as_bytes(), WireFormat::Json).await
+                        }
+                        Ok(Message::Binary(bytes)) => {
+                            debug!("Received binary message from {} ({} bytes)", connection_id, bytes.len());
+                            self.handle_client_message(&connection_id, &mut client, &bytes, WireFormat::MessagePack).await
                         }
                         Ok(Message::Close(_)) => {
-                            info!("WebSocket connection closed: {}", client_id);
+                            info!("WebSocket connection closed: {}", connection_id);
                             break;
                         }
                         Err(e) => {
                             error!("WebSocket error: {}", e);
                             break;
                         }
-                        _ => {}
+                        _ => Ok(()),
+                    };
+
+                    if let Err(e) = result {
+                        error!("Error handling message: {}", e);
+                        break;
                     }
                 }
             } => {},
-            // ブロードキャストメッセージを転送
+            // ブロードキャストメッセージと個別メッセージを転送
             _ = async {
-                while let Ok(broadcast_msg) = broadcast_rx.recv().await {
-                    debug!("Broadcasting message to client {}: {:?}", client_id, broadcast_msg);
-                    let json_msg = match broadcast_msg.to_json() {
-                        Ok(json) => json,
-                        Err(e) => {
-                            error!("Failed to serialize broadcast message: {}", e);
+                loop {
+                    let shared = tokio::select! {
+                        direct = direct_rx.recv() => match direct {
+                            Some(msg) => msg,
+                            None => break,
+                        },
+                        broadcast = broadcast_rx.recv() => match broadcast {
+                            Ok(msg) => msg,
+                            Err(RecvError::Lagged(skipped)) => {
+                                warn!("Client {} lagged behind, skipped {} messages", connection_id, skipped);
+                                if !lagging {
+                                    lagging = true;
+                                    self.viewer_stats.lag_started();
+                                }
+                                self.viewer_stats.dropped(skipped);
+                                match delta_seq {
+                                    // 差分購読者は最新のスナップショットで再同期する
+                                    Some(_) => SharedMessage::new(ServerMessage::Snapshot(self.status_stream.snapshot().await)),
+                                    // 完全な状態の配信は追いついた後のStatusで復帰する
+                                    None => continue,
+                                }
+                            }
+                            Err(RecvError::Closed) => break,
+                        },
+                        Ok(()) = filter_rx.changed() => match delta_seq {
+                            // 差分購読者は新しい条件のスナップショットから受け直す
+                            Some(_) => SharedMessage::new(ServerMessage::Snapshot(self.status_stream.snapshot().await)),
+                            // 完全な状態の配信は次のStatusから新しい条件で送る
+                            None => continue,
+                        },
+                    };
+                    let close = matches!(shared.message(), ServerMessage::Close);
+
+                    if lagging && broadcast_rx.is_empty() {
+                        lagging = false;
+                        self.viewer_stats.lag_ended();
+                        info!("Client {} caught up with broadcasts", connection_id);
+                    }
+
+                    // 購読モードに応じて完全な状態か差分のどちらかだけを送る
+                    let shared = match (shared.message(), delta_seq) {
+                        (ServerMessage::Status(_), Some(_)) | (ServerMessage::Delta(_), None) => continue,
+                        // 遅延中の古い状態は送らない
+                        (ServerMessage::Status(_) | ServerMessage::Sync(_), None) if lagging => {
+                            self.viewer_stats.dropped(1);
                             continue;
                         }
+                        (ServerMessage::Delta(delta), Some(last_seq)) => {
+                            if delta.seq <= last_seq {
+                                // 購読開始時のスナップショットに含まれている
+                                continue;
+                            }
+                            if delta.seq != last_seq + 1 {
+                                warn!("Delta gap for client {} ({} -> {}), resending snapshot", connection_id, last_seq, delta.seq);
+                                let snapshot = self.status_stream.snapshot().await;
+                                delta_seq = Some(snapshot.seq);
+                                SharedMessage::new(ServerMessage::Snapshot(snapshot))
+                            } else {
+                                delta_seq = Some(delta.seq);
+                                shared
+                            }
+                        }
+                        (ServerMessage::Snapshot(snapshot), _) => {
+                            delta_seq = Some(snapshot.seq);
+                            shared
+                        }
+                        _ => shared,
+                    };
+                    let Some(shared) = filter_message(shared, &filter_rx.borrow(), &mut visible) else {
+                        continue;
                     };
+                    debug!("Broadcasting message to client {}: {:?}", connection_id, shared.message());
+                    let Some(frame) = shared.frame(*format_rx.borrow()) else {
+                        continue;
+                    };
+
+                    match tokio::time::timeout(SEND_TIMEOUT, sender.send(frame)).await {
+                        Ok(Ok(())) => {
+                            debug!("Successfully sent broadcast message to client {}", connection_id);
+                        }
+                        Ok(Err(e)) => {
+                            error!("Failed to send broadcast message to client {}: {}", connection_id, e);
+                            break;
+                        }
+                        Err(_) => {
+                            warn!("Sending to client {} timed out, disconnecting slow consumer", connection_id);
+                            self.viewer_stats.slow_disconnect();
+                            break;
+                        }
+                    }
 
-                    if let Err(e) = sender.send(Message::Text(json_msg.into())).await {
-                        error!("Failed to send broadcast message to client {}: {}", client_id, e);
+                    if close {
+                        info!("Closing connection on server request: {}", connection_id);
                         break;
-                    } else {
-                        debug!("Successfully sent broadcast message to client {}", client_id);
                     }
                 }
-            } => {}
+            } => {},
+            // 送信が詰まっていても、切断の指示から一定時間で閉じる
+            _ = async {
+                if close_rx.wait_for(|closed| *closed).await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+                tokio::time::sleep(CLOSE_GRACE).await;
+            } => {
+                warn!("Closing connection {} without flushing its outbound queue", connection_id);
+            }
         }
 
         // クリーンアップ
-        if let Some(client_data) = self.client_manager.remove_client(&client_id).await {
+        let agent_id = client.agent_id;
+        self.sessions.write().await.remove(&connection_id);
+        // 結果を待っている要求には切断を知らせる
+        self.pending_commands.lock().await.retain(|_, pending| pending.connection_id != connection_id);
+        if lagging {
+            self.viewer_stats.lag_ended();
+        }
+        let removed = match &agent_id {
+            Some(agent_id) => self.client_manager.remove_client(agent_id, &connection_id).await,
+            None => None,
+        };
+        if let Some(client_data) = removed {
             // 切断通知をブロードキャスト
             let toast = pc_status_shared::ToastData {
                 message: format!("{} is disconnected", client_data.hostname),
                 color: "#0508".to_string(),
                 toast_time: 5000,
             };
-            let _ = self.broadcast_tx.send(ServerMessage::Toast(toast));
+            let _ = self
+                .broadcast_tx
+                .send(SharedMessage::for_tenant(ServerMessage::Toast(toast), &client_data.tenant));
+            self.notifier.notify(Notification::Disconnected {
+                host: agent_id.clone().unwrap_or_default(),
+                hostname: client_data.hostname,
+                time: chrono::Utc::now(),
+            });
         }
-        info!("Client disconnected: {}", client_id);
+        info!("Client disconnected: {}", connection_id);
     }
 
-    async fn handle_text_message(
+    async fn handle_client_message(
         &self,
-        client_id: &str,
-        text: &str,
+        connection_id: &str,
+        client: &mut ClientState,
+        bytes: &[u8],
+        format: WireFormat,
     ) -> Result<()> {
-        debug!("Received message from {}: {}", client_id, text);
+        let ClientState { agent_id, format_tx, filter_tx, sync_limit } = client;
+        match ClientMessage::decode(bytes, format) {
+            Ok(ClientMessage::Hi { data, pass, machine_id, protocol, capabilities }) => {
+                format_tx.send_replace(format);
+
+                let protocol = protocol.unwrap_or(LEGACY_PROTOCOL_VERSION);
+                if !(self.min_agent_protocol..=PROTOCOL_VERSION).contains(&protocol) {
+                    let message = format!(
+                        "Agent protocol version {} is not supported (supported: {}-{})",
+                        protocol, self.min_agent_protocol, PROTOCOL_VERSION
+                    );
+                    self.reject(connection_id, ErrorCode::IncompatibleProtocol, message, None).await;
+                    return Ok(());
+                }
+                let negotiated: Vec<_> = capabilities
+                    .into_iter()
+                    .filter(|capability| SERVER_CAPABILITIES.contains(capability))
+                    .collect();
+                debug!("Client {} uses protocol {} with capabilities {:?}", connection_id, protocol, negotiated);
+
+                // パスワード認証（パスワードから所属するテナントが決まる）
+                let remote = self.remote_ip(connection_id).await;
+                if let Some(ip) = remote
+                    && let Err(retry) = self.auth_guard.attempt(ip, Instant::now())
+                {
+                    let message = "Too many authentication attempts".to_string();
+                    let retry = retry.as_secs().max(1);
+                    self.reject(connection_id, ErrorCode::Overloaded, message, Some(retry)).await;
+                    return Ok(());
+                }
+                let provided_pass = data.pass.as_ref().or(pass.as_ref());
+                let Some(tenant) = provided_pass.and_then(|pass| self.tenants.agent_tenant(pass)).map(str::to_string)
+                else {
+                    warn!("Invalid password from client: {}", connection_id);
+                    let event = AuditEvent::new(AuditAction::AuthFailure, remote)
+                        .target(data.hostname.as_str())
+                        .detail("agent password");
+                    self.auth_guard.record_failure(&self.audit, event).await;
+                    self.reject(connection_id, ErrorCode::AuthFailed, "Authentication failed".to_string(), None)
+                        .await;
+                    return Ok(());
+                };
 
-        match ClientMessage::from_json(text) {
-            Ok(ClientMessage::Hi { data, pass }) => {
-                self.handle_hi_message(client_id, data, pass).await?;
+                // マシンIDを送信しない旧エージェントは接続IDで識別する
+                let machine_id = machine_id
+                    .filter(|id| is_valid_machine_id(id))
+                    .unwrap_or_else(|| connection_id.to_string());
+                let id = self.tenants.agent_id(&tenant, &machine_id);
+                if let Some(ip) = remote {
+                    self.auth_guard.success(ip, Instant::now());
+                }
+                let event = AuditEvent::new(AuditAction::AuthSuccess, remote)
+                    .target(id.as_str())
+                    .detail(format!("hostname={} tenant={}", data.hostname, tenant));
+                self.audit.record(event).await;
+                if self.handle_hi_message(&id, connection_id, &tenant, negotiated, data).await? {
+                    *agent_id = Some(id);
+                    let scope = self.tenants.agent_scope(&tenant);
+                    filter_tx.send_modify(|filter| filter.scope = scope);
+                }
+            }
+            Ok(ClientMessage::Sync(_)) if sync_limit.as_mut().is_some_and(|limit| !limit.try_take(Instant::now())) => {
+                debug!("Dropping Sync from {}: too frequent", connection_id);
             }
             Ok(ClientMessage::Sync(data)) => {
-                self.handle_sync_message(client_id, data).await?;
+                match agent_id {
+                    Some(agent_id) => self.handle_sync_message(agent_id, connection_id, data).await?,
+                    None => debug!("Sync message before Hi from {}", connection_id),
+                }
+            }
+            Ok(ClientMessage::CommandResult(result)) => {
+                match agent_id {
+                    Some(_) => self.handle_command_result(connection_id, result).await,
+                    None => debug!("Command result before Hi from {}", connection_id),
+                }
             }
-            Ok(ClientMessage::Only(hostname)) => {
-                debug!("Only message for hostname: {}", hostname);
-                // TODO: フィルタリング機能を実装
+            Ok(ClientMessage::UpdateReport(report)) => {
+                match agent_id {
+                    Some(agent_id) => self.handle_update_report(agent_id, connection_id, report).await,
+                    None => debug!("Update report before Hi from {}", connection_id),
+                }
+            }
+            Ok(ClientMessage::Only(_) | ClientMessage::Subscribe)
+                if agent_id.is_none() && filter_tx.borrow().scope.is_none() =>
+            {
+                let message = "Viewer token required".to_string();
+                self.reject(connection_id, ErrorCode::AuthFailed, message, None).await;
+            }
+            Ok(ClientMessage::Only(selector)) => {
+                debug!("Only message from {}: {}", connection_id, selector);
+                match selector.parse::<Selector>() {
+                    Ok(selector) => {
+                        filter_tx.send_modify(|filter| filter.selector = selector);
+                    }
+                    Err(e) => warn!("Ignoring selector from {}: {}", connection_id, e),
+                }
+            }
+            Ok(ClientMessage::Subscribe) => {
+                debug!("Delta subscription from {}", connection_id);
+                format_tx.send_replace(format);
+                let snapshot = self.status_stream.snapshot().await;
+                self.send_to(connection_id, ServerMessage::Snapshot(snapshot)).await;
             }
             Err(e) => {
                 warn!("Failed to parse message: {}", e);
@@ -152,34 +871,64 @@ impl WebSocketServer {
     async fn handle_hi_message(
         &self,
         client_id: &str,
+        connection_id: &str,
+        tenant: &str,
+        capabilities: Vec<Capability>,
         mut data: StatusData,
-        pass: Option<String>,
-    ) -> Result<()> {
-        // パスワード認証
-        let provided_pass = data.pass.as_ref().or(pass.as_ref());
-        if provided_pass != Some(&self.password) {
-            warn!("Invalid password from client: {}", client_id);
-            // 認証失敗時はクライアントを切断（実際の切断はWebSocketレベルで処理）
-            return Err(anyhow::anyhow!("Authentication failed"));
+    ) -> Result<bool> {
+        if !self.sanitize(client_id, connection_id, &mut data).await {
+            return Ok(false);
         }
-
-        // 重複ホスト名の処理
-        if self.client_manager.hostname_exists(&data.hostname).await {
-            if data.dev.unwrap_or(false) {
-                let count = self.client_manager.get_hostname_count(&data.hostname).await;
-                data.index = count;
-                data.hostname = format!("[DEV] {}_{}", data.hostname, data.index);
-            } else {
-                // 重複ホスト名で開発モードでない場合は切断
-                return Err(anyhow::anyhow!("Duplicate hostname"));
-            }
+        if self.bans.is_hostname_banned(&data.hostname).await {
+            let message = format!("Hostname {} is banned by administrator", data.hostname);
+            self.reject(connection_id, ErrorCode::Banned, message, Some(BANNED_RETRY_SECS)).await;
+            return Ok(false);
         }
 
+        // エージェントが送った値ではなく認証したテナントを使う
+        data.tenant = tenant.to_string();
+
         // 履歴を初期化
         data.histories = vec![];
+        // サーバー側のタグ・ラベルを付ける
+        self.host_labels.apply(&mut data);
 
-        // クライアントを登録
-        self.client_manager.add_client(client_id, data.clone()).await;
+        // クライアントを登録（重複ホスト名は設定されたポリシーで処理）
+        let remote = self.remote_ip(connection_id).await;
+        let registration = match self
+            .client_manager
+            .register_client(client_id, connection_id, remote, data.clone())
+            .await
+        {
+            Ok(registration) => registration,
+            Err(e) => {
+                let (code, retry) = match e {
+                    RegisterError::DuplicateHostname(_) | RegisterError::DuplicateAgent(_) => {
+                        let event = AuditEvent::new(AuditAction::DuplicateHostname, remote)
+                            .target(data.hostname.as_str())
+                            .detail(format!("agent={}", client_id));
+                        self.audit.record(event).await;
+                        (ErrorCode::DuplicateHostname, DUPLICATE_RETRY_SECS)
+                    }
+                    RegisterError::TooManyClients(_) => (ErrorCode::Overloaded, OVERLOADED_RETRY_SECS),
+                };
+                self.reject(connection_id, code, e.to_string(), Some(retry)).await;
+                return Ok(false);
+            }
+        };
+
+        // 置き換えられた古いセッションを切断
+        for replaced in &registration.replaced {
+            self.close(replaced).await;
+        }
+
+        let accepts_updates = capabilities.contains(&Capability::Updates);
+        if let Some(session) = self.sessions.write().await.get_mut(connection_id) {
+            session.info.agent_id = Some(client_id.to_string());
+            session.info.hostname = Some(data.hostname.clone());
+            session.info.tenant = Some(tenant.to_string());
+            session.info.capabilities = capabilities;
+        }
 
         // 接続通知をブロードキャスト
         let toast = pc_status_shared::ToastData {
@@ -187,18 +936,299 @@ impl WebSocketServer {
             color: "#0508".to_string(),
             toast_time: 5000,
         };
-        let _ = self.broadcast_tx.send(ServerMessage::Toast(toast));
+        let _ = self.broadcast_tx.send(SharedMessage::for_tenant(ServerMessage::Toast(toast), tenant));
+        self.notifier.notify(Notification::Connected {
+            host: client_id.to_string(),
+            hostname: data.hostname.clone(),
+            time: chrono::Utc::now(),
+        });
 
         info!("Client registered: {} ({})", client_id, data.hostname);
-        Ok(())
+        if accepts_updates {
+            // Syncはすべての接続に配信されるため、登録の確認はこの接続にだけ送る
+            self.send_to(connection_id, ServerMessage::Registered).await;
+            self.offer_update(client_id, connection_id, &data).await;
+        }
+        Ok(true)
     }
 
-    async fn handle_sync_message(&self, client_id: &str, data: StatusData) -> Result<()> {
+    async fn handle_sync_message(&self, client_id: &str, connection_id: &str, mut data: StatusData) -> Result<()> {
+        if !self.sanitize(client_id, connection_id, &mut data).await {
+            return Ok(());
+        }
         self.client_manager.update_client(client_id, data).await;
 
         // 同期メッセージをクライアントに送信
-        let _ = self.broadcast_tx.send(ServerMessage::Sync("sync".to_string()));
+        let _ = self.broadcast_tx.send(SharedMessage::new(ServerMessage::Sync("sync".to_string())));
 
         Ok(())
     }
 }
+
+/// 絞り込み条件に一致するホストだけのメッセージにする（送らない場合はNone）
+///
+/// `visible`には直前までに送ったホストのIDを保持し、差分の変換とアラートの絞り込みに使う。
+fn filter_message(
+    shared: Arc<SharedMessage>,
+    filter: &ConnectionFilter,
+    visible: &mut HashSet<String>,
+) -> Option<Arc<SharedMessage>> {
+    if filter.is_unrestricted() {
+        return Some(shared);
+    }
+    if let Some(tenant) = shared.tenant()
+        && !filter.scope.allows(tenant)
+    {
+        return None;
+    }
+    let matching = |clients: &ClientData| -> ClientData {
+        clients
+            .iter()
+            .filter(|(_, status)| filter.matches(status))
+            .map(|(id, status)| (id.clone(), status.clone()))
+            .collect()
+    };
+    let message = match shared.message() {
+        ServerMessage::Status(clients) => {
+            let clients = matching(clients);
+            *visible = clients.keys().cloned().collect();
+            ServerMessage::Status(clients)
+        }
+        ServerMessage::Snapshot(snapshot) => {
+            let clients = matching(&snapshot.clients);
+            *visible = clients.keys().cloned().collect();
+            ServerMessage::Snapshot(StatusSnapshot { seq: snapshot.seq, clients })
+        }
+        ServerMessage::Delta(delta) => ServerMessage::Delta(delta.filter(visible, |status| filter.matches(status))),
+        ServerMessage::Alert(alert)
+            if !filter.scope.allows(&alert.tenant)
+                || (!filter.selector.is_empty() && !visible.contains(&alert.host)) =>
+        {
+            return None;
+        }
+        _ => return Some(shared),
+    };
+    Some(SharedMessage::new(message))
+}
+
+/// マシンIDとして受け入れ可能な文字列か
+fn is_valid_machine_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl FromRef<WebSocketServer> for Arc<Tenants> {
+    fn from_ref(server: &WebSocketServer) -> Self {
+        server.tenants.clone()
+    }
+}
+
+impl FromRef<WebSocketServer> for Arc<AuthGuard> {
+    fn from_ref(server: &WebSocketServer) -> Self {
+        server.auth_guard.clone()
+    }
+}
+
+impl FromRef<WebSocketServer> for Arc<AuditLog> {
+    fn from_ref(server: &WebSocketServer) -> Self {
+        server.audit.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_manager::ConflictPolicy;
+    use axum::{routing::get, Router};
+    use pc_status_shared::test_support::status;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn server() -> WebSocketServer {
+        WebSocketServer::new(
+            ClientManager::new(ConflictPolicy::Reject, None, Duration::from_secs(3600)),
+            Arc::new(Tenants::single("pass".to_string())),
+            LEGACY_PROTOCOL_VERSION,
+            HostLabels::new(vec![]),
+            AuditLog::new(),
+            Limits::default(),
+        )
+    }
+
+    /// テスト用のサーバーを起動し、WebSocketのURLを返す
+    async fn start(server: WebSocketServer) -> String {
+        let app = Router::new().route("/ws", get(WebSocketServer::handle_websocket_upgrade)).with_state(server);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+        });
+        url
+    }
+
+    /// 接続してサーバーの挨拶を読み、接続IDを返す
+    async fn connect(server: &WebSocketServer, url: &str) -> (Client, String) {
+        let known: HashSet<String> = server.sessions().await.into_iter().map(|session| session.id).collect();
+        let (mut client, _) = connect_async(url).await.unwrap();
+        assert!(matches!(recv(&mut client).await, Some(ServerMessage::Hi(_))));
+        let id = loop {
+            if let Some(session) = server.sessions().await.into_iter().find(|session| !known.contains(&session.id)) {
+                break session.id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        (client, id)
+    }
+
+    /// 次のメッセージ（閉じられた場合・届かない場合はNone）
+    async fn recv(client: &mut Client) -> Option<ServerMessage> {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), client.next()).await.ok()??.ok()?;
+            match frame {
+                WsMessage::Text(text) => return ServerMessage::decode(text.as_bytes(), WireFormat::Json).ok(),
+                WsMessage::Binary(bytes) => return ServerMessage::decode(&bytes, WireFormat::MessagePack).ok(),
+                WsMessage::Close(_) => return None,
+                _ => continue,
+            }
+        }
+    }
+
+    async fn send(client: &mut Client, message: ClientMessage) {
+        client.send(WsMessage::Text(message.to_json().unwrap().into())).await.unwrap();
+    }
+
+    /// 自動更新に対応したエージェントのHi
+    fn hi(machine_id: &str, pass: &str) -> ClientMessage {
+        ClientMessage::Hi {
+            data: status(machine_id),
+            pass: Some(pass.to_string()),
+            machine_id: Some(machine_id.to_string()),
+            protocol: Some(PROTOCOL_VERSION),
+            capabilities: vec![Capability::Updates],
+        }
+    }
+
+    async fn wait_until_disconnected(server: &WebSocketServer, id: &str, within: Duration) -> bool {
+        tokio::time::timeout(within, async {
+            while server.sessions().await.iter().any(|session| session.id == id) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_closes_a_client_whose_queue_is_full() {
+        let server = server();
+        let url = start(server.clone()).await;
+        // 受信しない接続
+        let (_client, id) = connect(&server, &url).await;
+
+        // 送信待ちのキューを埋めて、Closeを積めない状態にする
+        let tx = server.sessions.read().await[&id].tx.clone();
+        let toast = ToastData { message: "queued".to_string(), color: String::new(), toast_time: 0 };
+        while tx.try_send(SharedMessage::new(ServerMessage::Toast(toast.clone()))).is_ok() {}
+
+        assert!(server.disconnect(&id).await.is_some());
+        assert!(wait_until_disconnected(&server, &id, CLOSE_GRACE + Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_flushes_queued_messages_first() {
+        let server = server();
+        let url = start(server.clone()).await;
+        let (mut client, id) = connect(&server, &url).await;
+
+        server.reject(&id, ErrorCode::Banned, "Banned by administrator".to_string(), Some(BANNED_RETRY_SECS)).await;
+        assert!(matches!(recv(&mut client).await, Some(ServerMessage::Error { code: ErrorCode::Banned, .. })));
+        assert!(matches!(recv(&mut client).await, Some(ServerMessage::Close)));
+        assert!(wait_until_disconnected(&server, &id, Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_delta_subscriber_is_resynced_with_a_snapshot() {
+        let server = server();
+        let url = start(server.clone()).await;
+        let (mut viewer, _) = connect(&server, &url).await;
+        send(&mut viewer, ClientMessage::Subscribe).await;
+        assert!(matches!(recv(&mut viewer).await, Some(ServerMessage::Snapshot(_))));
+
+        // テストのランタイムは単一スレッドのため、送り終わるまで転送されずに容量を超える
+        let toast = ToastData { message: "flood".to_string(), color: String::new(), toast_time: 0 };
+        for _ in 0..BROADCAST_CAPACITY * 2 {
+            server.broadcast_toast(toast.clone());
+        }
+
+        assert!(matches!(recv(&mut viewer).await, Some(ServerMessage::Snapshot(_))));
+        for _ in 0..BROADCAST_CAPACITY {
+            assert!(matches!(recv(&mut viewer).await, Some(ServerMessage::Toast(_))));
+        }
+        let stats = server.viewer_stats.snapshot();
+        assert_eq!(stats.lag_events, 1);
+        assert_eq!(stats.dropped_messages, BROADCAST_CAPACITY as u64);
+        assert_eq!(stats.lagging, 0);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_viewer_skips_stale_statuses() {
+        let server = server();
+        let url = start(server.clone()).await;
+        let (mut viewer, _) = connect(&server, &url).await;
+
+        for index in 0..BROADCAST_CAPACITY * 2 {
+            let clients = [("agent".to_string(), status(&format!("host-{}", index)))].into_iter().collect();
+            let _ = server.broadcast_tx.send(SharedMessage::new(ServerMessage::Status(clients)));
+        }
+
+        // 追いついた時点の最新の状態だけを受け取る
+        match recv(&mut viewer).await {
+            Some(ServerMessage::Status(clients)) => {
+                assert_eq!(clients["agent"].hostname, format!("host-{}", BROADCAST_CAPACITY * 2 - 1));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let stats = server.viewer_stats.snapshot();
+        assert_eq!(stats.lag_events, 1);
+        assert_eq!(stats.dropped_messages, BROADCAST_CAPACITY as u64 * 2 - 1);
+        assert_eq!(stats.lagging, 0);
+    }
+
+    #[tokio::test]
+    async fn test_registered_is_sent_only_to_the_registered_connection() {
+        let server = server();
+        let url = start(server.clone()).await;
+        let (mut agent, _) = connect(&server, &url).await;
+        let (mut rejected, _) = connect(&server, &url).await;
+
+        send(&mut agent, hi("agent-1", "pass")).await;
+        loop {
+            match recv(&mut agent).await {
+                Some(ServerMessage::Registered) => break,
+                Some(_) => continue,
+                None => panic!("agent was not registered"),
+            }
+        }
+        // ほかのエージェントのSyncは登録前の接続にも届く
+        send(&mut agent, ClientMessage::Sync(status("agent-1"))).await;
+        loop {
+            match recv(&mut rejected).await {
+                Some(ServerMessage::Sync(_)) => break,
+                Some(_) => continue,
+                None => panic!("sync was not broadcast"),
+            }
+        }
+
+        send(&mut rejected, hi("agent-2", "wrong")).await;
+        let mut received = vec![];
+        while let Some(message) = recv(&mut rejected).await {
+            received.push(message);
+        }
+        assert!(received.iter().any(|message| matches!(message, ServerMessage::Error { code: ErrorCode::AuthFailed, .. })));
+        assert!(!received.iter().any(|message| matches!(message, ServerMessage::Registered)));
+    }
+}
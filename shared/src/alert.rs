@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// アラートの状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    /// 条件を満たしているが、継続時間に達していない
+    Pending,
+    /// 発報中
+    Firing,
+    /// 発報後に条件が解消された
+    Resolved,
+}
+
+/// ルールとホストの組ごとのアラート
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// ルール名
+    pub rule: String,
+    /// エージェントのID
+    pub host: String,
+    pub hostname: String,
+    /// ホストが所属するテナント
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tenant: String,
+    pub state: AlertState,
+    /// 最後に評価したときの値
+    pub value: f64,
+    pub threshold: f64,
+    pub message: String,
+    /// 現在の状態になった時刻
+    pub since: DateTime<Utc>,
+}
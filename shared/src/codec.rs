@@ -0,0 +1,53 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// WebSocketで使用するメッセージのエンコード形式
+///
+/// JSONはテキストフレーム、MessagePackはバイナリフレームで送信する。
+/// フレームの種類で形式が判別できるため、受信側は常に両方を受け付ける。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireFormat {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "msgpack" | "messagepack" => Ok(Self::MessagePack),
+            other => Err(format!("Unknown wire format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("MessagePack encode error: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+    #[error("MessagePack decode error: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+}
+
+pub(crate) fn encode<T: Serialize>(value: &T, format: WireFormat) -> Result<Vec<u8>, CodecError> {
+    match format {
+        WireFormat::Json => Ok(serde_json::to_vec(value)?),
+        // 省略可能なフィールドがあるため、フィールド名付きのマップとしてエンコードする
+        WireFormat::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+    }
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8], format: WireFormat) -> Result<T, CodecError> {
+    match format {
+        WireFormat::Json => Ok(serde_json::from_slice(bytes)?),
+        WireFormat::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+    }
+}
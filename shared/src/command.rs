@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// サーバーからエージェントへのコマンドの実行要求
+///
+/// エージェントは`command`を自身の許可リストの名前として解釈し、任意のコマンドラインは受け付けない。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRequest {
+    /// 結果を対応付ける要求ID
+    pub id: String,
+    /// 許可リストに登録されたコマンドの名前
+    pub command: String,
+    /// 要求した管理者の接続元（エージェントの監査ログに記録する）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_by: Option<String>,
+}
+
+/// コマンドの実行結果の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    /// 終了した（成否は`exit_code`を参照）
+    Completed,
+    /// 起動できなかった
+    Failed,
+    /// 制限時間を超えたため強制終了した
+    TimedOut,
+    /// 許可リストにないコマンド
+    NotAllowed,
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+            Self::NotAllowed => "not_allowed",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// エージェントが返すコマンドの実行結果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandResult {
+    /// 要求ID
+    pub id: String,
+    pub status: CommandStatus,
+    /// 終了コード（シグナルで終了した場合や実行しなかった場合はNone）
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    /// 上限を超えた出力を切り詰めたか
+    #[serde(default)]
+    pub truncated: bool,
+    /// 実行にかかった時間（ミリ秒）
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+impl CommandResult {
+    /// 実行しなかった場合の結果
+    pub fn rejected(id: impl Into<String>, status: CommandStatus, message: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            status,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: message.into(),
+            truncated: false,
+            duration_ms: 0,
+        }
+    }
+
+    /// 終了コード0で終了したか
+    pub fn succeeded(&self) -> bool {
+        self.status == CommandStatus::Completed && self.exit_code == Some(0)
+    }
+}
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::types::{ClientData, Cpu, Gpu, HistoriesData, Ram, StatusData, Storage, Swap};
+
+/// 差分配信の起点となる完全なスナップショット
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusSnapshot {
+    pub seq: u64,
+    pub clients: ClientData,
+}
+
+/// 1ホスト分の変更点（変更のあったフィールドのみ含む）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<Cpu>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram: Option<Ram>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<Swap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storages: Option<Vec<Storage>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loadavg: Option<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<Vec<Gpu>>,
+    /// 履歴の先頭から削除する件数
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub histories_dropped: usize,
+    /// 履歴の末尾に追加するサンプル
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histories: Vec<HistoriesData>,
+}
+
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// 直前のシーケンス番号からの変更点
+///
+/// `seq`は連番で、受信側は欠番を検出したらスナップショットを再要求する。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusDelta {
+    pub seq: u64,
+    /// 新規（または識別情報が変わった）ホストの完全なデータ
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub added: ClientData,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub updated: HashMap<String, HostDelta>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl HostDelta {
+    /// `previous`から`next`への変更点を求める
+    fn between(previous: &StatusData, next: &StatusData) -> Self {
+        let mut delta = HostDelta::default();
+        if previous.cpu != next.cpu {
+            delta.cpu = Some(next.cpu.clone());
+        }
+        if previous.ram != next.ram {
+            delta.ram = Some(next.ram.clone());
+        }
+        if previous.swap != next.swap {
+            delta.swap = Some(next.swap.clone());
+        }
+        if previous.storages != next.storages {
+            delta.storages = Some(next.storages.clone());
+        }
+        if previous.uptime != next.uptime {
+            delta.uptime = Some(next.uptime);
+        }
+        if previous.loadavg != next.loadavg {
+            delta.loadavg = Some(next.loadavg);
+        }
+        if previous.gpus != next.gpus {
+            delta.gpus = Some(next.gpus.clone());
+        }
+
+        // 旧履歴の末尾と新履歴の先頭が一致する位置を探し、追加分だけを送る
+        let old = &previous.histories;
+        let new = &next.histories;
+        let dropped = (0..=old.len())
+            .find(|&dropped| {
+                let kept = &old[dropped..];
+                kept.len() <= new.len() && new[..kept.len()] == *kept
+            })
+            .unwrap_or(old.len());
+        delta.histories_dropped = dropped;
+        delta.histories = new[old.len() - dropped..].to_vec();
+
+        delta
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == HostDelta::default()
+    }
+
+    fn apply(&self, status: &mut StatusData) {
+        if let Some(cpu) = &self.cpu {
+            status.cpu = cpu.clone();
+        }
+        if let Some(ram) = &self.ram {
+            status.ram = ram.clone();
+        }
+        if let Some(swap) = &self.swap {
+            status.swap = swap.clone();
+        }
+        if let Some(storages) = &self.storages {
+            status.storages = storages.clone();
+        }
+        if let Some(uptime) = self.uptime {
+            status.uptime = uptime;
+        }
+        if let Some(loadavg) = self.loadavg {
+            status.loadavg = loadavg;
+        }
+        if let Some(gpus) = &self.gpus {
+            status.gpus = gpus.clone();
+        }
+        let dropped = self.histories_dropped.min(status.histories.len());
+        status.histories.drain(..dropped);
+        status.histories.extend(self.histories.iter().cloned());
+    }
+}
+
+/// ホスト名やバージョンなど、差分ではなく再追加として扱うフィールドが同じか
+fn same_identity(previous: &StatusData, next: &StatusData) -> bool {
+    previous.hostname == next.hostname
+        && previous.os == next.os
+        && previous.version == next.version
+        && previous.index == next.index
+        && previous.dev == next.dev
+        && previous.tags == next.tags
+        && previous.labels == next.labels
+        && previous.tenant == next.tenant
+}
+
+impl StatusDelta {
+    /// `previous`から`next`への差分を求める
+    pub fn between(seq: u64, previous: &ClientData, next: &ClientData) -> Self {
+        let mut delta = StatusDelta {
+            seq,
+            ..Default::default()
+        };
+
+        for (id, status) in next {
+            match previous.get(id) {
+                Some(old) if same_identity(old, status) => {
+                    let host_delta = HostDelta::between(old, status);
+                    if !host_delta.is_empty() {
+                        delta.updated.insert(id.clone(), host_delta);
+                    }
+                }
+                _ => {
+                    delta.added.insert(id.clone(), status.clone());
+                }
+            }
+        }
+        delta.removed = previous
+            .keys()
+            .filter(|id| !next.contains_key(*id))
+            .cloned()
+            .collect();
+
+        delta
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// 条件に一致するホストだけの差分にする
+    ///
+    /// `visible`は受信側に送ったホストのIDで、呼び出しごとに更新する。
+    /// タグやラベルが変わったホストは再追加として扱われるため、一致しなくなったホストは削除として送る。
+    /// 受信側が欠番を検出しないよう、空になっても`seq`はそのまま返す。
+    pub fn filter(&self, visible: &mut HashSet<String>, matches: impl Fn(&StatusData) -> bool) -> StatusDelta {
+        let mut delta = StatusDelta {
+            seq: self.seq,
+            ..Default::default()
+        };
+        for id in &self.removed {
+            if visible.remove(id) {
+                delta.removed.push(id.clone());
+            }
+        }
+        for (id, status) in &self.added {
+            if matches(status) {
+                visible.insert(id.clone());
+                delta.added.insert(id.clone(), status.clone());
+            } else if visible.remove(id) {
+                delta.removed.push(id.clone());
+            }
+        }
+        for (id, host_delta) in &self.updated {
+            if visible.contains(id) {
+                delta.updated.insert(id.clone(), host_delta.clone());
+            }
+        }
+        delta
+    }
+
+    /// 受信済みの状態に差分を適用する
+    pub fn apply(&self, clients: &mut ClientData) {
+        for id in &self.removed {
+            clients.remove(id);
+        }
+        for (id, status) in &self.added {
+            clients.insert(id.clone(), status.clone());
+        }
+        for (id, host_delta) in &self.updated {
+            if let Some(status) = clients.get_mut(id) {
+                host_delta.apply(status);
+            }
+        }
+    }
+}
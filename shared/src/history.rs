@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{HistoriesData, StatusData};
+
+/// REST APIで返すホストの情報
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// エージェントのID
+    pub id: String,
+    #[serde(flatten)]
+    pub status: StatusData,
+}
+
+/// 時刻付きの履歴（`/api/hosts/{host}/history`）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistorySample {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub data: HistoriesData,
+    pub loadavg: [f64; 3],
+    /// 記録した時点のタグ
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// 記録した時点のラベル
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl HistorySample {
+    pub fn new(time: DateTime<Utc>, status: &StatusData) -> Self {
+        Self {
+            time,
+            data: HistoriesData {
+                cpu: status.cpu.clone(),
+                ram: status.ram.clone(),
+                swap: status.swap.clone(),
+                storages: status.storages.clone(),
+                gpus: status.gpus.clone(),
+                uptime: status.uptime,
+            },
+            loadavg: status.loadavg,
+            tags: status.tags.clone(),
+            labels: status.labels.clone(),
+        }
+    }
+}
+
+/// `30s`・`15m`・`1h`・`7d`形式の期間を読む（単位なしは秒）
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+/// 期間（`1h`など）またはRFC 3339の時刻を、その時刻以降を表す開始時刻に変換する
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Some(duration) = parse_duration(value) {
+        return Some(now - chrono::Duration::from_std(duration).ok()?);
+    }
+    DateTime::parse_from_rfc3339(value).ok().map(|time| time.with_timezone(&Utc))
+}
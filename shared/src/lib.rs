@@ -1,12 +1,33 @@
 pub mod types;
 pub mod messages;
+pub mod delta;
+pub mod codec;
+pub mod protocol;
+pub mod alert;
+pub mod command;
+pub mod history;
+pub mod selector;
+pub mod validation;
+pub mod update;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 
 pub use types::*;
 pub use messages::*;
+pub use delta::*;
+pub use codec::{CodecError, WireFormat};
+pub use protocol::*;
+pub use alert::*;
+pub use command::*;
+pub use history::*;
+pub use selector::*;
+pub use validation::*;
+pub use update::*;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     #[test]
     fn test_status_data_serialization() {
@@ -29,9 +50,7 @@ mod tests {
             }],
             uptime: 3600,
             loadavg: [1.0, 1.5, 2.0],
-            gpus: vec![],
-            index: 0,
-            histories: vec![],
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&status).unwrap();
@@ -55,24 +74,62 @@ mod tests {
                     cpus: vec![],
                 },
                 ram: Ram { free: 4000, total: 8000 },
-                swap: Swap { free: 0, total: 0 },
-                storages: vec![],
                 uptime: 7200,
                 loadavg: [0.5, 0.7, 0.9],
-                gpus: vec![],
-                index: 0,
-                histories: vec![],
+                ..Default::default()
             },
             pass: Some("password".to_string()),
+            machine_id: Some("machine-1".to_string()),
+            protocol: Some(PROTOCOL_VERSION),
+            capabilities: vec![Capability::Binary],
         };
 
         let json = message.to_json().unwrap();
         let deserialized = ClientMessage::from_json(&json).unwrap();
 
         match deserialized {
-            ClientMessage::Hi { data, pass } => {
+            ClientMessage::Hi { data, pass, machine_id, protocol, capabilities } => {
                 assert_eq!(data.hostname, "linux-pc");
                 assert_eq!(pass, Some("password".to_string()));
+                assert_eq!(machine_id, Some("machine-1".to_string()));
+                assert_eq!(protocol, Some(PROTOCOL_VERSION));
+                assert_eq!(capabilities, vec![Capability::Binary]);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_client_hi_without_machine_id() {
+        let mut value = serde_json::to_value(ClientMessage::Hi {
+            data: StatusData {
+                os: "Linux".to_string(),
+                hostname: "old-agent".to_string(),
+                version: "1.0.0".to_string(),
+                cpu: Cpu {
+                    model: "AMD Ryzen".to_string(),
+                    cpus: vec![],
+                },
+                ..Default::default()
+            },
+            pass: None,
+            machine_id: None,
+            protocol: None,
+            capabilities: vec![],
+        })
+        .unwrap();
+        // 旧バージョンのエージェントはmachine_id・protocol・capabilitiesを送信しない
+        let fields = value["data"].as_object_mut().unwrap();
+        fields.remove("machine_id");
+        fields.remove("protocol");
+        fields.remove("capabilities");
+
+        let deserialized = ClientMessage::from_json(&value.to_string()).unwrap();
+        match deserialized {
+            ClientMessage::Hi { machine_id, protocol, capabilities, .. } => {
+                assert_eq!(machine_id, None);
+                assert_eq!(protocol, None);
+                assert!(capabilities.is_empty());
             }
             _ => panic!("Wrong message type"),
         }
@@ -99,4 +156,384 @@ mod tests {
             _ => panic!("Wrong message type"),
         }
     }
+
+    fn sample_status(hostname: &str, cpu: f64) -> StatusData {
+        StatusData {
+            cpu: Cpu {
+                model: "AMD Ryzen".to_string(),
+                cpus: vec![CpuData { cpu }],
+            },
+            ram: Ram { free: 4000, total: 8000 },
+            uptime: 100,
+            loadavg: [0.5, 0.7, 0.9],
+            ..test_support::status(hostname)
+        }
+    }
+
+    fn sample_history(cpu: f64) -> HistoriesData {
+        let status = sample_status("history", cpu);
+        HistoriesData {
+            cpu: status.cpu,
+            ram: status.ram,
+            swap: status.swap,
+            storages: status.storages,
+            gpus: status.gpus,
+            uptime: status.uptime,
+        }
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut previous = ClientData::new();
+        let mut host = sample_status("web", 10.0);
+        // 最大件数に達した履歴（同じ値を含む）
+        host.histories = (0..10).map(|i| sample_history((i % 2) as f64)).collect();
+        previous.insert("a".to_string(), host.clone());
+        previous.insert("b".to_string(), sample_status("db", 5.0));
+
+        let mut next = previous.clone();
+        let updated = next.get_mut("a").unwrap();
+        updated.cpu.cpus[0].cpu = 20.0;
+        updated.histories.remove(0);
+        updated.histories.push(sample_history(0.0));
+        next.remove("b");
+        next.insert("c".to_string(), sample_status("cache", 1.0));
+
+        let delta = StatusDelta::between(1, &previous, &next);
+        assert_eq!(delta.removed, vec!["b".to_string()]);
+        assert!(delta.added.contains_key("c"));
+        let host_delta = &delta.updated["a"];
+        assert!(host_delta.cpu.is_some());
+        assert!(host_delta.ram.is_none());
+        assert!(host_delta.histories.len() < 10);
+
+        let json = ServerMessage::Delta(delta).to_json().unwrap();
+        let delta = match ServerMessage::from_json(&json).unwrap() {
+            ServerMessage::Delta(delta) => delta,
+            _ => panic!("Wrong message type"),
+        };
+
+        let mut applied = previous.clone();
+        delta.apply(&mut applied);
+        assert_eq!(applied, next);
+    }
+
+    #[test]
+    fn test_delta_unchanged_is_empty() {
+        let mut clients = ClientData::new();
+        clients.insert("a".to_string(), sample_status("web", 10.0));
+
+        let delta = StatusDelta::between(1, &clients, &clients.clone());
+        assert!(delta.is_empty());
+    }
+
+    #[test]
+    fn test_delta_identity_change_is_readded() {
+        let mut previous = ClientData::new();
+        previous.insert("a".to_string(), sample_status("web", 10.0));
+        let mut next = previous.clone();
+        next.get_mut("a").unwrap().version = "2.0.0".to_string();
+
+        let delta = StatusDelta::between(1, &previous, &next);
+        assert!(delta.updated.is_empty());
+        assert_eq!(delta.added["a"].version, "2.0.0");
+    }
+
+    #[test]
+    fn test_subscribe_message_serialization() {
+        let json = ClientMessage::Subscribe.to_json().unwrap();
+        assert_eq!(json, r#"{"type":"Subscribe"}"#);
+        assert!(matches!(ClientMessage::from_json(&json).unwrap(), ClientMessage::Subscribe));
+    }
+
+    #[test]
+    fn test_messagepack_round_trip() {
+        let mut host = sample_status("web", 10.0);
+        host.histories = vec![sample_history(1.0)];
+        let message = ClientMessage::Hi {
+            data: host,
+            pass: Some("password".to_string()),
+            machine_id: None,
+            protocol: Some(PROTOCOL_VERSION),
+            capabilities: vec![],
+        };
+
+        let bytes = message.encode(WireFormat::MessagePack).unwrap();
+        assert!(bytes.len() < message.to_json().unwrap().len());
+        match ClientMessage::decode(&bytes, WireFormat::MessagePack).unwrap() {
+            ClientMessage::Hi { data, pass, machine_id, .. } => {
+                assert_eq!(data.os, "Linux");
+                assert_eq!(data.histories.len(), 1);
+                assert_eq!(pass, Some("password".to_string()));
+                assert_eq!(machine_id, None);
+            }
+            _ => panic!("Wrong message type"),
+        }
+
+        let bytes = ClientMessage::Subscribe.encode(WireFormat::MessagePack).unwrap();
+        assert!(matches!(
+            ClientMessage::decode(&bytes, WireFormat::MessagePack).unwrap(),
+            ClientMessage::Subscribe
+        ));
+    }
+
+    #[test]
+    fn test_messagepack_delta_round_trip() {
+        let mut previous = ClientData::new();
+        previous.insert("a".to_string(), sample_status("web", 10.0));
+        let mut next = previous.clone();
+        next.get_mut("a").unwrap().uptime = 200;
+
+        let delta = StatusDelta::between(3, &previous, &next);
+        let bytes = ServerMessage::Delta(delta).encode(WireFormat::MessagePack).unwrap();
+        let delta = match ServerMessage::decode(&bytes, WireFormat::MessagePack).unwrap() {
+            ServerMessage::Delta(delta) => delta,
+            _ => panic!("Wrong message type"),
+        };
+        assert_eq!(delta.seq, 3);
+
+        let mut applied = previous.clone();
+        delta.apply(&mut applied);
+        assert_eq!(applied, next);
+
+        let bytes = ServerMessage::Close.encode(WireFormat::MessagePack).unwrap();
+        assert!(matches!(
+            ServerMessage::decode(&bytes, WireFormat::MessagePack).unwrap(),
+            ServerMessage::Close
+        ));
+    }
+
+    #[test]
+    fn test_wire_format_parse() {
+        assert_eq!("json".parse::<WireFormat>().unwrap(), WireFormat::Json);
+        assert_eq!("MsgPack".parse::<WireFormat>().unwrap(), WireFormat::MessagePack);
+        assert!("cbor".parse::<WireFormat>().is_err());
+    }
+
+    #[test]
+    fn test_server_hello_accepts_legacy_greeting() {
+        let hello = match ServerMessage::from_json(r#"{"type":"Hi","data":"hello"}"#).unwrap() {
+            ServerMessage::Hi(hello) => hello,
+            _ => panic!("Wrong message type"),
+        };
+        assert_eq!(hello.message, "hello");
+        assert_eq!(hello.protocol, LEGACY_PROTOCOL_VERSION);
+        assert!(hello.capabilities.is_empty());
+    }
+
+    #[test]
+    fn test_server_hello_round_trip() {
+        let message = ServerMessage::Hi(ServerHello {
+            message: "hello".to_string(),
+            protocol: PROTOCOL_VERSION,
+            capabilities: vec![Capability::Binary, Capability::Delta],
+        });
+
+        for format in [WireFormat::Json, WireFormat::MessagePack] {
+            let bytes = message.encode(format).unwrap();
+            match ServerMessage::decode(&bytes, format).unwrap() {
+                ServerMessage::Hi(hello) => {
+                    assert_eq!(hello.protocol, PROTOCOL_VERSION);
+                    assert!(hello.supports(Capability::Delta));
+                    assert!(!hello.supports(Capability::Compression));
+                }
+                _ => panic!("Wrong message type"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_capability_and_error_code() {
+        let json = r#"{"type":"Hi","data":{"message":"hello","protocol":3,"capabilities":["binary","teleport"]}}"#;
+        match ServerMessage::from_json(json).unwrap() {
+            ServerMessage::Hi(hello) => {
+                assert_eq!(hello.capabilities, vec![Capability::Binary, Capability::Unknown]);
+            }
+            _ => panic!("Wrong message type"),
+        }
+
+        let json = r#"{"type":"Error","data":{"code":"out_of_coffee","message":"try later"}}"#;
+        match ServerMessage::from_json(json).unwrap() {
+            ServerMessage::Error { code, message, retry } => {
+                assert_eq!(code, ErrorCode::Unknown);
+                assert_eq!(message, "try later");
+                assert_eq!(retry, None);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_error_message_serialization() {
+        let message = ServerMessage::Error {
+            code: ErrorCode::DuplicateHostname,
+            message: "web is already connected".to_string(),
+            retry: Some(60),
+        };
+        let json = message.to_json().unwrap();
+        assert!(json.contains(r#""code":"duplicate_hostname""#));
+
+        match ServerMessage::from_json(&json).unwrap() {
+            ServerMessage::Error { code, retry, .. } => {
+                assert_eq!(code, ErrorCode::DuplicateHostname);
+                assert_eq!(retry, Some(60));
+                assert!(!code.is_fatal());
+            }
+            _ => panic!("Wrong message type"),
+        }
+        assert!(ErrorCode::AuthFailed.is_fatal());
+    }
+
+    #[test]
+    fn test_command_message_serialization() {
+        let request = ServerMessage::Command(CommandRequest {
+            id: "req-1".to_string(),
+            command: "restart-nginx".to_string(),
+            requested_by: None,
+        });
+        let json = request.to_json().unwrap();
+        assert_eq!(json, r#"{"type":"Command","data":{"id":"req-1","command":"restart-nginx"}}"#);
+
+        let json = r#"{"type":"CommandResult","data":{"id":"req-1","status":"completed","exit_code":0,"stdout":"ok"}}"#;
+        match ClientMessage::from_json(json).unwrap() {
+            ClientMessage::CommandResult(result) => {
+                assert_eq!(result.id, "req-1");
+                assert_eq!(result.stdout, "ok");
+                assert!(result.succeeded());
+            }
+            _ => panic!("Wrong message type"),
+        }
+
+        let rejected = CommandResult::rejected("req-2", CommandStatus::NotAllowed, "not allowed");
+        assert!(!rejected.succeeded());
+        let bytes = ClientMessage::CommandResult(rejected.clone()).encode(WireFormat::MessagePack).unwrap();
+        match ClientMessage::decode(&bytes, WireFormat::MessagePack).unwrap() {
+            ClientMessage::CommandResult(result) => assert_eq!(result, rejected),
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_update_message_serialization() {
+        let offer = ServerMessage::Update(UpdateOffer {
+            version: "v1.2.0".to_string(),
+            manifest: "/agent/manifest.json".to_string(),
+        });
+        let json = offer.to_json().unwrap();
+        assert_eq!(json, r#"{"type":"Update","data":{"version":"v1.2.0","manifest":"/agent/manifest.json"}}"#);
+
+        let json = r#"{"type":"UpdateReport","data":{"version":"v1.2.0","outcome":"rolled_back","message":"no connection"}}"#;
+        match ClientMessage::from_json(json).unwrap() {
+            ClientMessage::UpdateReport(report) => {
+                assert_eq!(report.outcome, UpdateOutcome::RolledBack);
+                assert_eq!(report.message.as_deref(), Some("no connection"));
+            }
+            _ => panic!("Wrong message type"),
+        }
+        // 未知の結果も受け付ける
+        let json = r#"{"type":"UpdateReport","data":{"version":"v1.2.0","outcome":"exploded"}}"#;
+        match ClientMessage::from_json(json).unwrap() {
+            ClientMessage::UpdateReport(report) => assert_eq!(report.outcome, UpdateOutcome::Unknown),
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_alert_message_serialization() {
+        let alert = Alert {
+            rule: "high-ram".to_string(),
+            host: "machine-1".to_string(),
+            hostname: "web".to_string(),
+            tenant: String::new(),
+            state: AlertState::Firing,
+            value: 95.0,
+            threshold: 90.0,
+            message: "web: high-ram".to_string(),
+            since: chrono::Utc::now(),
+        };
+        let json = ServerMessage::Alert(alert.clone()).to_json().unwrap();
+        assert!(json.contains(r#""state":"firing""#));
+
+        match ServerMessage::from_json(&json).unwrap() {
+            ServerMessage::Alert(decoded) => assert_eq!(decoded, alert),
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_parse_duration_and_since() {
+        assert_eq!(parse_duration("90"), Some(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Some(std::time::Duration::from_secs(900)));
+        assert_eq!(parse_duration("2d"), Some(std::time::Duration::from_secs(172800)));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("h"), None);
+
+        let now = chrono::Utc::now();
+        assert_eq!(parse_since("1h", now), Some(now - chrono::Duration::hours(1)));
+        assert_eq!(
+            parse_since("2024-01-02T03:04:05Z", now).unwrap().to_rfc3339(),
+            "2024-01-02T03:04:05+00:00"
+        );
+        assert_eq!(parse_since("yesterday", now), None);
+    }
+
+    #[test]
+    fn test_history_sample_is_flat() {
+        let sample = HistorySample::new(chrono::Utc::now(), &sample_status("web", 25.0));
+        let json = serde_json::to_value(&sample).unwrap();
+        assert!(json.get("time").is_some());
+        assert!(json.get("cpu").is_some());
+        assert_eq!(serde_json::from_value::<HistorySample>(json).unwrap(), sample);
+    }
+
+    #[test]
+    fn test_selector_matches_tags_and_labels() {
+        let mut status = sample_status("web-1", 10.0);
+        status.tags = parse_tags("gpu, ci,gpu");
+        status.labels = parse_labels("role=ci, site=tokyo").unwrap();
+        assert_eq!(status.tags, vec!["gpu".to_string(), "ci".to_string()]);
+
+        let matches = |selector: &str| selector.parse::<Selector>().unwrap().matches(&status);
+        assert!(matches(""));
+        assert!(matches("gpu,role=ci"));
+        assert!(matches("site=tok*,hostname=WEB-*"));
+        assert!(matches("site!=osaka,owner!=alice"));
+        assert!(!matches("gpu,role=build"));
+        assert!(!matches("arm"));
+        assert!(!matches("site!=tokyo"));
+
+        assert!("=ci".parse::<Selector>().is_err());
+        assert!(parse_labels("role").is_err());
+        assert_eq!("gpu, role!=ci".parse::<Selector>().unwrap().to_string(), "gpu,role!=ci");
+    }
+
+    #[test]
+    fn test_delta_filter_by_selector() {
+        let mut gpu = sample_status("gpu", 10.0);
+        gpu.tags = vec!["gpu".to_string()];
+        let mut previous = ClientData::new();
+        previous.insert("a".to_string(), gpu.clone());
+        previous.insert("b".to_string(), sample_status("web", 5.0));
+        let selector: Selector = "gpu".parse().unwrap();
+        let mut visible: HashSet<String> = previous
+            .iter()
+            .filter(|(_, status)| selector.matches(status))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        // aはタグが外れ、bは値だけ変わる
+        let mut next = previous.clone();
+        next.get_mut("a").unwrap().tags.clear();
+        next.get_mut("b").unwrap().cpu.cpus[0].cpu = 50.0;
+        let delta = StatusDelta::between(7, &previous, &next).filter(&mut visible, |status| selector.matches(status));
+        assert_eq!(delta.seq, 7);
+        assert_eq!(delta.removed, vec!["a".to_string()]);
+        assert!(delta.added.is_empty() && delta.updated.is_empty());
+        assert!(visible.is_empty());
+
+        // タグが付き直したら再び追加される
+        let delta = StatusDelta::between(8, &next, &previous).filter(&mut visible, |status| selector.matches(status));
+        assert!(delta.added.contains_key("a"));
+        assert!(visible.contains("a"));
+    }
 }
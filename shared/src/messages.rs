@@ -1,22 +1,66 @@
 use serde::{Deserialize, Serialize};
+use crate::alert::Alert;
+use crate::command::{CommandRequest, CommandResult};
+use crate::codec::{self, CodecError, WireFormat};
+use crate::delta::{StatusDelta, StatusSnapshot};
+use crate::protocol::{Capability, ErrorCode, ServerHello};
 use crate::types::{StatusData, ClientData, ToastData};
+use crate::update::{UpdateOffer, UpdateReport};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ServerMessage {
-    Hi(String),
+    Hi(ServerHello),
     Status(ClientData),
+    /// 差分配信の購読開始時に送る完全な状態
+    Snapshot(StatusSnapshot),
+    /// 直前のスナップショット・差分からの変更点
+    Delta(StatusDelta),
     Toast(ToastData),
+    /// アラートの発報・解消
+    Alert(Alert),
     Close,
     Sync(String),
+    /// 許可リストのコマンドの実行要求（`Commands`に対応したエージェントにだけ送る）
+    Command(CommandRequest),
+    /// 更新の指示（`Updates`に対応したエージェントにだけ送る）
+    Update(UpdateOffer),
+    /// 登録を受け付けたこと（`Updates`に対応したエージェントの接続にだけ送る）
+    Registered,
+    /// 接続を拒否・終了する理由（直後にCloseが続く）
+    Error {
+        code: ErrorCode,
+        message: String,
+        /// 再接続までに待つべき秒数（Noneの場合はサーバーからの指定なし）
+        #[serde(default)]
+        retry: Option<u64>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ClientMessage {
-    Hi { data: StatusData, pass: Option<String> },
+    Hi {
+        data: StatusData,
+        pass: Option<String>,
+        /// エージェントが初回起動時に生成・永続化するマシンID
+        #[serde(default)]
+        machine_id: Option<String>,
+        /// エージェントのプロトコルバージョン（旧エージェントは送信しない）
+        #[serde(default)]
+        protocol: Option<u32>,
+        #[serde(default)]
+        capabilities: Vec<Capability>,
+    },
     Sync(StatusData),
+    /// 配信するホストをセレクター（`gpu,role=ci`など）で絞り込む（空文字列で解除）
     Only(String),
+    /// 差分配信を購読する（再送するとスナップショットを再取得する）
+    Subscribe,
+    /// `Command`の実行結果
+    CommandResult(CommandResult),
+    /// 更新の結果
+    UpdateReport(UpdateReport),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +77,14 @@ impl ServerMessage {
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
         serde_json::from_str(json)
     }
+
+    pub fn encode(&self, format: WireFormat) -> Result<Vec<u8>, CodecError> {
+        codec::encode(self, format)
+    }
+
+    pub fn decode(bytes: &[u8], format: WireFormat) -> Result<Self, CodecError> {
+        codec::decode(bytes, format)
+    }
 }
 
 impl ClientMessage {
@@ -43,4 +95,12 @@ impl ClientMessage {
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
         serde_json::from_str(json)
     }
+
+    pub fn encode(&self, format: WireFormat) -> Result<Vec<u8>, CodecError> {
+        codec::encode(self, format)
+    }
+
+    pub fn decode(bytes: &[u8], format: WireFormat) -> Result<Self, CodecError> {
+        codec::decode(bytes, format)
+    }
 }
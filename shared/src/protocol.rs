@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+
+/// 現在のプロトコルバージョン
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// プロトコルバージョンを送信しない旧エージェント・サーバーのバージョン
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// ハンドシェイクで交換する機能
+///
+/// 未知の機能は`Unknown`として読み込むため、新しい機能を追加しても旧バージョンの
+/// 相手との接続は失敗しない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// メッセージの圧縮
+    Compression,
+    /// MessagePackによるバイナリフレーム
+    Binary,
+    /// スナップショットと差分による配信
+    Delta,
+    /// 切断中に蓄積した履歴の再送
+    Backfill,
+    /// 標準以外の追加コレクター
+    ExtraCollectors,
+    /// 許可リストのコマンドの実行（エージェントが許可リストを設定している場合だけ送る）
+    Commands,
+    /// サーバーの指示による自動更新（エージェントが自動更新を有効にしている場合だけ送る）
+    Updates,
+    #[serde(other)]
+    Unknown,
+}
+
+/// サーバーが接続直後に送信する挨拶
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ServerHelloRepr")]
+pub struct ServerHello {
+    pub message: String,
+    pub protocol: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+/// 旧サーバーは挨拶を文字列だけで送るため、両方の形式を受け付ける
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerHelloRepr {
+    Current {
+        message: String,
+        protocol: u32,
+        #[serde(default)]
+        capabilities: Vec<Capability>,
+    },
+    Legacy(String),
+}
+
+impl From<ServerHelloRepr> for ServerHello {
+    fn from(repr: ServerHelloRepr) -> Self {
+        match repr {
+            ServerHelloRepr::Current { message, protocol, capabilities } => Self {
+                message,
+                protocol,
+                capabilities,
+            },
+            ServerHelloRepr::Legacy(message) => Self {
+                message,
+                protocol: LEGACY_PROTOCOL_VERSION,
+                capabilities: vec![],
+            },
+        }
+    }
+}
+
+impl ServerHello {
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// `ServerMessage::Error`のエラーコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// 認証情報が正しくない（再接続しても成功しない）
+    AuthFailed,
+    /// 同じホスト名のエージェントが既に接続している
+    DuplicateHostname,
+    /// サーバーが混雑している
+    Overloaded,
+    /// プロトコルバージョンに互換性がない
+    IncompatibleProtocol,
+    /// 管理者によって接続を禁止されている
+    Banned,
+    /// 送信したデータが受け付けられない（ホスト名が不正、項目が多すぎるなど）
+    InvalidData,
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// 再接続しても解決しないエラーか
+    pub fn is_fatal(self) -> bool {
+        matches!(self, Self::AuthFailed | Self::IncompatibleProtocol)
+    }
+}
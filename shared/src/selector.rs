@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::StatusData;
+
+/// `*`を任意の文字列として大文字小文字を区別せずに照合する
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // ワイルドカードを含まない場合は完全一致
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// カンマ区切りのタグを読む（`gpu,ci`）
+pub fn parse_tags(value: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in value.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|existing| existing == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// カンマ区切りのラベルを読む（`role=ci,site=tokyo`）
+pub fn parse_labels(value: &str) -> Result<BTreeMap<String, String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+            _ => Err(format!("Invalid label: {} (expected key=value)", label)),
+        })
+        .collect()
+}
+
+/// セレクターの条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    /// タグを持っている
+    Tag(String),
+    /// ラベルの値が一致する（`negate`の場合は一致しない）
+    Label { key: String, value: String, negate: bool },
+}
+
+/// タグ・ラベルによるホストの絞り込み
+///
+/// カンマ区切りの条件をすべて満たすホストに一致する。
+/// - `gpu`: タグ`gpu`を持つ
+/// - `role=ci`: ラベル`role`が`ci`
+/// - `site!=osaka`: ラベル`site`が`osaka`ではない（ラベルがない場合も含む）
+/// - `hostname=web-*`: ホスト名
+///
+/// 値とタグには`*`をワイルドカードとして使用でき、大文字小文字は区別しない。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    terms: Vec<Term>,
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, status: &StatusData) -> bool {
+        self.matches_parts(&status.hostname, &status.tags, &status.labels)
+    }
+
+    /// ホスト名・タグ・ラベルが条件を満たすか
+    pub fn matches_parts(&self, hostname: &str, tags: &[String], labels: &BTreeMap<String, String>) -> bool {
+        self.terms.iter().all(|term| match term {
+            Term::Tag(pattern) => tags.iter().any(|tag| glob_match(pattern, tag)),
+            Term::Label { key, value, negate } => {
+                let actual = if key == "hostname" { Some(hostname) } else { labels.get(key).map(String::as_str) };
+                actual.is_some_and(|actual| glob_match(value, actual)) != *negate
+            }
+        })
+    }
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = s
+            .split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(|term| {
+                let (key, value, negate) = match term.split_once("!=") {
+                    Some((key, value)) => (key, value, true),
+                    None => match term.split_once('=') {
+                        Some((key, value)) => (key, value, false),
+                        None => return Ok(Term::Tag(term.to_string())),
+                    },
+                };
+                let key = key.trim();
+                if key.is_empty() {
+                    return Err(format!("Invalid selector: {} (missing label name)", term));
+                }
+                Ok(Term::Label { key: key.to_string(), value: value.trim().to_string(), negate })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { terms })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| match term {
+                Term::Tag(tag) => tag.clone(),
+                Term::Label { key, value, negate: false } => format!("{}={}", key, value),
+                Term::Label { key, value, negate: true } => format!("{}!={}", key, value),
+            })
+            .collect();
+        write!(f, "{}", terms.join(","))
+    }
+}
@@ -0,0 +1,14 @@
+//! テスト用のデータ（他のクレートのテストでは`test-support`フィーチャーで使う）
+
+use crate::StatusData;
+
+/// テスト用のエージェントのステータス（必要な項目は呼び出し側で上書きする）
+pub fn status(hostname: &str) -> StatusData {
+    StatusData {
+        dev: Some(false),
+        os: "Linux".to_string(),
+        hostname: hostname.to_string(),
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    }
+}
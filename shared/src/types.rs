@@ -1,24 +1,24 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CpuData {
     pub cpu: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Cpu {
     pub model: String,
     pub cpus: Vec<CpuData>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Ram {
     pub free: u64,
     pub total: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Swap {
     pub free: u64,
     pub total: u64,
@@ -31,13 +31,13 @@ pub struct Storage {
     pub total: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GpuMemory {
     pub free: u64,
     pub total: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Gpu {
     pub name: String,
     pub usage: f64,
@@ -51,7 +51,7 @@ pub struct NetworkData {
     pub transmitted: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HistoriesData {
     pub cpu: Cpu,
     pub ram: Ram,
@@ -61,7 +61,7 @@ pub struct HistoriesData {
     pub uptime: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct StatusData {
     pub pass: Option<String>,
     pub dev: Option<bool>,
@@ -78,6 +78,15 @@ pub struct StatusData {
     pub gpus: Vec<Gpu>,
     pub index: u32,
     pub histories: Vec<HistoriesData>,
+    /// 絞り込みやアラートに使うタグ（`gpu`など）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// 絞り込みやアラートに使うラベル（`role=ci`など）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// 所属するテナント（サーバーが認証に使ったパスワードから設定する）
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tenant: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
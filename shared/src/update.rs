@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 署名付きのマニフェスト（配布するファイルの形式）
+///
+/// `signature`は`manifest`の文字列（UTF-8のバイト列）に対するEd25519署名。
+/// JSONの書き方の違いで署名が変わらないよう、署名した文字列のまま埋め込む。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedManifest {
+    /// `UpdateManifest`のJSON
+    pub manifest: String,
+    /// Ed25519署名（16進数）
+    pub signature: String,
+}
+
+impl SignedManifest {
+    /// 署名を検証せずに中身を読む（エージェントは署名を検証してから使う）
+    pub fn parse_unverified(&self) -> Result<UpdateManifest, serde_json::Error> {
+        serde_json::from_str(&self.manifest)
+    }
+}
+
+/// エージェントの配布情報
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateManifest {
+    /// 配布するバージョン（`git describe`の形式、`v1.2.0`など）
+    pub version: String,
+    pub assets: Vec<UpdateAsset>,
+    /// 有効期限（古いマニフェストを使い回して配布できないよう、期限を過ぎたものは適用しない）
+    pub expires_at: DateTime<Utc>,
+    /// 現在より古いバージョンへ戻すためのマニフェストか（falseの場合は古いバージョンを適用しない）
+    #[serde(default)]
+    pub rollback: bool,
+}
+
+/// ターゲットごとの実行ファイル
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateAsset {
+    /// ターゲットトリプル（`x86_64-unknown-linux-gnu`など）
+    pub target: String,
+    /// ダウンロードURL（マニフェストのURLからの相対パスも可）
+    pub url: String,
+    /// サイズ（バイト）
+    pub size: u64,
+    /// SHA-256（16進数）
+    pub sha256: String,
+}
+
+impl UpdateManifest {
+    pub fn asset(&self, target: &str) -> Option<&UpdateAsset> {
+        self.assets.iter().find(|asset| asset.target == target)
+    }
+}
+
+/// サーバーからエージェントへの更新の指示（段階的な配布の対象になったエージェントにだけ送る）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateOffer {
+    /// 更新先のバージョン（マニフェストのバージョンと一致しなければ更新しない）
+    pub version: String,
+    /// マニフェストのURL（サーバーからの相対パスも可）
+    pub manifest: String,
+}
+
+/// 更新の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOutcome {
+    /// 更新後のバージョンでサーバーに接続できた
+    Installed,
+    /// ダウンロード・検証・置き換えに失敗した
+    Failed,
+    /// 更新後に接続できなかったため元のバージョンに戻した
+    RolledBack,
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for UpdateOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Installed => "installed",
+            Self::Failed => "failed",
+            Self::RolledBack => "rolled_back",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// エージェントが送る更新の結果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateReport {
+    /// 更新しようとしたバージョン
+    pub version: String,
+    pub outcome: UpdateOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::types::{Gpu, StatusData, Storage};
+
+/// ホスト名の最大長（DNSの上限）
+pub const MAX_HOSTNAME_LEN: usize = 253;
+/// OS名・CPUモデル名・タグなどの文字列の最大長（文字数、超えた分は切り詰める）
+pub const MAX_TEXT_LEN: usize = 256;
+/// CPUのコア数の上限
+pub const MAX_CPUS: usize = 4096;
+/// ストレージ数の上限
+pub const MAX_STORAGES: usize = 256;
+/// GPU数の上限
+pub const MAX_GPUS: usize = 64;
+/// タグ数・ラベル数の上限
+pub const MAX_TAGS: usize = 64;
+
+/// 受け付けられないエージェントのデータ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// ホスト名が空・長すぎる・使えない文字を含む
+    InvalidHostname(String),
+    /// 項目の数が上限を超えている
+    TooMany { field: &'static str, count: usize, max: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHostname(hostname) => write!(
+                f,
+                "Invalid hostname {:?} (use up to {} letters, digits, '-', '_' and '.')",
+                hostname, MAX_HOSTNAME_LEN
+            ),
+            Self::TooMany { field, count, max } => write!(f, "Too many {}: {} (max {})", field, count, max),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// ホスト名として受け付けるか
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.chars().count() <= MAX_HOSTNAME_LEN
+        && hostname.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// エージェントのデータを検証し、表示できない値を直す
+///
+/// ホスト名と項目の数は直さずにエラーにする。使用率は0〜100、空き容量は合計以下に収め、
+/// 文字列からは制御文字を除いて切り詰める。直した項目の名前を返す。
+pub fn sanitize_status(data: &mut StatusData) -> Result<Vec<&'static str>, ValidationError> {
+    if !is_valid_hostname(&data.hostname) {
+        return Err(ValidationError::InvalidHostname(truncate(&data.hostname, MAX_HOSTNAME_LEN)));
+    }
+    check_count("cpus", data.cpu.cpus.len(), MAX_CPUS)?;
+    check_count("storages", data.storages.len(), MAX_STORAGES)?;
+    check_count("gpus", data.gpus.len(), MAX_GPUS)?;
+    check_count("tags", data.tags.len(), MAX_TAGS)?;
+    check_count("labels", data.labels.len(), MAX_TAGS)?;
+
+    let mut fixed = Vec::new();
+    let mut note = |field: &'static str, changed: bool| {
+        if changed && !fixed.contains(&field) {
+            fixed.push(field);
+        }
+    };
+
+    note("os", sanitize_text(&mut data.os));
+    note("version", sanitize_text(&mut data.version));
+    note("cpu.model", sanitize_text(&mut data.cpu.model));
+    for core in &mut data.cpu.cpus {
+        note("cpu.cpus", clamp_percent(&mut core.cpu));
+    }
+    note("ram", clamp_free(&mut data.ram.free, data.ram.total));
+    note("swap", clamp_free(&mut data.swap.free, data.swap.total));
+    for storage in &mut data.storages {
+        note("storages", sanitize_storage(storage));
+    }
+    for gpu in &mut data.gpus {
+        note("gpus", sanitize_gpu(gpu));
+    }
+    for load in &mut data.loadavg {
+        if !load.is_finite() || *load < 0.0 {
+            *load = 0.0;
+            note("loadavg", true);
+        }
+    }
+
+    let mut tags_changed = false;
+    for tag in &mut data.tags {
+        tags_changed |= sanitize_text(tag);
+    }
+    let before = data.tags.len();
+    data.tags.retain(|tag| !tag.is_empty());
+    note("tags", tags_changed || data.tags.len() != before);
+
+    let labels = std::mem::take(&mut data.labels);
+    let mut labels_changed = false;
+    for (mut key, mut value) in labels {
+        labels_changed |= sanitize_text(&mut key) | sanitize_text(&mut value);
+        if key.is_empty() {
+            labels_changed = true;
+            continue;
+        }
+        data.labels.insert(key, value);
+    }
+    note("labels", labels_changed);
+
+    Ok(fixed)
+}
+
+fn check_count(field: &'static str, count: usize, max: usize) -> Result<(), ValidationError> {
+    if count > max {
+        return Err(ValidationError::TooMany { field, count, max });
+    }
+    Ok(())
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    text.chars().take(max).collect()
+}
+
+/// 制御文字を除いて`MAX_TEXT_LEN`文字に切り詰める（変更した場合はtrue）
+fn sanitize_text(text: &mut String) -> bool {
+    if text.chars().count() <= MAX_TEXT_LEN && !text.chars().any(char::is_control) {
+        return false;
+    }
+    *text = text.chars().filter(|c| !c.is_control()).take(MAX_TEXT_LEN).collect();
+    true
+}
+
+/// 使用率を0〜100に収める（NaNは0）
+fn clamp_percent(value: &mut f64) -> bool {
+    let clamped = if value.is_nan() { 0.0 } else { value.clamp(0.0, 100.0) };
+    let changed = clamped.to_bits() != value.to_bits();
+    *value = clamped;
+    changed
+}
+
+fn clamp_free(free: &mut u64, total: u64) -> bool {
+    if *free > total {
+        *free = total;
+        return true;
+    }
+    false
+}
+
+fn sanitize_storage(storage: &mut Storage) -> bool {
+    let name = storage.name.as_mut().is_some_and(sanitize_text);
+    clamp_free(&mut storage.free, storage.total) | name
+}
+
+fn sanitize_gpu(gpu: &mut Gpu) -> bool {
+    sanitize_text(&mut gpu.name) | clamp_percent(&mut gpu.usage) | clamp_free(&mut gpu.memory.free, gpu.memory.total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::types::{Cpu, CpuData, GpuMemory, Ram};
+
+    fn status() -> StatusData {
+        StatusData {
+            cpu: Cpu { model: "cpu".to_string(), cpus: vec![CpuData { cpu: 50.0 }] },
+            ram: Ram { free: 4, total: 8 },
+            loadavg: [0.5, 0.5, 0.5],
+            ..test_support::status("web-01.example.com")
+        }
+    }
+
+    #[test]
+    fn test_valid_status_is_unchanged() {
+        let mut data = status();
+        assert_eq!(sanitize_status(&mut data), Ok(vec![]));
+        assert_eq!(data, status());
+    }
+
+    #[test]
+    fn test_hostnames() {
+        assert!(is_valid_hostname("web-01"));
+        assert!(is_valid_hostname("DESKTOP_ABC.local"));
+        assert!(is_valid_hostname("サーバー1"));
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("web 01"));
+        assert!(!is_valid_hostname("<script>"));
+        assert!(!is_valid_hostname("web\n01"));
+        assert!(!is_valid_hostname(&"a".repeat(MAX_HOSTNAME_LEN + 1)));
+
+        let mut data = StatusData { hostname: "<b>web</b>".to_string(), ..status() };
+        assert!(matches!(sanitize_status(&mut data), Err(ValidationError::InvalidHostname(_))));
+    }
+
+    #[test]
+    fn test_too_many_items_are_rejected() {
+        let mut data = status();
+        data.storages = vec![Storage { name: None, free: 0, total: 0 }; MAX_STORAGES + 1];
+        assert_eq!(
+            sanitize_status(&mut data),
+            Err(ValidationError::TooMany { field: "storages", count: MAX_STORAGES + 1, max: MAX_STORAGES })
+        );
+
+        let mut data = status();
+        data.cpu.cpus = vec![CpuData { cpu: 0.0 }; MAX_CPUS + 1];
+        assert!(matches!(sanitize_status(&mut data), Err(ValidationError::TooMany { field: "cpus", .. })));
+    }
+
+    #[test]
+    fn test_values_are_clamped() {
+        let mut data = status();
+        data.cpu.cpus = vec![CpuData { cpu: f64::NAN }, CpuData { cpu: -5.0 }, CpuData { cpu: 250.0 }];
+        data.ram = Ram { free: 16, total: 8 };
+        data.loadavg = [f64::INFINITY, -1.0, 2.0];
+        data.gpus = vec![Gpu {
+            name: "gpu\u{0}".to_string(),
+            usage: f64::NEG_INFINITY,
+            memory: GpuMemory { free: 2, total: 1 },
+        }];
+        data.os = "x".repeat(MAX_TEXT_LEN + 10);
+        data.tags = vec!["\u{7}".to_string(), "gpu".to_string()];
+
+        let fixed = sanitize_status(&mut data).unwrap();
+        assert_eq!(fixed, vec!["os", "cpu.cpus", "ram", "gpus", "loadavg", "tags"]);
+        let cpus: Vec<f64> = data.cpu.cpus.iter().map(|core| core.cpu).collect();
+        assert_eq!(cpus, vec![0.0, 0.0, 100.0]);
+        assert_eq!(data.ram.free, 8);
+        assert_eq!(data.loadavg, [0.0, 0.0, 2.0]);
+        assert_eq!(data.gpus[0].name, "gpu");
+        assert_eq!(data.gpus[0].usage, 0.0);
+        assert_eq!(data.gpus[0].memory.free, 1);
+        assert_eq!(data.os.chars().count(), MAX_TEXT_LEN);
+        assert_eq!(data.tags, vec!["gpu".to_string()]);
+    }
+}
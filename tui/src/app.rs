@@ -0,0 +1,305 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use pc_status_shared::{ClientData, StatusData};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use crate::connection::Update;
+use crate::metrics;
+
+/// 画面下部に残す通知の数
+const MAX_NOTICES: usize = 3;
+
+/// 表の並び順
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Cpu,
+    Ram,
+    Disk,
+    Load,
+    Uptime,
+}
+
+impl SortKey {
+    fn next(self) -> Self {
+        match self {
+            Self::Name => Self::Cpu,
+            Self::Cpu => Self::Ram,
+            Self::Ram => Self::Disk,
+            Self::Disk => Self::Load,
+            Self::Load => Self::Uptime,
+            Self::Uptime => Self::Name,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Cpu => "cpu",
+            Self::Ram => "ram",
+            Self::Disk => "disk",
+            Self::Load => "load",
+            Self::Uptime => "uptime",
+        }
+    }
+
+    fn compare(self, a: &StatusData, b: &StatusData) -> Ordering {
+        let by_value = |value: fn(&StatusData) -> Option<f64>| {
+            value(a).unwrap_or(-1.0).total_cmp(&value(b).unwrap_or(-1.0))
+        };
+        match self {
+            Self::Name => Ordering::Equal,
+            Self::Cpu => by_value(|status| metrics::cpu_usage(&status.cpu)),
+            Self::Ram => by_value(metrics::ram_usage),
+            Self::Disk => by_value(metrics::disk_usage),
+            Self::Load => a.loadavg[0].total_cmp(&b.loadavg[0]),
+            Self::Uptime => a.uptime.cmp(&b.uptime),
+        }
+        .then_with(|| display_name(a).to_lowercase().cmp(&display_name(b).to_lowercase()))
+    }
+}
+
+/// 入力の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    /// ホスト名の絞り込みを入力中
+    Filter,
+}
+
+/// インスタンス番号を含む表示名
+pub fn display_name(status: &StatusData) -> String {
+    if status.index > 0 {
+        format!("{} #{}", status.hostname, status.index)
+    } else {
+        status.hostname.clone()
+    }
+}
+
+pub struct App {
+    pub url: String,
+    pub connection: String,
+    pub connected: bool,
+    pub clients: ClientData,
+    /// 選択中のエージェントID（並べ替えても選択を保つ）
+    pub selected: Option<String>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub filter: String,
+    pub mode: Mode,
+    pub show_detail: bool,
+    pub notices: VecDeque<String>,
+    pub should_quit: bool,
+}
+
+impl App {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            connection: "Connecting...".to_string(),
+            connected: false,
+            clients: ClientData::new(),
+            selected: None,
+            sort: SortKey::Name,
+            descending: false,
+            filter: String::new(),
+            mode: Mode::Normal,
+            show_detail: false,
+            notices: VecDeque::new(),
+            should_quit: false,
+        }
+    }
+
+    pub fn update(&mut self, update: Update) {
+        match update {
+            Update::Connected(greeting) => {
+                self.connected = true;
+                self.connection = format!("Connected ({})", greeting);
+            }
+            Update::Disconnected(reason) => {
+                self.connected = false;
+                self.connection = format!("Disconnected: {} (retrying)", reason);
+                self.clients.clear();
+            }
+            Update::Clients(clients) => self.clients = clients,
+            Update::Notice(notice) => {
+                if self.notices.len() >= MAX_NOTICES {
+                    self.notices.pop_front();
+                }
+                self.notices.push_back(notice);
+            }
+        }
+    }
+
+    /// 絞り込み・並べ替えた表の行
+    pub fn rows(&self) -> Vec<(&String, &StatusData)> {
+        let filter = self.filter.to_lowercase();
+        let mut rows: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, status)| filter.is_empty() || matches_filter(status, &filter))
+            .collect();
+        rows.sort_by(|(_, a), (_, b)| {
+            let ordering = self.sort.compare(a, b);
+            if self.descending { ordering.reverse() } else { ordering }
+        });
+        rows
+    }
+
+    /// 選択中の行の位置（未選択や絞り込みで消えた場合は先頭）
+    pub fn selected_index(&self, rows: &[(&String, &StatusData)]) -> Option<usize> {
+        if rows.is_empty() {
+            return None;
+        }
+        let index = self
+            .selected
+            .as_ref()
+            .and_then(|selected| rows.iter().position(|(id, _)| *id == selected));
+        Some(index.unwrap_or(0))
+    }
+
+    pub fn selected_host(&self) -> Option<(&String, &StatusData)> {
+        let rows = self.rows();
+        let index = self.selected_index(&rows)?;
+        rows.get(index).copied()
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        let rows = self.rows();
+        let Some(index) = self.selected_index(&rows) else {
+            return;
+        };
+        let index = index.saturating_add_signed(offset).min(rows.len() - 1);
+        self.selected = Some(rows[index].0.clone());
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.should_quit = true;
+            return;
+        }
+
+        match self.mode {
+            Mode::Filter => match key.code {
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            },
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Home | KeyCode::Char('g') => self.move_selection(isize::MIN),
+                KeyCode::End | KeyCode::Char('G') => self.move_selection(isize::MAX),
+                KeyCode::Char('s') => self.sort = self.sort.next(),
+                KeyCode::Char('r') => self.descending = !self.descending,
+                KeyCode::Char('/') => self.mode = Mode::Filter,
+                KeyCode::Enter | KeyCode::Char('d') => self.show_detail = !self.show_detail,
+                _ => {}
+            },
+        }
+    }
+}
+
+/// ホスト名・タグ・ラベル（`key=value`）のいずれかに絞り込みの文字列を含むか
+fn matches_filter(status: &StatusData, filter: &str) -> bool {
+    display_name(status).to_lowercase().contains(filter)
+        || status.tags.iter().any(|tag| tag.to_lowercase().contains(filter))
+        || status
+            .labels
+            .iter()
+            .any(|(key, value)| format!("{}={}", key, value).to_lowercase().contains(filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pc_status_shared::{test_support, Cpu, CpuData};
+
+    fn status(hostname: &str, cpu: f64, uptime: u64) -> StatusData {
+        StatusData {
+            cpu: Cpu { model: "cpu".to_string(), cpus: vec![CpuData { cpu }] },
+            uptime,
+            ..test_support::status(hostname)
+        }
+    }
+
+    fn app() -> App {
+        let mut app = App::new("ws://localhost".to_string());
+        app.update(Update::Clients(
+            [("a", status("web", 10.0, 300)), ("b", status("db", 80.0, 100)), ("c", status("cache", 40.0, 200))]
+                .into_iter()
+                .map(|(id, status)| (id.to_string(), status))
+                .collect(),
+        ));
+        app
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn hostnames(app: &App) -> Vec<String> {
+        app.rows().iter().map(|(_, status)| status.hostname.clone()).collect()
+    }
+
+    #[test]
+    fn test_sorting() {
+        let mut app = app();
+        assert_eq!(hostnames(&app), vec!["cache", "db", "web"]);
+
+        app.handle_key(key(KeyCode::Char('s')));
+        assert_eq!(app.sort, SortKey::Cpu);
+        assert_eq!(hostnames(&app), vec!["web", "cache", "db"]);
+
+        app.handle_key(key(KeyCode::Char('r')));
+        assert_eq!(hostnames(&app), vec!["db", "cache", "web"]);
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut app = app();
+        for code in [KeyCode::Char('/'), KeyCode::Char('C'), KeyCode::Char('a'), KeyCode::Enter] {
+            app.handle_key(key(code));
+        }
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(hostnames(&app), vec!["cache"]);
+
+        app.handle_key(key(KeyCode::Char('/')));
+        app.handle_key(key(KeyCode::Esc));
+        assert_eq!(hostnames(&app).len(), 3);
+
+        // タグとラベルでも絞り込める
+        let mut db = status("db", 80.0, 100);
+        db.tags = vec!["gpu".to_string()];
+        db.labels.insert("role".to_string(), "ci".to_string());
+        app.update(Update::Clients([("b".to_string(), db), ("a".to_string(), status("web", 10.0, 300))].into()));
+        app.filter = "gpu".to_string();
+        assert_eq!(hostnames(&app), vec!["db"]);
+        app.filter = "role=ci".to_string();
+        assert_eq!(hostnames(&app), vec!["db"]);
+    }
+
+    #[test]
+    fn test_selection_follows_host_when_sorting() {
+        let mut app = app();
+        app.handle_key(key(KeyCode::Down));
+        assert_eq!(app.selected_host().unwrap().1.hostname, "db");
+
+        app.handle_key(key(KeyCode::Char('s')));
+        assert_eq!(app.selected_host().unwrap().1.hostname, "db");
+
+        app.handle_key(key(KeyCode::Char('G')));
+        app.handle_key(key(KeyCode::Down));
+        assert_eq!(app.selected_host().unwrap().1.hostname, "db");
+        app.handle_key(key(KeyCode::Char('g')));
+        assert_eq!(app.selected_host().unwrap().1.hostname, "web");
+    }
+}
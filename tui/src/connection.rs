@@ -0,0 +1,98 @@
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use pc_status_shared::{ClientData, ClientMessage, ServerMessage, WireFormat};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// 再接続までの待ち時間
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// 画面に反映する変更
+#[derive(Debug)]
+pub enum Update {
+    /// サーバーに接続した（サーバーの挨拶）
+    Connected(String),
+    /// 接続が切れた（理由）
+    Disconnected(String),
+    /// 全ホストの最新の状態
+    Clients(ClientData),
+    /// トーストやアラートなどの通知
+    Notice(String),
+}
+
+/// サーバーに閲覧者として接続し続け、受信した内容を送る
+///
+/// `selector`を指定した場合は、一致するホストだけを配信するようサーバーに求める。
+pub async fn run(url: String, selector: Option<String>, tx: mpsc::Sender<Update>) {
+    loop {
+        let reason = match connect(&url, selector.as_deref(), &tx).await {
+            Ok(()) => "Connection closed".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        if tx.send(Update::Disconnected(reason)).await.is_err() {
+            // 画面側が終了している
+            return;
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(url: &str, selector: Option<&str>, tx: &mpsc::Sender<Update>) -> Result<()> {
+    let (ws_stream, _) = connect_async(url).await?;
+    let (mut write, mut read) = ws_stream.split();
+
+    // 絞り込みは購読より先に送り、最初のスナップショットから適用させる
+    if let Some(selector) = selector {
+        write.send(Message::Text(ClientMessage::Only(selector.to_string()).to_json()?.into())).await?;
+    }
+
+    // 差分配信を購読し、スナップショットに差分を適用して状態を保つ
+    let subscribe = Message::Text(ClientMessage::Subscribe.to_json()?.into());
+    write.send(subscribe.clone()).await?;
+    let mut clients = ClientData::new();
+    let mut delta_seq: Option<u64> = None;
+
+    while let Some(msg) = read.next().await {
+        let message = match msg? {
+            Message::Text(text) => ServerMessage::decode(text.as_bytes(), WireFormat::Json)?,
+            Message::Binary(bytes) => ServerMessage::decode(&bytes, WireFormat::MessagePack)?,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let update = match message {
+            ServerMessage::Hi(hello) => Update::Connected(hello.message),
+            ServerMessage::Snapshot(snapshot) => {
+                delta_seq = Some(snapshot.seq);
+                clients = snapshot.clients;
+                Update::Clients(clients.clone())
+            }
+            ServerMessage::Delta(delta) => match delta_seq {
+                Some(last) if delta.seq <= last => continue,
+                Some(last) if delta.seq == last + 1 => {
+                    delta.apply(&mut clients);
+                    delta_seq = Some(delta.seq);
+                    Update::Clients(clients.clone())
+                }
+                // 取りこぼした差分があるためスナップショットを取り直す
+                _ => {
+                    delta_seq = None;
+                    write.send(subscribe.clone()).await?;
+                    continue;
+                }
+            },
+            ServerMessage::Toast(toast) => Update::Notice(toast.message),
+            ServerMessage::Alert(alert) => Update::Notice(format!("[{:?}] {}", alert.state, alert.message)),
+            ServerMessage::Error { code, message, .. } => bail!("Server rejected connection ({:?}): {}", code, message),
+            ServerMessage::Close => break,
+            _ => continue,
+        };
+        if tx.send(update).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
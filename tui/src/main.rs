@@ -0,0 +1,65 @@
+mod app;
+mod connection;
+mod metrics;
+mod ui;
+
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyEventKind};
+use futures_util::StreamExt;
+use ratatui::DefaultTerminal;
+use std::env;
+use tokio::sync::mpsc;
+
+use crate::app::App;
+use crate::connection::Update;
+
+/// 接続先の既定値
+const DEFAULT_URL: &str = "ws://localhost:3000/ws";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+
+    // rustlsのデフォルトCryptoProviderを初期化（wss://での接続に使用）
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("Failed to install rustls crypto provider");
+
+    // 接続先は引数、環境変数PCSC_VIEWER_URIの順で決める
+    let url = env::args()
+        .nth(1)
+        .or_else(|| env::var("PCSC_VIEWER_URI").ok())
+        .unwrap_or_else(|| DEFAULT_URL.to_string());
+
+    // サーバー側で絞り込むセレクター（`gpu,role=ci`など）
+    let selector = env::var("PCSC_VIEWER_SELECTOR").ok().filter(|selector| !selector.is_empty());
+
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(connection::run(url.clone(), selector, tx));
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, App::new(url), rx).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(terminal: &mut DefaultTerminal, mut app: App, mut rx: mpsc::Receiver<Update>) -> Result<()> {
+    let mut events = EventStream::new();
+
+    while !app.should_quit {
+        terminal.draw(|frame| ui::draw(frame, &app))?;
+
+        tokio::select! {
+            Some(update) = rx.recv() => app.update(update),
+            Some(event) = events.next() => {
+                if let Event::Key(key) = event?
+                    && key.kind == KeyEventKind::Press
+                {
+                    app.handle_key(key);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
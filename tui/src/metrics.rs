@@ -0,0 +1,113 @@
+use pc_status_shared::{Cpu, StatusData};
+
+const SPARK_BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub fn percent(free: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| total.saturating_sub(free) as f64 / total as f64 * 100.0)
+}
+
+pub fn cpu_usage(cpu: &Cpu) -> Option<f64> {
+    (!cpu.cpus.is_empty()).then(|| cpu.cpus.iter().map(|core| core.cpu).sum::<f64>() / cpu.cpus.len() as f64)
+}
+
+pub fn ram_usage(status: &StatusData) -> Option<f64> {
+    percent(status.ram.free, status.ram.total)
+}
+
+pub fn swap_usage(status: &StatusData) -> Option<f64> {
+    percent(status.swap.free, status.swap.total)
+}
+
+/// 最も使用率の高いストレージの使用率
+pub fn disk_usage(status: &StatusData) -> Option<f64> {
+    status
+        .storages
+        .iter()
+        .filter_map(|storage| percent(storage.free, storage.total))
+        .reduce(f64::max)
+}
+
+/// 最も使用率の高いGPUの使用率
+pub fn gpu_usage(status: &StatusData) -> Option<f64> {
+    status.gpus.iter().map(|gpu| gpu.usage).reduce(f64::max)
+}
+
+/// 履歴のCPU使用率（古い順）
+pub fn cpu_history(status: &StatusData) -> Vec<u64> {
+    status
+        .histories
+        .iter()
+        .map(|history| cpu_usage(&history.cpu).unwrap_or_default().round() as u64)
+        .collect()
+}
+
+/// 履歴のメモリ使用率（古い順）
+pub fn ram_history(status: &StatusData) -> Vec<u64> {
+    status
+        .histories
+        .iter()
+        .map(|history| percent(history.ram.free, history.ram.total).unwrap_or_default().round() as u64)
+        .collect()
+}
+
+/// 0〜100の値を1文字ずつのブロックで表す
+pub fn sparkline(values: &[u64]) -> String {
+    values
+        .iter()
+        .map(|&value| SPARK_BLOCKS[(value.min(100) as usize * (SPARK_BLOCKS.len() - 1)) / 100])
+        .collect()
+}
+
+pub fn format_percent(value: Option<f64>) -> String {
+    value.map(|value| format!("{:.1}%", value)).unwrap_or_else(|| "-".to_string())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_uptime(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = seconds % 3600 / 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[0, 50, 100, 150]), "▁▄██");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(8 * 1024 * 1024 * 1024), "8.0 GB");
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(3 * 86400 + 2 * 3600 + 5 * 60 + 9), "3d 2h 5m");
+        assert_eq!(format_uptime(59 * 60), "0h 59m");
+    }
+}
@@ -0,0 +1,226 @@
+use pc_status_shared::StatusData;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Cell, Paragraph, Row, Sparkline, Table, TableState},
+    Frame,
+};
+
+use crate::app::{display_name, App, Mode};
+use crate::metrics;
+
+/// フロントエンドの使用率バーと同じ閾値で色を付ける
+fn usage_style(value: Option<f64>) -> Style {
+    match value {
+        Some(value) if value > 90.0 => Style::new().fg(Color::Red),
+        Some(value) if value > 75.0 => Style::new().fg(Color::Yellow),
+        Some(_) => Style::new().fg(Color::Green),
+        None => Style::new().fg(Color::DarkGray),
+    }
+}
+
+fn usage_cell(value: Option<f64>) -> Cell<'static> {
+    Cell::from(Span::styled(metrics::format_percent(value), usage_style(value)))
+}
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(app.notices.len() as u16 + 1),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, app, header);
+
+    if app.show_detail {
+        let [table, detail] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(body);
+        draw_table(frame, app, table);
+        draw_detail(frame, app, detail);
+    } else {
+        draw_table(frame, app, body);
+    }
+
+    draw_footer(frame, app, footer);
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let connection_style = if app.connected {
+        Style::new().fg(Color::Green)
+    } else {
+        Style::new().fg(Color::Red)
+    };
+    let mut spans = vec![
+        Span::styled(" PC Status ", Style::new().add_modifier(Modifier::BOLD | Modifier::REVERSED)),
+        Span::raw(format!(" {} ", app.url)),
+        Span::styled(app.connection.clone(), connection_style),
+        Span::raw(format!(
+            "  hosts: {}  sort: {}{}",
+            app.clients.len(),
+            app.sort.label(),
+            if app.descending { " ↓" } else { " ↑" }
+        )),
+    ];
+    if !app.filter.is_empty() || app.mode == Mode::Filter {
+        spans.push(Span::styled(format!("  filter: {}", app.filter), Style::new().fg(Color::Cyan)));
+        if app.mode == Mode::Filter {
+            spans.push(Span::styled("_", Style::new().add_modifier(Modifier::SLOW_BLINK)));
+        }
+    }
+    frame.render_widget(Line::from(spans), area);
+}
+
+fn draw_table(frame: &mut Frame, app: &App, area: Rect) {
+    let rows = app.rows();
+    let selected = app.selected_index(&rows);
+
+    let header = Row::new(["Host", "CPU", "History", "RAM", "Swap", "Disk", "GPU", "Load", "Uptime"])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+    let table_rows = rows.iter().map(|(_, status)| {
+        let cpu = metrics::cpu_usage(&status.cpu);
+        Row::new(vec![
+            Cell::from(display_name(status)),
+            usage_cell(cpu),
+            Cell::from(metrics::sparkline(&metrics::cpu_history(status))),
+            usage_cell(metrics::ram_usage(status)),
+            usage_cell(metrics::swap_usage(status)),
+            usage_cell(metrics::disk_usage(status)),
+            usage_cell(metrics::gpu_usage(status)),
+            Cell::from(format!("{:.2}", status.loadavg[0])),
+            Cell::from(metrics::format_uptime(status.uptime)),
+        ])
+    });
+
+    let widths = [
+        Constraint::Min(16),
+        Constraint::Length(7),
+        Constraint::Length(10),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(7),
+        Constraint::Length(6),
+        Constraint::Length(12),
+    ];
+    let table = Table::new(table_rows, widths)
+        .header(header)
+        .block(Block::bordered().title(" Hosts "))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    let mut state = TableState::default().with_selected(selected);
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn detail_lines(status: &StatusData) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        Line::from(format!("OS: {}  Agent: {}", status.os, status.version)),
+        Line::from(format!(
+            "Tags: {}  Labels: {}",
+            if status.tags.is_empty() { "-".to_string() } else { status.tags.join(", ") },
+            if status.labels.is_empty() {
+                "-".to_string()
+            } else {
+                status.labels.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(", ")
+            }
+        )),
+        Line::from(format!("CPU: {} ({} cores)", status.cpu.model, status.cpu.cpus.len())),
+        Line::from(
+            status
+                .cpu
+                .cpus
+                .iter()
+                .map(|core| Span::styled(format!("{:>3.0} ", core.cpu), usage_style(Some(core.cpu))))
+                .collect::<Vec<_>>(),
+        ),
+        Line::from(format!(
+            "RAM: {} / {} ({})",
+            metrics::format_bytes(status.ram.total.saturating_sub(status.ram.free)),
+            metrics::format_bytes(status.ram.total),
+            metrics::format_percent(metrics::ram_usage(status))
+        )),
+        Line::from(format!(
+            "Swap: {} / {} ({})",
+            metrics::format_bytes(status.swap.total.saturating_sub(status.swap.free)),
+            metrics::format_bytes(status.swap.total),
+            metrics::format_percent(metrics::swap_usage(status))
+        )),
+        Line::from(format!(
+            "Load: {:.2} {:.2} {:.2}  Uptime: {}",
+            status.loadavg[0],
+            status.loadavg[1],
+            status.loadavg[2],
+            metrics::format_uptime(status.uptime)
+        )),
+    ];
+
+    for storage in &status.storages {
+        let usage = metrics::percent(storage.free, storage.total);
+        lines.push(Line::from(vec![
+            Span::raw(format!(
+                "Disk {}: {} / {} ",
+                storage.name.as_deref().unwrap_or("-"),
+                metrics::format_bytes(storage.total.saturating_sub(storage.free)),
+                metrics::format_bytes(storage.total)
+            )),
+            Span::styled(metrics::format_percent(usage), usage_style(usage)),
+        ]));
+    }
+    for gpu in &status.gpus {
+        let memory = metrics::percent(gpu.memory.free, gpu.memory.total);
+        lines.push(Line::from(vec![
+            Span::raw(format!("GPU {}: ", gpu.name)),
+            Span::styled(metrics::format_percent(Some(gpu.usage)), usage_style(Some(gpu.usage))),
+            Span::raw(" mem "),
+            Span::styled(metrics::format_percent(memory), usage_style(memory)),
+        ]));
+    }
+    lines
+}
+
+fn draw_detail(frame: &mut Frame, app: &App, area: Rect) {
+    let Some((id, status)) = app.selected_host() else {
+        frame.render_widget(Paragraph::new("No host selected").block(Block::bordered().title(" Detail ")), area);
+        return;
+    };
+
+    let [info, cpu, ram] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(5), Constraint::Length(5)]).areas(area);
+
+    let title = format!(" {} ({}) ", display_name(status), id);
+    frame.render_widget(Paragraph::new(detail_lines(status)).block(Block::bordered().title(title)), info);
+
+    let cpu_history = metrics::cpu_history(status);
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(" CPU history "))
+            .data(&cpu_history)
+            .max(100)
+            .style(Style::new().fg(Color::Cyan)),
+        cpu,
+    );
+
+    let ram_history = metrics::ram_history(status);
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(" RAM history "))
+            .data(&ram_history)
+            .max(100)
+            .style(Style::new().fg(Color::Magenta)),
+        ram,
+    );
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let mut lines: Vec<Line> = app
+        .notices
+        .iter()
+        .map(|notice| Line::styled(notice.clone(), Style::new().fg(Color::Yellow)))
+        .collect();
+    let help = match app.mode {
+        Mode::Normal => " q: quit  ↑↓/jk: select  s: sort  r: reverse  /: filter  Enter: detail",
+        Mode::Filter => " type to filter  Enter: apply  Esc: clear",
+    };
+    lines.push(Line::styled(help, Style::new().fg(Color::DarkGray)));
+    frame.render_widget(Paragraph::new(lines), area);
+}